@@ -0,0 +1,56 @@
+//! Just enough of the zip format to hand several files to the browser as one download
+
+/// CRC-32 as zip wants it, reflected with the 0xEDB88320 polynomial
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// An uncompressed zip of `(path, contents)` pairs, paths use forward slashes
+pub fn write_zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut zip = vec![];
+    let mut central_directory = vec![];
+    for (name, data) in files.iter() {
+        let offset = zip.len() as u32;
+        let crc = crc32(data);
+        let size = data.len() as u32;
+
+        zip.extend(0x04034b50u32.to_le_bytes());
+        // version 1.0, no flags, stored, midnight 1980-01-01
+        zip.extend([10u8, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        zip.extend(crc.to_le_bytes());
+        zip.extend(size.to_le_bytes());
+        zip.extend(size.to_le_bytes());
+        zip.extend((name.len() as u16).to_le_bytes());
+        zip.extend(0u16.to_le_bytes());
+        zip.extend(name.as_bytes());
+        zip.extend(data);
+
+        central_directory.extend(0x02014b50u32.to_le_bytes());
+        central_directory.extend([10u8, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        central_directory.extend(crc.to_le_bytes());
+        central_directory.extend(size.to_le_bytes());
+        central_directory.extend(size.to_le_bytes());
+        central_directory.extend((name.len() as u16).to_le_bytes());
+        // extra and comment length, disk, internal and external attributes
+        central_directory.extend([0u8; 12]);
+        central_directory.extend(offset.to_le_bytes());
+        central_directory.extend(name.as_bytes());
+    }
+    let central_directory_offset = zip.len() as u32;
+    zip.extend(&central_directory);
+    zip.extend(0x06054b50u32.to_le_bytes());
+    zip.extend([0u8; 4]);
+    zip.extend((files.len() as u16).to_le_bytes());
+    zip.extend((files.len() as u16).to_le_bytes());
+    zip.extend((central_directory.len() as u32).to_le_bytes());
+    zip.extend(central_directory_offset.to_le_bytes());
+    zip.extend(0u16.to_le_bytes());
+    zip
+}
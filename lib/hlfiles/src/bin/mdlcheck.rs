@@ -0,0 +1,59 @@
+//! Runs `MdlFile::validate` over models from the command line, exits with 1 when any has errors
+//!
+//! `mdlcheck models/scientist.mdl models/barney.mdl`
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use hlfiles::hlmdl::{Companion, MdlFile, Severity};
+
+/// Loads the model and whichever of its companion files sit next to it
+fn load(path: &Path) -> Result<MdlFile, String> {
+    let buf = std::fs::read(path).map_err(|err| err.to_string())?;
+    let mut mdl_file = MdlFile::from_bytes(&buf).map_err(|err| err.to_string())?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    for companion in mdl_file.missing_companions() {
+        let mut name = mdl_file.companion_name(companion);
+        if companion == Companion::Textures {
+            // named after the file on disk, not whatever the header was compiled as
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+            name = format!("{}T.mdl", stem);
+        }
+        if let Ok(file) = std::fs::read(directory.join(&name)) {
+            mdl_file.add_companion(&name, file).map_err(|err| format!("{}: {}", name, err))?;
+        }
+    }
+    Ok(mdl_file)
+}
+
+fn main() -> ExitCode {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: mdlcheck <model.mdl>...");
+        return ExitCode::from(2)
+    }
+    let mut failed = false;
+    for path in paths.iter() {
+        let mdl_file = match load(Path::new(path)) {
+            Ok(mdl_file) => mdl_file,
+            Err(err) => {
+                println!("{}: error: {}", path, err);
+                failed = true;
+                continue
+            },
+        };
+        let issues = mdl_file.validate();
+        if issues.is_empty() {
+            println!("{}: ok", path);
+        }
+        for issue in issues.iter() {
+            println!("{}: {}", path, issue);
+        }
+        failed |= issues.iter().any(|issue| issue.severity == Severity::Error);
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
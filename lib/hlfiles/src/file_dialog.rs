@@ -1,4 +1,4 @@
-type FileData = (String, Vec<u8>);
+pub type FileData = (String, Vec<u8>);
 
 // wasm
 
@@ -7,17 +7,25 @@ use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsCast;
 #[cfg(target_arch = "wasm32")]
-use web_sys::{window, Url, File, HtmlInputElement, FileReader};
+use web_sys::{window, Url, File, HtmlInputElement, FileReader, ProgressEvent};
 #[cfg(target_arch = "wasm32")]
 use js_sys::{Uint8Array, Array, ArrayBuffer};
 
 
+#[cfg(target_arch = "wasm32")]
+use std::{cell::RefCell, rc::Rc};
+
 #[cfg(target_arch = "wasm32")]
 pub struct FileDialog {
-    tx: std::sync::mpsc::Sender<FileData>,
-    rx: std::sync::mpsc::Receiver<FileData>,
+    tx: std::sync::mpsc::Sender<Vec<FileData>>,
+    rx: std::sync::mpsc::Receiver<Vec<FileData>>,
     input: HtmlInputElement,
     closure: Option<Closure<dyn FnMut()>>,
+    readers: Rc<RefCell<Vec<FileReader>>>,
+    /// (bytes loaded, bytes total) per file being read
+    read_progress: Rc<RefCell<Vec<(f64, f64)>>>,
+    /// (file name, reason) of files the browser couldn't read
+    failed: Rc<RefCell<Vec<(String, String)>>>,
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -37,6 +45,9 @@ impl Default for FileDialog {
             tx,
             input,
             closure: None,
+            readers: Rc::new(RefCell::new(vec![])),
+            read_progress: Rc::new(RefCell::new(vec![])),
+            failed: Rc::new(RefCell::new(vec![])),
         }
     }
 }
@@ -53,6 +64,26 @@ impl Drop for FileDialog {
 
 #[cfg(target_arch = "wasm32")]
 impl FileDialog {
+    /// Lets the user pick more than one file at a time
+    pub fn multiple(self, multiple: bool) -> Self {
+        if multiple {
+            self.input.set_attribute("multiple", "").unwrap();
+        } else {
+            self.input.remove_attribute("multiple").unwrap();
+        }
+        self
+    }
+
+    /// Only offer files with these extensions, e.g. `&["wad", "mdl"]`
+    pub fn accept(self, extensions: &[&str]) -> Self {
+        let accept = extensions.iter()
+            .map(|extension| format!(".{}", extension.trim_start_matches('.')))
+            .collect::<Vec<String>>()
+            .join(",");
+        self.input.set_attribute("accept", &accept).unwrap();
+        self
+    }
+
     pub fn open(&mut self) {
         if let Some(closure) = &self.closure {
             self.input.remove_event_listener_with_callback("change", closure.as_ref().unchecked_ref()).unwrap();
@@ -61,23 +92,73 @@ impl FileDialog {
 
         let tx = self.tx.clone();
         let input_clone = self.input.clone();
+        let readers = self.readers.clone();
+        let read_progress = self.read_progress.clone();
+        let failed = self.failed.clone();
 
         let closure = Closure::once(move || {
-            if let Some(file) = input_clone.files().and_then(|files| files.get(0)) {
+            let files = match input_clone.files() {
+                Some(files) if files.length() > 0 => files,
+                _ => return,
+            };
+            // every reader fills its own slot, the last one to finish sends the batch in
+            // the order the files were selected
+            let num_files = files.length() as usize;
+            let batch: Rc<RefCell<Vec<Option<FileData>>>> = Rc::new(RefCell::new(vec![None; num_files]));
+            let remaining = Rc::new(RefCell::new(num_files));
+            readers.borrow_mut().clear();
+            *read_progress.borrow_mut() = (0..num_files)
+                .map(|itr| (0.0, files.get(itr as u32).unwrap().size()))
+                .collect();
+            for itr in 0..num_files {
+                let file = files.get(itr as u32).unwrap();
                 let reader = FileReader::new().unwrap();
                 let reader_clone = reader.clone();
                 let name = file.name();
-                let onload_closure = Closure::once(Box::new(move || {
-                    let array_buffer = reader_clone.result().unwrap().dyn_into::<ArrayBuffer>().unwrap();
-                    let buffer = Uint8Array::new(&array_buffer).to_vec();
-                    let file_data = (name, buffer);
-                    tx.send(file_data).ok();
+                let tx = tx.clone();
+                let batch = batch.clone();
+                let remaining = remaining.clone();
+                let readers_clone = readers.clone();
+                let read_progress_clone = read_progress.clone();
+                let failed = failed.clone();
+                let onload_closure = {
+                    let batch = batch.clone();
+                    let remaining = remaining.clone();
+                    let readers = readers.clone();
+                    let read_progress = read_progress.clone();
+                    let name = name.clone();
+                    let tx = tx.clone();
+                    Closure::once(Box::new(move || {
+                        let array_buffer = reader_clone.result().unwrap().dyn_into::<ArrayBuffer>().unwrap();
+                        let buffer = Uint8Array::new(&array_buffer).to_vec();
+                        batch.borrow_mut()[itr] = Some((name, buffer));
+                        reader_done(&remaining, &batch, &readers, &read_progress, &tx);
+                    }))
+                };
+                // the slot stays empty, the rest of the batch still comes through
+                let onerror_closure = Closure::once(Box::new(move || {
+                    failed.borrow_mut().push((name, String::from("the browser couldn't read the file")));
+                    reader_done(&remaining, &batch, &readers_clone, &read_progress_clone, &tx);
                 }));
+                let read_progress_clone = read_progress.clone();
+                let onprogress_closure = Closure::<dyn FnMut(ProgressEvent)>::new(move |event: ProgressEvent| {
+                    if let Some(progress) = read_progress_clone.borrow_mut().get_mut(itr) {
+                        progress.0 = event.loaded();
+                    }
+                });
 
                 reader.set_onload(Some(onload_closure.as_ref().unchecked_ref()));
+                reader.set_onerror(Some(onerror_closure.as_ref().unchecked_ref()));
+                reader.set_onabort(Some(onerror_closure.as_ref().unchecked_ref()));
+                reader.set_onprogress(Some(onprogress_closure.as_ref().unchecked_ref()));
                 reader.read_as_array_buffer(&file).unwrap();
                 onload_closure.forget();
+                onerror_closure.forget();
+                onprogress_closure.forget();
+                readers.borrow_mut().push(reader);
             }
+            // so picking the same file again still fires "change"
+            input_clone.set_value("");
         });
 
         self.input.add_event_listener_with_callback("change", closure.as_ref().unchecked_ref()).unwrap();
@@ -85,14 +166,45 @@ impl FileDialog {
         self.input.click();
     }
 
-    pub fn get(&self) -> Option<(String, Vec<u8>)> {
-        if let Ok(file_data) = self.rx.try_recv() {
-            Some(file_data)
+    /// Every file picked in one go comes back together
+    pub fn get(&self) -> Option<Vec<FileData>> {
+        if let Ok(file_datas) = self.rx.try_recv() {
+            Some(file_datas)
         } else {
             None
         }
     }
 
+    /// How far along reading the picked files is, None when nothing is being read
+    pub fn progress(&self) -> Option<f32> {
+        let read_progress = self.read_progress.borrow();
+        if read_progress.is_empty() {
+            return None
+        }
+        let (loaded, total) = read_progress.iter()
+            .fold((0.0, 0.0), |(loaded, total), progress| (loaded + progress.0, total + progress.1));
+        if total <= 0.0 {
+            return Some(0.0)
+        }
+        Some((loaded / total) as f32)
+    }
+
+    /// (file name, reason) of every file that couldn't be read since the last call
+    pub fn errors(&mut self) -> Vec<(String, String)> {
+        self.failed.borrow_mut().drain(..).collect()
+    }
+
+    /// Aborts reading, nothing from this batch will come out of `get()`
+    pub fn cancel(&mut self) {
+        for reader in self.readers.borrow_mut().drain(..) {
+            reader.set_onload(None);
+            reader.set_onerror(None);
+            reader.set_onabort(None);
+            reader.abort();
+        }
+        self.read_progress.borrow_mut().clear();
+    }
+
     pub fn save(&self, filename: &str, filedata: Vec<u8>) {
         let array = Uint8Array::from(filedata.as_slice());
         let blob_parts = Array::new();
@@ -115,3 +227,112 @@ impl FileDialog {
     }
 }
 
+/// Counts one reader as finished, the last one sends every file that could be read
+#[cfg(target_arch = "wasm32")]
+fn reader_done(
+    remaining: &RefCell<usize>,
+    batch: &RefCell<Vec<Option<FileData>>>,
+    readers: &RefCell<Vec<FileReader>>,
+    read_progress: &RefCell<Vec<(f64, f64)>>,
+    tx: &std::sync::mpsc::Sender<Vec<FileData>>,
+) {
+    *remaining.borrow_mut() -= 1;
+    if *remaining.borrow() == 0 {
+        let file_datas: Vec<FileData> = batch.borrow_mut().drain(..).flatten().collect();
+        readers.borrow_mut().clear();
+        read_progress.borrow_mut().clear();
+        if !file_datas.is_empty() {
+            tx.send(file_datas).ok();
+        }
+    }
+}
+
+
+// native
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileDialog {
+    tx: std::sync::mpsc::Sender<Vec<FileData>>,
+    rx: std::sync::mpsc::Receiver<Vec<FileData>>,
+    multiple: bool,
+    extensions: Vec<String>,
+    failed: Vec<(String, String)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for FileDialog {
+    fn default() -> Self {
+        let (tx, rx) = std::sync::mpsc::channel();
+        Self {
+            tx,
+            rx,
+            multiple: false,
+            extensions: vec![],
+            failed: vec![],
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileDialog {
+    pub fn multiple(mut self, multiple: bool) -> Self {
+        self.multiple = multiple;
+        self
+    }
+
+    pub fn accept(mut self, extensions: &[&str]) -> Self {
+        self.extensions = extensions.iter()
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .collect();
+        self
+    }
+
+    pub fn open(&mut self) {
+        let mut dialog = rfd::FileDialog::new();
+        if !self.extensions.is_empty() {
+            dialog = dialog.add_filter("Supported files", &self.extensions);
+        }
+        let paths = if self.multiple {
+            dialog.pick_files().unwrap_or_default()
+        } else {
+            dialog.pick_file().into_iter().collect()
+        };
+        let mut file_datas: Vec<FileData> = vec![];
+        for path in paths.iter() {
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => path.to_string_lossy().to_string(),
+            };
+            match std::fs::read(path) {
+                Ok(buffer) => file_datas.push((name, buffer)),
+                Err(error) => self.failed.push((name, error.to_string())),
+            }
+        }
+        if !file_datas.is_empty() {
+            self.tx.send(file_datas).ok();
+        }
+    }
+
+    pub fn get(&self) -> Option<Vec<FileData>> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Native reads are done by the time `open()` returns
+    pub fn progress(&self) -> Option<f32> {
+        None
+    }
+
+    /// (file name, reason) of every file that couldn't be read since the last call
+    pub fn errors(&mut self) -> Vec<(String, String)> {
+        self.failed.drain(..).collect()
+    }
+
+    pub fn cancel(&mut self) {
+    }
+
+    pub fn save(&self, filename: &str, filedata: Vec<u8>) {
+        if let Some(path) = rfd::FileDialog::new().set_file_name(filename).save_file() {
+            std::fs::write(path, filedata).ok();
+        }
+    }
+}
@@ -0,0 +1,1264 @@
+use std::fmt;
+use bytebuffer::ByteBuffer;
+use crate::file_dialog::FileDialog;
+use crate::hlwad::{Texture, TextureHeader, WadFile, WadReader, MIPMAP_LEVEL, TEXTURE_HEADER_SIZE};
+use crate::math::Vec3;
+
+mod textures;
+mod report;
+mod entities;
+mod geometry;
+mod viewport;
+mod tree;
+pub use geometry::{sample_lightmap, LightmapAtlas, LightmapExtents, LIGHTMAP_SCALE, TEX_SPECIAL};
+pub use viewport::{MapViewport, RenderMode};
+pub use entities::{parse_entities, write_entities, Entity};
+pub use report::{map_texture_names, wad_key_names, TextureReport, TextureSource};
+pub use tree::{Visibility, Visit, CONTENTS_SOLID};
+
+pub const BSP_VERSION: i32 = 30;
+pub const NUM_LUMPS: usize = 15;
+pub const BSP_HEADER_SIZE: usize = 4 + NUM_LUMPS * 8;
+
+/// Lump order in the header
+pub mod lump {
+    pub const ENTITIES: usize = 0;
+    pub const PLANES: usize = 1;
+    pub const TEXTURES: usize = 2;
+    pub const VERTICES: usize = 3;
+    pub const VISIBILITY: usize = 4;
+    pub const NODES: usize = 5;
+    pub const TEX_INFO: usize = 6;
+    pub const FACES: usize = 7;
+    pub const LIGHTING: usize = 8;
+    pub const CLIP_NODES: usize = 9;
+    pub const LEAVES: usize = 10;
+    pub const MARK_SURFACES: usize = 11;
+    pub const EDGES: usize = 12;
+    pub const SURF_EDGES: usize = 13;
+    pub const MODELS: usize = 14;
+
+    pub const NAMES: [&str; super::NUM_LUMPS] = [
+        "entities", "planes", "textures", "vertices", "visibility", "nodes", "texinfo", "faces",
+        "lighting", "clipnodes", "leaves", "marksurfaces", "edges", "surfedges", "models",
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BspError {
+    BadVersion(i32),
+    /// Ran out of bytes while reading something
+    UnexpectedEof,
+    LumpOutOfBounds { lump: &'static str, offset: i32, size: i32, file_size: usize },
+    /// The lump isn't a whole number of its structures
+    BadLumpSize { lump: &'static str, size: usize, item_size: usize },
+    /// An index in one lump points past the end of another
+    BadIndex { what: &'static str, index: i64, count: usize },
+    /// The entity lump isn't `{ "key" "value" }` blocks
+    BadEntities { line: usize, reason: &'static str },
+    /// A node's child would turn the tree into a graph that walks could go around forever in
+    BadTree { node: usize, child: usize, reason: &'static str },
+}
+
+impl fmt::Display for BspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BspError::BadVersion(version) => write!(f, "version {} isn't a GoldSrc map, expected {}", version, BSP_VERSION),
+            BspError::UnexpectedEof => write!(f, "the file ends in the middle of a structure"),
+            BspError::LumpOutOfBounds { lump, offset, size, file_size } => write!(f, "{} lump ({} bytes at offset {}) is outside of the {} byte file", lump, size, offset, file_size),
+            BspError::BadLumpSize { lump, size, item_size } => write!(f, "{} lump is {} bytes, not a multiple of {}", lump, size, item_size),
+            BspError::BadIndex { what, index, count } => write!(f, "{} {} is out of range, there are {}", what, index, count),
+            BspError::BadEntities { line, reason } => write!(f, "entities line {}: {}", line, reason),
+            BspError::BadTree { node, child, reason } => write!(f, "node {} has node {} as a child, {}", node, child, reason),
+        }
+    }
+}
+
+impl std::error::Error for BspError {}
+
+impl From<std::io::Error> for BspError {
+    fn from(_: std::io::Error) -> Self {
+        BspError::UnexpectedEof
+    }
+}
+
+fn read_vec3(reader: &mut ByteBuffer) -> Result<Vec3, BspError> {
+    Ok([reader.read_f32()?, reader.read_f32()?, reader.read_f32()?])
+}
+
+fn check_index(what: &'static str, index: i64, count: usize) -> Result<(), BspError> {
+    if index < 0 || index as usize >= count {
+        return Err(BspError::BadIndex { what, index, count })
+    }
+    Ok(())
+}
+
+/// `lump_t`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lump {
+    pub offset: i32,
+    pub length: i32,
+}
+
+/// `dheader_t`
+#[derive(Debug, Clone, Copy)]
+pub struct BspHeader {
+    pub version: i32,
+    pub lumps: [Lump; NUM_LUMPS],
+}
+
+impl BspHeader {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        reader.set_endian(bytebuffer::Endian::LittleEndian);
+        let version = reader.read_i32()?;
+        let mut lumps = [Lump::default(); NUM_LUMPS];
+        for lump in lumps.iter_mut() {
+            lump.offset = reader.read_i32()?;
+            lump.length = reader.read_i32()?;
+        }
+        Ok(Self { version, lumps })
+    }
+}
+
+pub const PLANE_SIZE: usize = 20;
+
+/// `dplane_t`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub dist: f32,
+    /// 0-2 axial along x, y or z, 3-5 closest to that axis
+    pub plane_type: i32,
+}
+
+impl Plane {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        Ok(Self {
+            normal: read_vec3(reader)?,
+            dist: reader.read_f32()?,
+            plane_type: reader.read_i32()?,
+        })
+    }
+}
+
+pub const VERTEX_SIZE: usize = 12;
+pub const EDGE_SIZE: usize = 4;
+pub const SURF_EDGE_SIZE: usize = 4;
+
+pub const TEX_INFO_SIZE: usize = 40;
+
+/// `texinfo_t`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TexInfo {
+    /// s and t axes, a texel coordinate is `dot(position, axis) + offset`
+    pub vecs: [[f32; 4]; 2],
+    pub miptex: i32,
+    /// 1 is TEX_SPECIAL, sky and water that get no lightmap
+    pub flags: i32,
+}
+
+impl TexInfo {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        let mut vecs = [[0.0; 4]; 2];
+        for value in vecs.iter_mut().flatten() {
+            *value = reader.read_f32()?;
+        }
+        Ok(Self {
+            vecs,
+            miptex: reader.read_i32()?,
+            flags: reader.read_i32()?,
+        })
+    }
+}
+
+pub const FACE_SIZE: usize = 20;
+
+/// `dface_t`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Face {
+    pub plane: u16,
+    /// Non zero when the face points the opposite way to its plane
+    pub side: u16,
+    pub first_edge: i32,
+    pub num_edges: u16,
+    pub tex_info: u16,
+    /// Light styles, 255 ends the list
+    pub styles: [u8; 4],
+    /// Byte offset into the lighting lump, -1 for none
+    pub light_offset: i32,
+}
+
+impl Face {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        Ok(Self {
+            plane: reader.read_u16()?,
+            side: reader.read_u16()?,
+            first_edge: reader.read_i32()?,
+            num_edges: reader.read_u16()?,
+            tex_info: reader.read_u16()?,
+            styles: reader.read_bytes(4)?.try_into().unwrap(),
+            light_offset: reader.read_i32()?,
+        })
+    }
+}
+
+pub const NODE_SIZE: usize = 24;
+
+/// `dnode_t`, splits space along a plane. The faces lying on that plane hang off the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub plane: i32,
+    /// In front of and behind the plane, negative ones are leaf `-1 - child`
+    pub children: [i16; 2],
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    pub first_face: u16,
+    pub num_faces: u16,
+}
+
+impl Node {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        Ok(Self {
+            plane: reader.read_i32()?,
+            children: [reader.read_i16()?, reader.read_i16()?],
+            mins: [reader.read_i16()?, reader.read_i16()?, reader.read_i16()?],
+            maxs: [reader.read_i16()?, reader.read_i16()?, reader.read_i16()?],
+            first_face: reader.read_u16()?,
+            num_faces: reader.read_u16()?,
+        })
+    }
+}
+
+pub const LEAF_SIZE: usize = 28;
+pub const MARK_SURFACE_SIZE: usize = 2;
+
+/// `dleaf_t`, a convex piece of space at the bottom of the tree. Leaf 0 is the solid space outside
+/// the map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leaf {
+    /// -1 empty, -2 solid, lower for liquids and sky
+    pub contents: i32,
+    /// Byte offset of the leaf's visible set in the visibility lump, -1 for none
+    pub vis_offset: i32,
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    /// Into `BspFile::mark_surfaces`, the faces touching the leaf
+    pub first_mark_surface: u16,
+    pub num_mark_surfaces: u16,
+    pub ambient_levels: [u8; 4],
+}
+
+impl Leaf {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        Ok(Self {
+            contents: reader.read_i32()?,
+            vis_offset: reader.read_i32()?,
+            mins: [reader.read_i16()?, reader.read_i16()?, reader.read_i16()?],
+            maxs: [reader.read_i16()?, reader.read_i16()?, reader.read_i16()?],
+            first_mark_surface: reader.read_u16()?,
+            num_mark_surfaces: reader.read_u16()?,
+            ambient_levels: reader.read_bytes(4)?.try_into().unwrap(),
+        })
+    }
+}
+
+pub const MODEL_SIZE: usize = 64;
+
+/// `dmodel_t`, model 0 is the world and the rest are brush entities like doors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Model {
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub origin: Vec3,
+    /// Node for drawing then the three clipping hulls
+    pub head_nodes: [i32; 4],
+    pub vis_leafs: i32,
+    pub first_face: i32,
+    pub num_faces: i32,
+}
+
+impl Model {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, BspError> {
+        Ok(Self {
+            mins: read_vec3(reader)?,
+            maxs: read_vec3(reader)?,
+            origin: read_vec3(reader)?,
+            head_nodes: [reader.read_i32()?, reader.read_i32()?, reader.read_i32()?, reader.read_i32()?],
+            vis_leafs: reader.read_i32()?,
+            first_face: reader.read_i32()?,
+            num_faces: reader.read_i32()?,
+        })
+    }
+}
+
+/// One entry of the texture lump, one the compiler couldn't find at all has an empty name
+#[derive(Debug, Clone)]
+pub struct MipTex {
+    pub header: TextureHeader,
+    /// `None` when the map only names the texture and the engine finds it in a WAD
+    pub texture: Option<Texture>,
+}
+
+impl MipTex {
+    pub fn name(&self) -> String {
+        let len = self.header.sz_name.iter().position(|byte| *byte == 0).unwrap_or(16);
+        String::from_utf8_lossy(&self.header.sz_name[..len]).to_string()
+    }
+}
+
+pub struct BspFile {
+    pub header: BspHeader,
+    /// Entity lump as text, `{ "key" "value" }` blocks. Bytes that aren't UTF-8 show up as U+FFFD,
+    /// the lump is only rewritten from this once it's been changed
+    pub entities: String,
+    pub planes: Vec<Plane>,
+    pub textures: Vec<MipTex>,
+    pub vertices: Vec<Vec3>,
+    pub tex_infos: Vec<TexInfo>,
+    pub faces: Vec<Face>,
+    pub nodes: Vec<Node>,
+    pub leaves: Vec<Leaf>,
+    /// Face indices, each leaf owns a run of them
+    pub mark_surfaces: Vec<u16>,
+    /// Each leaf's potentially visible set, run length encoded
+    pub visibility: Vec<u8>,
+    /// RGB samples, faces point into it with `light_offset`
+    pub lighting: Vec<u8>,
+    pub edges: Vec<[u16; 2]>,
+    /// Edge indices, negative ones are walked backwards
+    pub surf_edges: Vec<i32>,
+    pub models: Vec<Model>,
+    /// The whole file, for the lumps that aren't decoded
+    pub data: Vec<u8>,
+}
+
+impl fmt::Debug for BspFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BspFile")
+         .field("header", &self.header)
+         .field("planes", &self.planes.len())
+         .field("textures", &self.textures.len())
+         .field("vertices", &self.vertices.len())
+         .field("tex_infos", &self.tex_infos.len())
+         .field("faces", &self.faces.len())
+         .field("nodes", &self.nodes.len())
+         .field("leaves", &self.leaves.len())
+         .field("mark_surfaces", &self.mark_surfaces.len())
+         .field("visibility", &self.visibility.len())
+         .field("lighting", &self.lighting.len())
+         .field("edges", &self.edges.len())
+         .field("surf_edges", &self.surf_edges.len())
+         .field("models", &self.models.len())
+         .finish()
+    }
+}
+
+impl BspFile {
+    pub fn validate_header(buf: &[u8]) -> bool {
+        buf.len() >= BSP_HEADER_SIZE && i32::from_le_bytes(buf[0..4].try_into().unwrap()) == BSP_VERSION
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, BspError> {
+        let mut bsp_file = Self::from_header(buf.to_vec())?;
+        for index in 0..NUM_LUMPS {
+            bsp_file.read_lump(index)?;
+        }
+        bsp_file.check_indices()?;
+        Ok(bsp_file)
+    }
+
+    /// Reads the header and checks every lump fits in `data`, the lumps are left empty for
+    /// `read_lump` to fill in
+    fn from_header(data: Vec<u8>) -> Result<Self, BspError> {
+        let mut reader = ByteBuffer::from_bytes(&data);
+        let header = BspHeader::from_reader(&mut reader)?;
+        if header.version != BSP_VERSION {
+            return Err(BspError::BadVersion(header.version))
+        }
+        for (itr, lump) in header.lumps.iter().enumerate() {
+            if lump.offset < 0 || lump.length < 0 || lump.offset as i64 + lump.length as i64 > data.len() as i64 {
+                return Err(BspError::LumpOutOfBounds { lump: lump::NAMES[itr], offset: lump.offset, size: lump.length, file_size: data.len() })
+            }
+        }
+        Ok(Self {
+            header,
+            entities: String::new(),
+            planes: vec![],
+            textures: vec![],
+            vertices: vec![],
+            tex_infos: vec![],
+            faces: vec![],
+            nodes: vec![],
+            leaves: vec![],
+            mark_surfaces: vec![],
+            visibility: vec![],
+            lighting: vec![],
+            edges: vec![],
+            surf_edges: vec![],
+            models: vec![],
+            data,
+        })
+    }
+
+    /// Decodes one lump into its field, clipnodes aren't decoded
+    fn read_lump(&mut self, index: usize) -> Result<(), BspError> {
+        let buf = self.lump(index);
+        match index {
+            lump::ENTITIES => self.entities = decode_entities(buf),
+            lump::PLANES => self.planes = read_lump(index, buf, PLANE_SIZE, Plane::from_reader)?,
+            lump::TEXTURES => self.textures = read_textures(buf)?,
+            lump::VERTICES => self.vertices = read_lump(index, buf, VERTEX_SIZE, read_vec3)?,
+            lump::VISIBILITY => self.visibility = buf.to_vec(),
+            lump::NODES => self.nodes = read_lump(index, buf, NODE_SIZE, Node::from_reader)?,
+            lump::TEX_INFO => self.tex_infos = read_lump(index, buf, TEX_INFO_SIZE, TexInfo::from_reader)?,
+            lump::FACES => self.faces = read_lump(index, buf, FACE_SIZE, Face::from_reader)?,
+            lump::LIGHTING => self.lighting = buf.to_vec(),
+            lump::LEAVES => self.leaves = read_lump(index, buf, LEAF_SIZE, Leaf::from_reader)?,
+            lump::MARK_SURFACES => self.mark_surfaces = read_lump(index, buf, MARK_SURFACE_SIZE, |reader| Ok(reader.read_u16()?))?,
+            lump::EDGES => self.edges = read_lump(index, buf, EDGE_SIZE, |reader| Ok([reader.read_u16()?, reader.read_u16()?]))?,
+            lump::SURF_EDGES => self.surf_edges = read_lump(index, buf, SURF_EDGE_SIZE, |reader| Ok(reader.read_i32()?))?,
+            lump::MODELS => self.models = read_lump(index, buf, MODEL_SIZE, Model::from_reader)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Every index one lump has into another has to land inside it
+    fn check_indices(&self) -> Result<(), BspError> {
+        for edge in self.edges.iter() {
+            for vertex in edge {
+                check_index("edge vertex", *vertex as i64, self.vertices.len())?;
+            }
+        }
+        for surf_edge in self.surf_edges.iter() {
+            check_index("surfedge", surf_edge.unsigned_abs() as i64, self.edges.len())?;
+        }
+        for tex_info in self.tex_infos.iter() {
+            check_index("texinfo miptex", tex_info.miptex as i64, self.textures.len())?;
+        }
+        for face in self.faces.iter() {
+            check_index("face plane", face.plane as i64, self.planes.len())?;
+            check_index("face texinfo", face.tex_info as i64, self.tex_infos.len())?;
+            if face.first_edge < 0 || face.first_edge as usize + face.num_edges as usize > self.surf_edges.len() {
+                return Err(BspError::BadIndex { what: "face edges", index: face.first_edge as i64 + face.num_edges as i64, count: self.surf_edges.len() })
+            }
+        }
+        // the compiler writes a parent before its children and every node has one parent, so the
+        // tree walks only ever go down and visit each node once
+        let mut has_parent = vec![false; self.nodes.len()];
+        for (itr, node) in self.nodes.iter().enumerate() {
+            check_index("node plane", node.plane as i64, self.planes.len())?;
+            for child in node.children {
+                if child >= 0 {
+                    check_index("node child", child as i64, self.nodes.len())?;
+                    let child = child as usize;
+                    if child <= itr {
+                        return Err(BspError::BadTree { node: itr, child, reason: "children come after their parent" })
+                    }
+                    if has_parent[child] {
+                        return Err(BspError::BadTree { node: itr, child, reason: "which already has a parent" })
+                    }
+                    has_parent[child] = true;
+                } else {
+                    check_index("node leaf", !child as i64, self.leaves.len())?;
+                }
+            }
+            if node.first_face as usize + node.num_faces as usize > self.faces.len() {
+                return Err(BspError::BadIndex { what: "node faces", index: node.first_face as i64 + node.num_faces as i64, count: self.faces.len() })
+            }
+        }
+        for leaf in self.leaves.iter() {
+            if leaf.first_mark_surface as usize + leaf.num_mark_surfaces as usize > self.mark_surfaces.len() {
+                return Err(BspError::BadIndex { what: "leaf marksurfaces", index: leaf.first_mark_surface as i64 + leaf.num_mark_surfaces as i64, count: self.mark_surfaces.len() })
+            }
+        }
+        for mark_surface in self.mark_surfaces.iter() {
+            check_index("marksurface", *mark_surface as i64, self.faces.len())?;
+        }
+        for model in self.models.iter() {
+            if model.first_face < 0 || model.num_faces < 0 || model.first_face as usize + model.num_faces as usize > self.faces.len() {
+                return Err(BspError::BadIndex { what: "model faces", index: model.first_face as i64 + model.num_faces as i64, count: self.faces.len() })
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the map back out with `entities` and `textures` as they are now, every other lump
+    /// is copied from `data` as it was read
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut lumps: Vec<Vec<u8>> = (0..NUM_LUMPS).map(|index| self.lump(index).to_vec()).collect();
+        // untouched entities keep their original bytes, decoding them may have lost some
+        if self.entities != decode_entities(self.lump(lump::ENTITIES)) {
+            lumps[lump::ENTITIES] = self.entities.as_bytes().to_vec();
+            lumps[lump::ENTITIES].push(0);
+        }
+        lumps[lump::TEXTURES] = write_textures(&self.textures);
+
+        let mut buf = vec![0; BSP_HEADER_SIZE];
+        buf[0..4].copy_from_slice(&BSP_VERSION.to_le_bytes());
+        for (itr, lump) in lumps.iter().enumerate() {
+            let offset = buf.len();
+            buf[(4 + itr * 8)..(8 + itr * 8)].copy_from_slice(&(offset as i32).to_le_bytes());
+            buf[(8 + itr * 8)..(12 + itr * 8)].copy_from_slice(&(lump.len() as i32).to_le_bytes());
+            buf.extend(lump);
+            buf.resize((buf.len() + 3) & !3, 0);
+        }
+        buf
+    }
+
+    /// Raw bytes of any lump, see `lump` for the indices
+    pub fn lump(&self, index: usize) -> &[u8] {
+        let lump = self.header.lumps[index];
+        &self.data[(lump.offset as usize)..((lump.offset + lump.length) as usize)]
+    }
+
+    /// The corners of a face in winding order
+    pub fn face_vertices(&self, face: &Face) -> Vec<Vec3> {
+        self.surf_edges[(face.first_edge as usize)..(face.first_edge as usize + face.num_edges as usize)].iter()
+            .map(|surf_edge| {
+                let edge = self.edges[surf_edge.unsigned_abs() as usize];
+                let vertex = if *surf_edge >= 0 { edge[0] } else { edge[1] };
+                self.vertices[vertex as usize]
+            })
+            .collect()
+    }
+}
+
+fn decode_entities(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf).trim_end_matches('\0').to_string()
+}
+
+fn read_lump<T>(
+    index: usize,
+    buf: &[u8],
+    item_size: usize,
+    from_reader: fn(&mut ByteBuffer) -> Result<T, BspError>,
+) -> Result<Vec<T>, BspError> {
+    if !buf.len().is_multiple_of(item_size) {
+        return Err(BspError::BadLumpSize { lump: lump::NAMES[index], size: buf.len(), item_size })
+    }
+    let mut reader = ByteBuffer::from_bytes(buf);
+    reader.set_endian(bytebuffer::Endian::LittleEndian);
+    (0..(buf.len() / item_size)).map(|_| from_reader(&mut reader)).collect()
+}
+
+/// `dmiptexlump_t`, a count, that many offsets from the start of the lump, then the miptexes
+fn read_textures(buf: &[u8]) -> Result<Vec<MipTex>, BspError> {
+    if buf.is_empty() {
+        return Ok(vec![])
+    }
+    let read_i32 = |offset: usize| buf.get(offset..(offset + 4))
+        .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(BspError::UnexpectedEof);
+    let count = read_i32(0)?;
+    if count < 0 || 4 + count as usize * 4 > buf.len() {
+        return Err(BspError::BadLumpSize { lump: lump::NAMES[lump::TEXTURES], size: buf.len(), item_size: 4 })
+    }
+    let mut textures = vec![];
+    for itr in 0..(count as usize) {
+        let offset = read_i32(4 + itr * 4)?;
+        // -1 is a texture the compiler couldn't find, the engine shows the checkerboard
+        let miptex = if offset >= 0 { buf.get((offset as usize)..) } else { None };
+        let miptex = match miptex {
+            Some(miptex) if miptex.len() >= TEXTURE_HEADER_SIZE => miptex,
+            _ => {
+                textures.push(MipTex { header: TextureHeader::from_bytes(&[0; TEXTURE_HEADER_SIZE]), texture: None });
+                continue
+            },
+        };
+        let header = TextureHeader::from_bytes(miptex[..TEXTURE_HEADER_SIZE].try_into().unwrap());
+        textures.push(MipTex { header, texture: Texture::from_miptex(miptex) });
+    }
+    Ok(textures)
+}
+
+fn write_textures(textures: &[MipTex]) -> Vec<u8> {
+    let mut buf = (textures.len() as i32).to_le_bytes().to_vec();
+    let mut miptexes = vec![];
+    let mut offset = 4 + textures.len() * 4;
+    for miptex in textures.iter() {
+        let bytes = match &miptex.texture {
+            Some(texture) => texture.to_miptex(),
+            None if miptex.header.sz_name[0] == 0 => {
+                buf.extend((-1i32).to_le_bytes());
+                continue
+            },
+            // just the name and size, with no mips the engine looks in the WADs
+            None => {
+                let mut header = miptex.header;
+                header.mip_offsets = [0; 4];
+                header.to_vec()
+            },
+        };
+        buf.extend((offset as i32).to_le_bytes());
+        offset += bytes.len();
+        miptexes.push(bytes);
+    }
+    for bytes in miptexes {
+        buf.extend(bytes);
+    }
+    buf
+}
+
+pub struct BspFileWidget {
+    pub bsp_file: BspFile,
+    /// Thumbnails lining up with `BspFile::textures`, `None` for the ones kept in WADs
+    pub textures: Vec<Option<egui::TextureHandle>>,
+    pub init_textures: bool,
+    /// Opens WADs to embed textures from, and saves what comes out
+    file_dialog: FileDialog,
+    /// What the last embed did, or why it didn't
+    texture_message: Option<String>,
+    /// Set by the check button, the app answers with the open WADs
+    wants_wads: bool,
+    texture_report: Option<TextureReport>,
+    /// Parsed from `bsp_file.entities`, which is rewritten on every edit
+    pub entities: Vec<Entity>,
+    /// Why the lump couldn't be parsed, it's only shown as text then
+    entity_error: Option<String>,
+    entity_search: String,
+    pub viewport: MapViewport,
+    pub name: String,
+    pub visible: bool,
+    pub id: usize,
+}
+
+/// Parses a map on a `LoadTask`, the header first, then a lump per step, then the checks
+/// between lumps
+pub struct BspParser {
+    data: Vec<u8>,
+    bsp_file: Option<BspFile>,
+    /// Steps done so far
+    next: usize,
+}
+
+impl BspParser {
+    /// The header, every lump and the index checks
+    const STEPS: usize = 1 + NUM_LUMPS + 1;
+
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data, bsp_file: None, next: 0 }
+    }
+
+    /// Does the next step, returns false once the map is parsed
+    pub fn step(&mut self) -> Result<bool, BspError> {
+        let bsp_file = match &mut self.bsp_file {
+            Some(bsp_file) => bsp_file,
+            None => {
+                self.bsp_file = Some(BspFile::from_header(std::mem::take(&mut self.data))?);
+                self.next = 1;
+                return Ok(true)
+            },
+        };
+        match self.next {
+            next if next <= NUM_LUMPS => bsp_file.read_lump(next - 1)?,
+            next if next < Self::STEPS => bsp_file.check_indices()?,
+            _ => return Ok(false),
+        }
+        self.next += 1;
+        Ok(true)
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.next as f32 / Self::STEPS as f32
+    }
+
+    /// Runs whatever steps are left
+    pub fn finish(mut self) -> Result<BspFile, BspError> {
+        while self.step()? {}
+        Ok(self.bsp_file.expect("a finished parser has a map"))
+    }
+}
+
+impl BspFileWidget {
+    pub fn from_bytes(buf: &[u8], id: usize, name: String) -> Result<Self, BspError> {
+        Ok(Self::from_bsp_file(BspFile::from_bytes(buf)?, id, name))
+    }
+
+    pub fn from_bsp_file(bsp_file: BspFile, id: usize, name: String) -> Self {
+        let (entities, entity_error) = match bsp_file.parse_entities() {
+            Ok(entities) => (entities, None),
+            Err(err) => (vec![], Some(err.to_string())),
+        };
+        Self {
+            bsp_file,
+            textures: vec![],
+            init_textures: true,
+            file_dialog: FileDialog::default().multiple(true).accept(&["wad"]),
+            texture_message: None,
+            wants_wads: false,
+            texture_report: None,
+            entities,
+            entity_error,
+            entity_search: String::new(),
+            viewport: MapViewport::default(),
+            name,
+            visible: true,
+            id,
+        }
+    }
+}
+
+impl BspFileWidget {
+    fn lumps_ui(&self, ui: &mut egui::Ui) {
+        egui::Grid::new("bsp_lumps").striped(true).show(ui, |ui| {
+            for (itr, lump) in self.bsp_file.header.lumps.iter().enumerate() {
+                ui.label(lump::NAMES[itr]);
+                ui.monospace(format!("{:>9} bytes at {:>9}", lump.length, lump.offset));
+                ui.end_row();
+            }
+        });
+    }
+
+    fn entities_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("📋Copy").clicked() {
+                ui.output_mut(|o| o.copied_text = self.bsp_file.entities.clone());
+            }
+            if ui.button("Save BSP").clicked() {
+                self.file_dialog.save(&self.name, self.bsp_file.to_bytes());
+            }
+        });
+        if let Some(err) = &self.entity_error {
+            ui.colored_label(ui.visuals().error_fg_color, format!("Can't edit, {}", err));
+            egui::ScrollArea::vertical()
+                .id_source("entities_text")
+                .max_height(400.0)
+                .show(ui, |ui| ui.monospace(&self.bsp_file.entities));
+            return
+        }
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            ui.text_edit_singleline(&mut self.entity_search);
+            if ui.button("Add entity").clicked() {
+                self.entities.push(Entity::new("info_target"));
+                changed = true;
+            }
+        });
+        let mut remove = None;
+        egui::ScrollArea::vertical()
+            .id_source("entities_list")
+            .max_height(400.0)
+            .show(ui, |ui| {
+                for (itr, entity) in self.entities.iter_mut().enumerate() {
+                    if !self.entity_search.is_empty() && !entity.matches(&self.entity_search) {
+                        continue
+                    }
+                    let title = match entity.get("targetname") {
+                        Some(targetname) => format!("{} {} ({})", itr, entity.classname(), targetname),
+                        None => format!("{} {}", itr, entity.classname()),
+                    };
+                    egui::CollapsingHeader::new(title)
+                        .id_source(("entity", itr))
+                        .show(ui, |ui| {
+                            let mut remove_pair = None;
+                            egui::Grid::new(("entity_pairs", itr)).show(ui, |ui| {
+                                for (pair_itr, (key, value)) in entity.pairs.iter_mut().enumerate() {
+                                    changed |= ui.text_edit_singleline(key).changed();
+                                    changed |= ui.text_edit_singleline(value).changed();
+                                    if ui.small_button("🗑").clicked() {
+                                        remove_pair = Some(pair_itr);
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                            if let Some(pair_itr) = remove_pair {
+                                entity.pairs.remove(pair_itr);
+                                changed = true;
+                            }
+                            ui.horizontal(|ui| {
+                                if ui.button("Add key").clicked() {
+                                    entity.pairs.push(Default::default());
+                                    changed = true;
+                                }
+                                // the world is always entity 0
+                                if ui.add_enabled(itr != 0, egui::Button::new("Remove entity")).clicked() {
+                                    remove = Some(itr);
+                                }
+                            });
+                        });
+                }
+            });
+        if let Some(itr) = remove {
+            self.entities.remove(itr);
+            changed = true;
+        }
+        if changed {
+            self.bsp_file.set_entities(&self.entities);
+        }
+    }
+
+    fn textures_ui(&mut self, ui: &mut egui::Ui) {
+        let external = self.bsp_file.external_textures();
+        let stem = self.name.strip_suffix(".bsp").unwrap_or(&self.name).to_string();
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.bsp_file.textures.iter().any(|miptex| miptex.texture.is_some()), egui::Button::new("Extract to WAD")).clicked() {
+                self.file_dialog.save(&format!("{}.wad", stem), self.bsp_file.to_wad().to_bytes());
+            }
+            if ui.add_enabled(!external.is_empty(), egui::Button::new("Embed from WAD…")).clicked() {
+                self.file_dialog.open();
+            }
+            if ui.button("Save BSP").clicked() {
+                self.file_dialog.save(&self.name, self.bsp_file.to_bytes());
+            }
+            if ui.button("Check against open WADs").clicked() {
+                self.wants_wads = true;
+            }
+        });
+        if let Some(texture_report) = &self.texture_report {
+            egui::CollapsingHeader::new("Texture sources")
+                .id_source("texture_report")
+                .default_open(true)
+                .show(ui, |ui| texture_report.ui(ui, &self.file_dialog, &format!("{}_textures", stem)));
+        }
+        if let Some(files) = self.file_dialog.get() {
+            let mut embedded = vec![];
+            let mut errors = vec![];
+            for (name, file) in files {
+                match WadReader::new(&file) {
+                    Ok(_) => embedded.extend(self.bsp_file.embed_textures(&WadFile::from_bytes(&file))),
+                    Err(err) => errors.push(format!("{}: {}", name, err)),
+                }
+            }
+            errors.insert(0, format!("Embedded {} textures, {} still external", embedded.len(), self.bsp_file.external_textures().len()));
+            self.texture_message = Some(errors.join("\n"));
+            self.init_textures = true;
+        }
+        if let Some(message) = &self.texture_message {
+            ui.label(message);
+        }
+        ui.horizontal_wrapped(|ui| {
+            for (miptex, thumbnail) in self.bsp_file.textures.iter().zip(self.textures.iter()) {
+                if let Some(thumbnail) = thumbnail {
+                    ui.add(egui::Image::new(thumbnail, thumbnail.size_vec2()))
+                        .on_hover_text(format!("{} {}x{}", miptex.name(), miptex.header.n_width, miptex.header.n_height));
+                }
+            }
+        });
+        if !external.is_empty() {
+            ui.label(format!("{} from WADs", external.len()));
+            ui.monospace(external.join("\n"));
+        }
+    }
+
+    fn models_ui(&self, ui: &mut egui::Ui) {
+        for (itr, model) in self.bsp_file.models.iter().enumerate() {
+            ui.monospace(format!("*{:<4} {:>6} faces {:?} {:?}", itr, model.num_faces, model.mins, model.maxs));
+        }
+    }
+}
+
+impl super::HlFileWidget for BspFileWidget {
+    fn show(&mut self, ctx: &egui::Context) {
+        let mut vis = self.visible;
+        use super::View as _;
+        egui::Window::new(self.name.as_str())
+            .open(&mut vis)
+            .scroll2([true, true])
+            .id(egui::Id::new(self.id))
+            .show(ctx, |ui| self.ui(ui));
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_visibility(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    fn get_visibility(&mut self) -> bool {
+        self.visible
+    }
+
+    fn wants_wads(&self) -> bool {
+        self.wants_wads
+    }
+
+    fn receive_wads(&mut self, wads: &[(String, &WadFile)]) {
+        self.texture_report = Some(self.bsp_file.texture_report(wads));
+        self.wants_wads = false;
+    }
+}
+
+impl super::View for BspFileWidget {
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        if self.init_textures {
+            self.textures = self.bsp_file.textures.iter()
+                .map(|miptex| miptex.texture.as_ref().map(|texture| ui.ctx().load_texture(
+                    miptex.name(),
+                    egui::ColorImage::from_rgb(
+                        [texture.header.n_width as usize, texture.header.n_height as usize],
+                        &texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0)),
+                    Default::default())))
+                .collect();
+            self.init_textures = false;
+        }
+        ui.label(format!(
+            "{} faces, {} vertices, {} models, {} KiB of lighting",
+            self.bsp_file.faces.len(),
+            self.bsp_file.vertices.len(),
+            self.bsp_file.models.len(),
+            self.bsp_file.lighting.len() / 1024));
+        egui::CollapsingHeader::new("Preview")
+            .id_source("preview")
+            .show(ui, |ui| {
+                self.viewport.controls_ui(ui);
+                self.viewport.ui(ui, &self.bsp_file, &self.textures);
+            });
+        egui::CollapsingHeader::new("Lumps")
+            .id_source("lumps")
+            .show(ui, |ui| self.lumps_ui(ui));
+        egui::CollapsingHeader::new(format!("Entities ({})", self.entities.len()))
+            .id_source("entities")
+            .show(ui, |ui| self.entities_ui(ui));
+        egui::CollapsingHeader::new(format!("Textures ({})", self.bsp_file.textures.len()))
+            .id_source("textures")
+            .default_open(true)
+            .show(ui, |ui| self.textures_ui(ui));
+        egui::CollapsingHeader::new(format!("Models ({})", self.bsp_file.models.len()))
+            .id_source("models")
+            .show(ui, |ui| self.models_ui(ui));
+    }
+}
+
+/// A `.map` editor source, all there is to show is which textures it needs
+pub struct MapFileWidget {
+    pub texture_names: Vec<String>,
+    texture_report: TextureReport,
+    file_dialog: FileDialog,
+    /// Asks for the open WADs on the first frame and again whenever the button is pressed
+    wants_wads: bool,
+    pub name: String,
+    pub visible: bool,
+    pub id: usize,
+}
+
+impl MapFileWidget {
+    pub fn from_bytes(buf: &[u8], id: usize, name: String) -> Self {
+        let texture_names = map_texture_names(&String::from_utf8_lossy(buf));
+        let texture_report = TextureReport::new(&texture_names, &[], &[]);
+        Self {
+            texture_names,
+            texture_report,
+            file_dialog: FileDialog::default(),
+            wants_wads: true,
+            name,
+            visible: true,
+            id,
+        }
+    }
+}
+
+impl super::HlFileWidget for MapFileWidget {
+    fn show(&mut self, ctx: &egui::Context) {
+        let mut vis = self.visible;
+        use super::View as _;
+        egui::Window::new(self.name.as_str())
+            .open(&mut vis)
+            .scroll2([true, true])
+            .id(egui::Id::new(self.id))
+            .show(ctx, |ui| self.ui(ui));
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_visibility(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    fn get_visibility(&mut self) -> bool {
+        self.visible
+    }
+
+    fn wants_wads(&self) -> bool {
+        self.wants_wads
+    }
+
+    fn receive_wads(&mut self, wads: &[(String, &WadFile)]) {
+        self.texture_report = TextureReport::new(&self.texture_names, &[], wads);
+        self.wants_wads = false;
+    }
+}
+
+impl super::View for MapFileWidget {
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Check against open WADs").clicked() {
+            self.wants_wads = true;
+        }
+        let stem = self.name.strip_suffix(".map").unwrap_or(&self.name);
+        self.texture_report.ui(ui, &self.file_dialog, &format!("{}_textures", stem));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One triangle on the floor with a 16x16 embedded texture, split from the solid below by one
+    /// node
+    fn test_bsp() -> Vec<u8> {
+        let mut lumps: Vec<Vec<u8>> = vec![vec![]; NUM_LUMPS];
+        lumps[lump::ENTITIES] = b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec();
+        for value in [0.0f32, 0.0, 1.0, 0.0] {
+            lumps[lump::PLANES].extend(value.to_le_bytes());
+        }
+        lumps[lump::PLANES].extend(2i32.to_le_bytes());
+
+        let mut miptex = b"floor\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+        miptex.extend(16u32.to_le_bytes());
+        miptex.extend(16u32.to_le_bytes());
+        for offset in [40u32, 40 + 256, 40 + 256 + 64, 40 + 256 + 64 + 16] {
+            miptex.extend(offset.to_le_bytes());
+        }
+        miptex.extend((0..(256 + 64 + 16 + 4)).map(|itr| (itr % 3) as u8));
+        miptex.extend(256u16.to_le_bytes());
+        miptex.extend([255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        miptex.extend([0u8; 256 * 3 - 9 + 2]);
+        lumps[lump::TEXTURES].extend(1i32.to_le_bytes());
+        lumps[lump::TEXTURES].extend(8i32.to_le_bytes());
+        lumps[lump::TEXTURES].extend(miptex);
+
+        for vertex in [[0.0f32, 0.0, 0.0], [64.0, 0.0, 0.0], [0.0, 64.0, 0.0]] {
+            for value in vertex {
+                lumps[lump::VERTICES].extend(value.to_le_bytes());
+            }
+        }
+        for value in [1.0f32, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+            lumps[lump::TEX_INFO].extend(value.to_le_bytes());
+        }
+        lumps[lump::TEX_INFO].extend([0u8; 8]);
+        for edge in [[0u16, 1], [1, 2], [0, 2]] {
+            for vertex in edge {
+                lumps[lump::EDGES].extend(vertex.to_le_bytes());
+            }
+        }
+        for surf_edge in [0i32, 1, -2] {
+            lumps[lump::SURF_EDGES].extend(surf_edge.to_le_bytes());
+        }
+        lumps[lump::FACES].extend([0u8; 4]);
+        lumps[lump::FACES].extend(0i32.to_le_bytes());
+        lumps[lump::FACES].extend(3u16.to_le_bytes());
+        lumps[lump::FACES].extend(0u16.to_le_bytes());
+        lumps[lump::FACES].extend([0, 255, 255, 255]);
+        lumps[lump::FACES].extend(0i32.to_le_bytes());
+        // 64 texels across is 5 samples each way
+        lumps[lump::LIGHTING] = (0..(3 * 5 * 5)).map(|itr| (itr / 3 * 10) as u8).collect();
+        lumps[lump::NODES].extend(0i32.to_le_bytes());
+        for value in [-2i16, -1, 0, 0, 0, 64, 64, 64, 0, 1] {
+            lumps[lump::NODES].extend(value.to_le_bytes());
+        }
+        for (contents, vis_offset, marks) in [(CONTENTS_SOLID, -1i32, 0u16), (-1, 0, 1)] {
+            lumps[lump::LEAVES].extend(contents.to_le_bytes());
+            lumps[lump::LEAVES].extend(vis_offset.to_le_bytes());
+            for value in [0i16, 0, 0, 64, 64, 64] {
+                lumps[lump::LEAVES].extend(value.to_le_bytes());
+            }
+            lumps[lump::LEAVES].extend(0u16.to_le_bytes());
+            lumps[lump::LEAVES].extend(marks.to_le_bytes());
+            lumps[lump::LEAVES].extend([0u8; 4]);
+        }
+        lumps[lump::MARK_SURFACES] = 0u16.to_le_bytes().to_vec();
+        // leaf 1 sees itself
+        lumps[lump::VISIBILITY] = vec![0b1];
+        for value in [0.0f32, 0.0, 0.0, 64.0, 64.0, 0.0, 0.0, 0.0, 0.0] {
+            lumps[lump::MODELS].extend(value.to_le_bytes());
+        }
+        for value in [0i32, 0, 0, 0, 1, 0, 1] {
+            lumps[lump::MODELS].extend(value.to_le_bytes());
+        }
+
+        let mut buf = BSP_VERSION.to_le_bytes().to_vec();
+        let mut offset = BSP_HEADER_SIZE;
+        for lump in lumps.iter() {
+            buf.extend((offset as i32).to_le_bytes());
+            buf.extend((lump.len() as i32).to_le_bytes());
+            offset += lump.len();
+        }
+        for lump in lumps {
+            buf.extend(lump);
+        }
+        buf
+    }
+
+    #[test]
+    fn reads_lumps_and_embedded_textures() {
+        let buf = test_bsp();
+        assert!(BspFile::validate_header(&buf));
+        let bsp_file = BspFile::from_bytes(&buf).unwrap();
+        assert!(bsp_file.entities.contains("worldspawn"));
+        assert_eq!(bsp_file.planes[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(bsp_file.models[0].num_faces, 1);
+        assert_eq!(bsp_file.face_vertices(&bsp_file.faces[0]), vec![[0.0, 0.0, 0.0], [64.0, 0.0, 0.0], [0.0, 64.0, 0.0]]);
+
+        assert_eq!(bsp_file.textures[0].name(), "floor");
+        let texture = bsp_file.textures[0].texture.as_ref().unwrap();
+        assert_eq!(&texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0)[0..6], &[255, 0, 0, 0, 255, 0]);
+
+        let mut truncated = buf.clone();
+        truncated.truncate(buf.len() - 10);
+        assert!(matches!(BspFile::from_bytes(&truncated), Err(BspError::LumpOutOfBounds { .. })));
+
+        let mut parser = BspParser::new(buf);
+        let mut steps = 0;
+        while parser.step().unwrap() {
+            steps += 1;
+            assert!(parser.progress() < 1.0 || steps == BspParser::STEPS);
+        }
+        assert_eq!(steps, BspParser::STEPS);
+        assert_eq!(parser.progress(), 1.0);
+        assert_eq!(parser.finish().unwrap().faces, bsp_file.faces);
+        let mut parser = BspParser::new(truncated);
+        assert!(matches!(parser.step(), Err(BspError::LumpOutOfBounds { .. })));
+    }
+
+    #[test]
+    fn walks_the_tree_back_to_front() {
+        let bsp_file = BspFile::from_bytes(&test_bsp()).unwrap();
+        assert_eq!(bsp_file.leaves.len(), 2);
+        assert_eq!(bsp_file.leaf_at(0, [8.0, 8.0, 10.0]), Some(1));
+        assert_eq!(bsp_file.leaf_at(0, [8.0, 8.0, -10.0]), Some(0));
+
+        let visibility = bsp_file.visibility(1);
+        assert_eq!(visibility.leaves, vec![false, true]);
+        assert_eq!(visibility.nodes, vec![true]);
+        assert_eq!(visibility.faces, vec![true]);
+        // nothing to go on from inside the solid, so everything counts
+        assert_eq!(bsp_file.visibility(0).leaves, vec![true, true]);
+
+        let walk = |eye: Vec3| {
+            let mut visits = vec![];
+            bsp_file.walk_back_to_front(0, eye, &|_| true, &mut |visit| visits.push(visit));
+            visits
+        };
+        assert_eq!(walk([8.0, 8.0, 10.0]), vec![Visit::Leaf(0), Visit::Face(0), Visit::Leaf(1)]);
+        assert_eq!(walk([8.0, 8.0, -10.0]), vec![Visit::Leaf(1), Visit::Face(0), Visit::Leaf(0)]);
+
+        let mut buf = test_bsp();
+        let nodes = BspFile::from_bytes(&buf).unwrap().header.lumps[lump::NODES].offset as usize;
+        buf[(nodes + 4)..(nodes + 6)].copy_from_slice(&5i16.to_le_bytes());
+        assert!(matches!(BspFile::from_bytes(&buf), Err(BspError::BadIndex { what: "node child", .. })));
+        buf[(nodes + 4)..(nodes + 6)].copy_from_slice(&0i16.to_le_bytes());
+        assert!(matches!(BspFile::from_bytes(&buf), Err(BspError::BadTree { node: 0, child: 0, .. })));
+    }
+
+    #[test]
+    fn textures_move_between_bsp_and_wad() {
+        let mut bsp_file = BspFile::from_bytes(&test_bsp()).unwrap();
+        let wad_bytes = bsp_file.to_wad().to_bytes();
+        let wad_file = WadFile::from_bytes(&wad_bytes);
+        assert_eq!(wad_file.entries.len(), 1);
+        assert_eq!(wad_file.find_texture("FLOOR").unwrap().palette[1].g, 255);
+
+        bsp_file.textures[0].texture = None;
+        let external = BspFile::from_bytes(&bsp_file.to_bytes()).unwrap();
+        assert_eq!(external.external_textures(), vec![String::from("floor")]);
+        assert_eq!(external.faces, bsp_file.faces);
+        assert!(external.entities.contains("worldspawn"));
+
+        let mut embedded = external;
+        assert_eq!(embedded.embed_textures(&wad_file), vec![String::from("floor")]);
+        let embedded = BspFile::from_bytes(&embedded.to_bytes()).unwrap();
+        assert!(embedded.external_textures().is_empty());
+        let texture = embedded.textures[0].texture.as_ref().unwrap();
+        assert_eq!(&texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0)[0..6], &[255, 0, 0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn reports_texture_sources() {
+        let map = "{\n( 0 0 0 ) ( 1 0 0 ) ( 0 1 0 ) FLOOR 0 0 0 1 1\n\
+            ( 0 0 0 ) ( 0 1 0 ) ( 0 0 1 ) {blue [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1\n\
+            ( 0 0 0 ) ( 0 0 1 ) ( 1 0 0 ) floor 0 0 0 1 1\n}\n";
+        let names = map_texture_names(map);
+        assert_eq!(names, vec![String::from("FLOOR"), String::from("{blue")]);
+
+        let bsp_file = BspFile::from_bytes(&test_bsp()).unwrap();
+        let wad_file = bsp_file.to_wad();
+        let wads = [(String::from("test.wad"), &wad_file)];
+        let report = TextureReport::new(&names, &[], &wads);
+        assert_eq!(report.entries[0].1, TextureSource::Wad(String::from("test.wad")));
+        assert_eq!(report.missing().collect::<Vec<_>>(), vec!["{blue"]);
+        assert_eq!(report.to_wad().entries.len(), 1);
+
+        assert_eq!(bsp_file.texture_report(&wads).entries[0].1, TextureSource::Embedded);
+
+        // the map's own WAD list decides which open WAD a texture comes from
+        assert_eq!(wad_key_names("\\half-life\\valve\\halflife.wad; C:/maps/test.wad;"), vec![String::from("halflife.wad"), String::from("test.wad")]);
+        let mut bsp_file = bsp_file;
+        bsp_file.textures[0].texture = None;
+        let mut entities = bsp_file.parse_entities().unwrap();
+        entities[0].set("wad", "\\half-life\\valve\\halflife.wad;C:\\maps\\test.wad");
+        bsp_file.set_entities(&entities);
+        let wads = [(String::from("first.wad"), &wad_file), (String::from("test.wad"), &wad_file)];
+        let report = bsp_file.texture_report(&wads);
+        assert_eq!(report.entries[0].1, TextureSource::Wad(String::from("test.wad")));
+        assert_eq!(report.map_wads, vec![(String::from("halflife.wad"), false), (String::from("test.wad"), true)]);
+    }
+
+    #[test]
+    fn entities_edit_and_write_back() {
+        let mut bsp_file = BspFile::from_bytes(&test_bsp()).unwrap();
+        let mut entities = bsp_file.parse_entities().unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].classname(), "worldspawn");
+
+        entities[0].set("wad", "\\half-life\\valve\\halflife.wad");
+        let mut light = Entity::new("light");
+        light.set("_light", "255 255 128 200");
+        light.set("origin", "16 16 32");
+        entities.push(light);
+        bsp_file.set_entities(&entities);
+
+        let written = BspFile::from_bytes(&bsp_file.to_bytes()).unwrap();
+        assert_eq!(written.parse_entities().unwrap(), entities);
+        assert_eq!(written.header.lumps[lump::ENTITIES].length as usize, bsp_file.entities.len() + 1);
+        assert_eq!(written.faces, bsp_file.faces);
+
+        assert!(matches!(parse_entities("{\n\"classname\" \"light\"\n"), Err(BspError::BadEntities { reason: "missing } at the end", .. })));
+        assert!(matches!(parse_entities("{\n\"classname\"\n}\n"), Err(BspError::BadEntities { .. })));
+    }
+
+    #[test]
+    fn entities_keep_bytes_that_are_not_utf8() {
+        let mut buf = test_bsp();
+        let lump_bytes = b"{\n\"classname\" \"worldspawn\"\n\"message\" \"Caf\xe9\"\n}\n\0";
+        let offset = buf.len();
+        buf.extend(lump_bytes);
+        buf[4..8].copy_from_slice(&(offset as i32).to_le_bytes());
+        buf[8..12].copy_from_slice(&(lump_bytes.len() as i32).to_le_bytes());
+
+        let mut bsp_file = BspFile::from_bytes(&buf).unwrap();
+        assert!(bsp_file.entities.contains("Caf\u{fffd}"));
+        let written = BspFile::from_bytes(&bsp_file.to_bytes()).unwrap();
+        assert_eq!(written.lump(lump::ENTITIES), lump_bytes);
+
+        let mut entities = bsp_file.parse_entities().unwrap();
+        entities[0].set("wad", "halflife.wad");
+        bsp_file.set_entities(&entities);
+        let written = BspFile::from_bytes(&bsp_file.to_bytes()).unwrap();
+        assert_eq!(written.parse_entities().unwrap(), entities);
+    }
+
+    #[test]
+    fn lightmaps_pack_into_atlas() {
+        let bsp_file = BspFile::from_bytes(&test_bsp()).unwrap();
+        let face = &bsp_file.faces[0];
+        assert_eq!(bsp_file.face_normal(face), [0.0, 0.0, 1.0]);
+        let (extents, samples) = bsp_file.face_lightmap(face).unwrap();
+        assert_eq!((extents.mins, extents.width, extents.height), ([0.0, 0.0], 5, 5));
+        assert_eq!(extents.sample_coord([32.0, 16.0]), [2.0, 1.0]);
+        assert_eq!(sample_lightmap(&extents, samples, [0.5, 0.0]), [5.0; 3]);
+        assert_eq!(sample_lightmap(&extents, samples, [2.0, 1.0]), [70.0; 3]);
+
+        let atlas = bsp_file.lightmap_atlas(16);
+        assert_eq!((atlas.width, atlas.height), (16, 7));
+        assert_eq!(atlas.offsets, vec![Some([1, 1])]);
+        // the border repeats the edge samples
+        assert_eq!(&atlas.pixels[0..3], &[0, 0, 0]);
+        assert_eq!(&atlas.pixels[((6 * 16) + 6) * 3..((6 * 16) + 7) * 3], &[240, 240, 240]);
+        assert_eq!(bsp_file.lightmap_atlas(4).offsets, vec![None]);
+    }
+}
@@ -0,0 +1,117 @@
+use super::{BspError, BspFile};
+use crate::math::Vec3;
+
+/// One `{ }` block of the entity lump, keys are kept in the order they were written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entity {
+    pub pairs: Vec<(String, String)>,
+}
+
+impl Entity {
+    pub fn new(classname: &str) -> Self {
+        Self { pairs: vec![(String::from("classname"), classname.to_string())] }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Changes the first `key`, or adds it at the end
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.pairs.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.pairs.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    /// Values like `origin` and `angles` that are three numbers split by spaces
+    pub fn get_vec3(&self, key: &str) -> Option<Vec3> {
+        let values: Vec<f32> = self.get(key)?.split_whitespace().filter_map(|value| value.parse().ok()).collect();
+        match values[..] {
+            [x, y, z] => Some([x, y, z]),
+            _ => None,
+        }
+    }
+
+    pub fn classname(&self) -> &str {
+        self.get("classname").unwrap_or("")
+    }
+
+    /// Case insensitive search through keys and values
+    pub fn matches(&self, search: &str) -> bool {
+        let search = search.to_lowercase();
+        self.pairs.iter().any(|(key, value)| key.to_lowercase().contains(&search) || value.to_lowercase().contains(&search))
+    }
+}
+
+/// Parses the entity lump, there's no escaping so a string runs to the next quote
+pub fn parse_entities(text: &str) -> Result<Vec<Entity>, BspError> {
+    let mut entities = vec![];
+    let mut current: Option<Entity> = None;
+    let mut key: Option<String> = None;
+    let mut line = 1;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '{' if current.is_none() => current = Some(Entity::default()),
+            '}' if key.is_none() => match current.take() {
+                Some(entity) => entities.push(entity),
+                None => return Err(BspError::BadEntities { line, reason: "} without a {" }),
+            },
+            '"' if current.is_some() => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            string.push(c);
+                        },
+                        None => return Err(BspError::BadEntities { line, reason: "unterminated string" }),
+                    }
+                }
+                match key.take() {
+                    Some(key) => current.as_mut().unwrap().pairs.push((key, string)),
+                    None => key = Some(string),
+                }
+            },
+            '\0' => break,
+            c if c.is_whitespace() => {},
+            '{' => return Err(BspError::BadEntities { line, reason: "{ inside an entity" }),
+            '}' => return Err(BspError::BadEntities { line, reason: "key without a value" }),
+            _ => return Err(BspError::BadEntities { line, reason: "text outside of quotes" }),
+        }
+    }
+    if current.is_some() {
+        return Err(BspError::BadEntities { line, reason: "missing } at the end" })
+    }
+    Ok(entities)
+}
+
+/// Writes entities the way the compile tools do, one pair per line. Quotes can't be escaped so
+/// they're dropped
+pub fn write_entities(entities: &[Entity]) -> String {
+    let mut text = String::new();
+    for entity in entities.iter() {
+        text.push_str("{\n");
+        for (key, value) in entity.pairs.iter() {
+            text.push_str(&format!("\"{}\" \"{}\"\n", key.replace('"', ""), value.replace('"', "")));
+        }
+        text.push_str("}\n");
+    }
+    text
+}
+
+impl BspFile {
+    pub fn parse_entities(&self) -> Result<Vec<Entity>, BspError> {
+        parse_entities(&self.entities)
+    }
+
+    /// Replaces the entity lump, `to_bytes` sizes it to fit
+    pub fn set_entities(&mut self, entities: &[Entity]) {
+        self.entities = write_entities(entities);
+    }
+}
@@ -0,0 +1,140 @@
+use super::{BspFile, Face, TexInfo};
+use crate::math::{self, Vec3};
+
+/// Texels per lightmap sample
+pub const LIGHTMAP_SCALE: f32 = 16.0;
+/// `TexInfo::flags` bit for sky and liquids, which are drawn without a lightmap
+pub const TEX_SPECIAL: i32 = 1;
+
+/// Where a face's lightmap sits on its texture plane, `CalcSurfaceExtents` in the engine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightmapExtents {
+    /// Texel coordinate of the first sample
+    pub mins: [f32; 2],
+    pub width: usize,
+    pub height: usize,
+}
+
+impl LightmapExtents {
+    /// Position of a texel coordinate in samples from the first one
+    pub fn sample_coord(&self, texel: [f32; 2]) -> [f32; 2] {
+        [(texel[0] - self.mins[0]) / LIGHTMAP_SCALE, (texel[1] - self.mins[1]) / LIGHTMAP_SCALE]
+    }
+}
+
+impl TexInfo {
+    pub fn texel(&self, position: Vec3) -> [f32; 2] {
+        self.vecs.map(|axis| math::dot(position, [axis[0], axis[1], axis[2]]) + axis[3])
+    }
+}
+
+/// Every face's lightmap packed into one RGB image
+#[derive(Debug, Clone)]
+pub struct LightmapAtlas {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    /// Pixel of each face's first sample, lining up with `BspFile::faces`
+    pub offsets: Vec<Option<[usize; 2]>>,
+}
+
+impl BspFile {
+    /// The face's normal, its plane's flipped when it faces the other way
+    pub fn face_normal(&self, face: &Face) -> Vec3 {
+        let normal = self.planes[face.plane as usize].normal;
+        if face.side != 0 {
+            math::scale(normal, -1.0)
+        } else {
+            normal
+        }
+    }
+
+    /// `None` for faces without light, or whose lightmap would run past the lighting lump
+    pub fn lightmap_extents(&self, face: &Face) -> Option<LightmapExtents> {
+        let tex_info = &self.tex_infos[face.tex_info as usize];
+        if tex_info.flags & TEX_SPECIAL != 0 || face.light_offset < 0 || face.styles[0] == 255 || face.num_edges < 3 {
+            return None
+        }
+        let mut mins = [f32::MAX; 2];
+        let mut maxs = [f32::MIN; 2];
+        for position in self.face_vertices(face) {
+            let texel = tex_info.texel(position);
+            for axis in 0..2 {
+                mins[axis] = mins[axis].min(texel[axis]);
+                maxs[axis] = maxs[axis].max(texel[axis]);
+            }
+        }
+        let first = mins.map(|min| (min / LIGHTMAP_SCALE).floor());
+        let last = maxs.map(|max| (max / LIGHTMAP_SCALE).ceil());
+        let extents = LightmapExtents {
+            mins: first.map(|first| first * LIGHTMAP_SCALE),
+            width: (last[0] - first[0]) as usize + 1,
+            height: (last[1] - first[1]) as usize + 1,
+        };
+        if face.light_offset as usize + extents.width * extents.height * 3 > self.lighting.len() {
+            return None
+        }
+        Some(extents)
+    }
+
+    /// RGB samples of the face's first light style, row by row
+    pub fn face_lightmap(&self, face: &Face) -> Option<(LightmapExtents, &[u8])> {
+        let extents = self.lightmap_extents(face)?;
+        let start = face.light_offset as usize;
+        Some((extents, &self.lighting[start..(start + extents.width * extents.height * 3)]))
+    }
+
+    /// Lightmaps packed in rows `width` pixels wide, each with a one pixel border copied from its
+    /// edge so filtering doesn't bleed between faces
+    pub fn lightmap_atlas(&self, width: usize) -> LightmapAtlas {
+        let mut offsets = vec![];
+        let mut placed = vec![];
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for face in self.faces.iter() {
+            let (extents, samples) = match self.face_lightmap(face) {
+                Some(lightmap) if lightmap.0.width + 2 <= width => lightmap,
+                _ => {
+                    offsets.push(None);
+                    continue
+                },
+            };
+            if x + extents.width + 2 > width {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            offsets.push(Some([x + 1, y + 1]));
+            placed.push((x, y, extents, samples));
+            x += extents.width + 2;
+            row_height = row_height.max(extents.height + 2);
+        }
+        let height = (y + row_height).max(1);
+        let mut pixels = vec![0; width * height * 3];
+        for (x, y, extents, samples) in placed {
+            for row in 0..(extents.height + 2) {
+                let sample_row = row.saturating_sub(1).min(extents.height - 1);
+                for column in 0..(extents.width + 2) {
+                    let sample = sample_row * extents.width + column.saturating_sub(1).min(extents.width - 1);
+                    let pixel = ((y + row) * width + x + column) * 3;
+                    pixels[pixel..(pixel + 3)].copy_from_slice(&samples[(sample * 3)..(sample * 3 + 3)]);
+                }
+            }
+        }
+        LightmapAtlas { width, height, pixels, offsets }
+    }
+}
+
+/// Bilinear sample of a face lightmap, `coord` in samples as from `LightmapExtents::sample_coord`
+pub fn sample_lightmap(extents: &LightmapExtents, samples: &[u8], coord: [f32; 2]) -> [f32; 3] {
+    let x = coord[0].clamp(0.0, (extents.width - 1) as f32);
+    let y = coord[1].clamp(0.0, (extents.height - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(extents.width - 1), (y0 + 1).min(extents.height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |x: usize, y: usize, channel: usize| samples[(y * extents.width + x) * 3 + channel] as f32;
+    [0, 1, 2].map(|channel| {
+        let top = at(x0, y0, channel) * (1.0 - fx) + at(x1, y0, channel) * fx;
+        let bottom = at(x0, y1, channel) * (1.0 - fx) + at(x1, y1, channel) * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
@@ -0,0 +1,167 @@
+use super::BspFile;
+use crate::file_dialog::FileDialog;
+use crate::hlwad::{Texture, WadFile};
+
+/// Where a texture a map uses comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureSource {
+    /// Compiled into the BSP
+    Embedded,
+    /// Found in the WAD with this name
+    Wad(String),
+    /// Nowhere to be found, the engine draws a checkerboard
+    Missing,
+}
+
+/// Every texture a map uses and where it was found
+#[derive(Debug, Clone, Default)]
+pub struct TextureReport {
+    pub entries: Vec<(String, TextureSource)>,
+    /// The textures found in WADs, enough for a WAD of only what the map needs
+    pub needed: Vec<Texture>,
+    /// WADs the map's worldspawn names, in its search order, and whether each one is open
+    pub map_wads: Vec<(String, bool)>,
+}
+
+impl TextureReport {
+    /// Looks `names` up in `wads` in order, the first WAD that has a texture wins as it does in
+    /// the engine. Names in `embedded` aren't looked for
+    pub fn new(names: &[String], embedded: &[String], wads: &[(String, &WadFile)]) -> Self {
+        let mut report = Self::default();
+        for name in names.iter() {
+            if embedded.iter().any(|embedded| embedded.eq_ignore_ascii_case(name)) {
+                report.entries.push((name.clone(), TextureSource::Embedded));
+                continue
+            }
+            let found = wads.iter().find_map(|(wad_name, wad_file)| wad_file.find_texture(name).map(|texture| (wad_name, texture)));
+            match found {
+                Some((wad_name, texture)) => {
+                    report.entries.push((name.clone(), TextureSource::Wad(wad_name.clone())));
+                    report.needed.push(texture.clone());
+                },
+                None => report.entries.push((name.clone(), TextureSource::Missing)),
+            }
+        }
+        report
+    }
+
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter()
+            .filter(|(_, source)| *source == TextureSource::Missing)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// A WAD holding just the textures the map needs from the open WADs
+    pub fn to_wad(&self) -> WadFile {
+        WadFile::from_textures(self.needed.clone())
+    }
+
+    /// The report as a table, plus a button to save the minimal WAD as `stem.wad`
+    pub fn ui(&self, ui: &mut egui::Ui, file_dialog: &FileDialog, stem: &str) {
+        let num_missing = self.missing().count();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} textures, {} missing", self.entries.len(), num_missing));
+            if ui.add_enabled(!self.needed.is_empty(), egui::Button::new("Save needed textures as WAD")).clicked() {
+                file_dialog.save(&format!("{}.wad", stem), self.to_wad().to_bytes());
+            }
+            if num_missing > 0 && ui.button("📋Copy missing").clicked() {
+                ui.output_mut(|o| o.copied_text = self.missing().collect::<Vec<_>>().join("\n"));
+            }
+        });
+        if !self.map_wads.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Map WADs:");
+                for (name, open) in self.map_wads.iter() {
+                    if *open {
+                        ui.label(name);
+                    } else {
+                        ui.colored_label(ui.visuals().error_fg_color, format!("{} (not open)", name));
+                    }
+                }
+            });
+        }
+        egui::Grid::new(("texture_report", stem)).striped(true).show(ui, |ui| {
+            for (name, source) in self.entries.iter() {
+                ui.monospace(name);
+                match source {
+                    TextureSource::Embedded => ui.label("embedded"),
+                    TextureSource::Wad(wad_name) if self.map_wads.is_empty() || self.map_wads.iter().any(|(name, _)| name.eq_ignore_ascii_case(file_name(wad_name))) => ui.label(wad_name),
+                    // the engine won't look in a WAD the map doesn't name
+                    TextureSource::Wad(wad_name) => ui.colored_label(ui.visuals().warn_fg_color, format!("{} (not in the map's list)", wad_name)),
+                    TextureSource::Missing => ui.colored_label(ui.visuals().error_fg_color, "missing"),
+                };
+                ui.end_row();
+            }
+        });
+    }
+}
+
+/// Texture names used by the brushes of a `.map` source, both the Quake and Valve 220 face
+/// formats put the name right after the three points
+pub fn map_texture_names(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for line in text.lines() {
+        let line = line.trim_start();
+        if !line.starts_with('(') {
+            continue
+        }
+        let name = line.splitn(4, ')').nth(3).and_then(|rest| rest.split_whitespace().next());
+        if let Some(name) = name {
+            if !names.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// The part of a path after the last slash of either kind
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// File names of the WADs in a worldspawn `wad` key, the compile tools write full paths split by `;`
+pub fn wad_key_names(value: &str) -> Vec<String> {
+    value.split(';')
+        .map(|path| path.trim())
+        .filter(|path| !path.is_empty())
+        .map(|path| file_name(path).to_string())
+        .collect()
+}
+
+impl BspFile {
+    /// The worldspawn's `wad` key as file names, empty when there's none
+    pub fn map_wads(&self) -> Vec<String> {
+        self.parse_entities().unwrap_or_default().iter()
+            .find(|entity| entity.classname() == "worldspawn")
+            .and_then(|worldspawn| worldspawn.get("wad"))
+            .map(wad_key_names)
+            .unwrap_or_default()
+    }
+
+    /// Names of every texture in the texture lump, embedded or not
+    pub fn texture_names(&self) -> Vec<String> {
+        self.textures.iter()
+            .filter(|miptex| miptex.header.sz_name[0] != 0)
+            .map(|miptex| miptex.name())
+            .collect()
+    }
+
+    /// Looks through the open WADs the map names first, in its order, then the rest of `wads`
+    pub fn texture_report(&self, wads: &[(String, &WadFile)]) -> TextureReport {
+        let embedded: Vec<String> = self.textures.iter()
+            .filter(|miptex| miptex.texture.is_some())
+            .map(|miptex| miptex.name())
+            .collect();
+        let map_wads = self.map_wads();
+        let mut ordered: Vec<(String, &WadFile)> = wads.to_vec();
+        ordered.sort_by_key(|(wad_name, _)| {
+            map_wads.iter().position(|name| name.eq_ignore_ascii_case(file_name(wad_name))).unwrap_or(usize::MAX)
+        });
+        let mut report = TextureReport::new(&self.texture_names(), &embedded, &ordered);
+        report.map_wads = map_wads.iter()
+            .map(|name| (name.clone(), wads.iter().any(|(wad_name, _)| name.eq_ignore_ascii_case(file_name(wad_name)))))
+            .collect();
+        report
+    }
+}
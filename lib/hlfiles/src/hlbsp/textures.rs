@@ -0,0 +1,36 @@
+use super::BspFile;
+use crate::hlwad::WadFile;
+
+impl BspFile {
+    /// Every texture compiled into the map, as a WAD of its own
+    pub fn to_wad(&self) -> WadFile {
+        WadFile::from_textures(self.textures.iter().filter_map(|miptex| miptex.texture.clone()).collect())
+    }
+
+    /// Textures the map uses but doesn't carry, the engine looks for them in the WADs
+    /// worldspawn lists
+    pub fn external_textures(&self) -> Vec<String> {
+        self.textures.iter()
+            .filter(|miptex| miptex.texture.is_none() && miptex.header.sz_name[0] != 0)
+            .map(|miptex| miptex.name())
+            .collect()
+    }
+
+    /// Copies the external textures `wad_file` has into the map, like compiling with
+    /// `-wadinclude`, and returns the names of the ones it embedded
+    pub fn embed_textures(&mut self, wad_file: &WadFile) -> Vec<String> {
+        let mut embedded = vec![];
+        for miptex in self.textures.iter_mut().filter(|miptex| miptex.texture.is_none() && miptex.header.sz_name[0] != 0) {
+            let name = miptex.name();
+            if let Some(texture) = wad_file.find_texture(&name) {
+                let mut texture = texture.clone();
+                // keep the map's spelling, faces were compiled against it
+                texture.header.sz_name = miptex.header.sz_name;
+                miptex.header = texture.header;
+                miptex.texture = Some(texture);
+                embedded.push(name);
+            }
+        }
+        embedded
+    }
+}
@@ -0,0 +1,135 @@
+use super::{BspFile, Node};
+use crate::math::{self, Vec3};
+
+/// `Leaf::contents` of the space inside brushes
+pub const CONTENTS_SOLID: i32 = -2;
+
+/// What `BspFile::walk_back_to_front` comes across, in painting order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    Leaf(usize),
+    Face(usize),
+}
+
+/// What might be seen from one leaf of the world, everything when the map has no vis data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Visibility {
+    pub leaves: Vec<bool>,
+    /// Nodes with a visible leaf somewhere under them
+    pub nodes: Vec<bool>,
+    /// Faces touching a visible leaf
+    pub faces: Vec<bool>,
+}
+
+impl BspFile {
+    /// 0 when `position` is in front of the node's plane, 1 when it's behind
+    fn node_side(&self, node: &Node, position: Vec3) -> usize {
+        let plane = &self.planes[node.plane as usize];
+        if math::dot(plane.normal, position) - plane.dist >= 0.0 { 0 } else { 1 }
+    }
+
+    /// The leaf `position` falls in, going down from `head_node`. `None` when there's no tree
+    pub fn leaf_at(&self, head_node: i32, position: Vec3) -> Option<usize> {
+        let mut child = head_node;
+        // a broken file could loop, a real path is never longer than the node count
+        for _ in 0..=self.nodes.len() {
+            if child < 0 {
+                return Some(!child as usize)
+            }
+            let node = self.nodes.get(child as usize)?;
+            child = node.children[self.node_side(node, position)] as i32;
+        }
+        None
+    }
+
+    /// Decompresses the leaf's row of the visibility lump, runs of zero bytes are stored as a zero
+    /// and a count. Rows start at leaf 1 since nothing is seen from the solid leaf
+    fn leaf_pvs(&self, leaf: usize) -> Option<Vec<bool>> {
+        let vis_offset = self.leaves.get(leaf).filter(|leaf| leaf.contents != CONTENTS_SOLID)?.vis_offset;
+        if leaf == 0 || vis_offset < 0 {
+            return None
+        }
+        let vis_leafs = self.models.first()?.vis_leafs.max(0) as usize;
+        let mut bytes = self.visibility.get((vis_offset as usize)..)?.iter();
+        let mut visible = vec![false; self.leaves.len()];
+        let mut itr = 0;
+        while itr < vis_leafs {
+            match *bytes.next()? {
+                0 => itr += 8 * *bytes.next()? as usize,
+                bits => {
+                    for bit in 0..8 {
+                        if bits & (1 << bit) != 0 {
+                            if let Some(visible) = visible.get_mut(1 + itr + bit) {
+                                *visible = true;
+                            }
+                        }
+                    }
+                    itr += 8;
+                },
+            }
+        }
+        visible[leaf] = true;
+        Some(visible)
+    }
+
+    /// What can be seen from `leaf` of the world model, from outside the map everything can
+    pub fn visibility(&self, leaf: usize) -> Visibility {
+        let leaves = match self.leaf_pvs(leaf) {
+            Some(leaves) => leaves,
+            None => return Visibility {
+                leaves: vec![true; self.leaves.len()],
+                nodes: vec![true; self.nodes.len()],
+                faces: vec![true; self.faces.len()],
+            },
+        };
+        let mut nodes = vec![false; self.nodes.len()];
+        if let Some(world) = self.models.first() {
+            self.mark_nodes(world.head_nodes[0], &leaves, &mut nodes, 0);
+        }
+        let mut faces = vec![false; self.faces.len()];
+        for (leaf, _) in self.leaves.iter().zip(leaves.iter()).filter(|(_, visible)| **visible) {
+            let first = leaf.first_mark_surface as usize;
+            for mark_surface in self.mark_surfaces[first..(first + leaf.num_mark_surfaces as usize)].iter() {
+                faces[*mark_surface as usize] = true;
+            }
+        }
+        Visibility { leaves, nodes, faces }
+    }
+
+    fn mark_nodes(&self, child: i32, leaves: &[bool], nodes: &mut [bool], depth: usize) -> bool {
+        if child < 0 {
+            return leaves.get(!child as usize).copied().unwrap_or(false)
+        }
+        let node = match self.nodes.get(child as usize) {
+            Some(node) if depth <= self.nodes.len() => node,
+            _ => return false,
+        };
+        let front = self.mark_nodes(node.children[0] as i32, leaves, nodes, depth + 1);
+        let back = self.mark_nodes(node.children[1] as i32, leaves, nodes, depth + 1);
+        nodes[child as usize] |= front || back;
+        front || back
+    }
+
+    /// Walks a model's tree taking the side of each plane away from `eye` first, so faces come
+    /// out in the order a painter would draw them. `enter` can skip a node and everything under it
+    pub fn walk_back_to_front(&self, head_node: i32, eye: Vec3, enter: &dyn Fn(usize) -> bool, visit: &mut dyn FnMut(Visit)) {
+        self.walk(head_node, eye, enter, visit, 0);
+    }
+
+    fn walk(&self, child: i32, eye: Vec3, enter: &dyn Fn(usize) -> bool, visit: &mut dyn FnMut(Visit), depth: usize) {
+        if child < 0 {
+            visit(Visit::Leaf(!child as usize));
+            return
+        }
+        let node = match self.nodes.get(child as usize) {
+            Some(node) if depth <= self.nodes.len() && enter(child as usize) => node,
+            _ => return,
+        };
+        let near = self.node_side(node, eye);
+        self.walk(node.children[1 - near] as i32, eye, enter, visit, depth + 1);
+        for face in (node.first_face as usize)..(node.first_face as usize + node.num_faces as usize) {
+            visit(Visit::Face(face));
+        }
+        self.walk(node.children[near] as i32, eye, enter, visit, depth + 1);
+    }
+}
@@ -0,0 +1,522 @@
+use super::geometry::{sample_lightmap, LightmapAtlas};
+use super::tree::{Visibility, Visit, CONTENTS_SOLID};
+use super::BspFile;
+use crate::math::{self, Vec3};
+
+const FOV_DEGREES: f32 = 75.0;
+/// Faces are clipped this far in front of the camera
+const NEAR: f32 = 1.0;
+/// Units per second, shift flies three times as fast
+const FLY_SPEED: f32 = 320.0;
+/// Player origin to eyes, `VEC_VIEW` in the SDK
+const EYE_HEIGHT: f32 = 28.0;
+/// Faces repeating their texture more often than this are drawn stretched instead of split. The
+/// compile tools subdivide faces to 240 texels so only tiny textures come near it
+const MAX_TILES: f32 = 64.0;
+const ATLAS_WIDTH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Textured,
+    /// Textures shaded by the lightmap at every corner
+    Lit,
+    Lightmap,
+    Wireframe,
+    /// Outlines seen straight from above
+    TopDown,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Corner {
+    position: Vec3,
+    uv: egui::Pos2,
+    /// Into the lightmap atlas
+    lightmap_uv: egui::Pos2,
+    light: [f32; 3],
+}
+
+impl Corner {
+    fn lerp(&self, other: &Corner, t: f32) -> Corner {
+        Corner {
+            position: math::lerp(self.position, other.position, t),
+            uv: self.uv + (other.uv - self.uv) * t,
+            lightmap_uv: self.lightmap_uv + (other.lightmap_uv - self.lightmap_uv) * t,
+            light: [0, 1, 2].map(|itr| self.light[itr] + (other.light[itr] - self.light[itr]) * t),
+        }
+    }
+}
+
+/// Keeps the part of a convex polygon where `distance` isn't negative
+fn clip(corners: &[Corner], distance: impl Fn(&Corner) -> f32) -> Vec<Corner> {
+    let mut clipped = vec![];
+    for itr in 0..corners.len() {
+        let (a, b) = (&corners[itr], &corners[(itr + 1) % corners.len()]);
+        let (distance_a, distance_b) = (distance(a), distance(b));
+        if distance_a >= 0.0 {
+            clipped.push(*a);
+        }
+        if (distance_a >= 0.0) != (distance_b >= 0.0) {
+            clipped.push(a.lerp(b, distance_a / (distance_a - distance_b)));
+        }
+    }
+    clipped
+}
+
+/// egui textures clamp instead of repeating, so faces are cut along whole UVs and each piece is
+/// moved back into 0..1
+fn split_tiles(corners: Vec<Corner>) -> Vec<Vec<Corner>> {
+    let mut min = egui::pos2(f32::MAX, f32::MAX);
+    let mut max = egui::pos2(f32::MIN, f32::MIN);
+    for corner in corners.iter() {
+        min = min.min(corner.uv);
+        max = max.max(corner.uv);
+    }
+    let first = egui::pos2(min.x.floor(), min.y.floor());
+    let last = egui::pos2(max.x.ceil().max(first.x + 1.0), max.y.ceil().max(first.y + 1.0));
+    if (last.x - first.x) * (last.y - first.y) > MAX_TILES {
+        return vec![corners]
+    }
+    let mut pieces = vec![];
+    let mut u = first.x;
+    while u < last.x {
+        let column = clip(&clip(&corners, |corner| corner.uv.x - u), |corner| u + 1.0 - corner.uv.x);
+        let mut v = first.y;
+        while v < last.y {
+            let mut piece = clip(&clip(&column, |corner| corner.uv.y - v), |corner| v + 1.0 - corner.uv.y);
+            if piece.len() >= 3 {
+                for corner in piece.iter_mut() {
+                    corner.uv -= egui::vec2(u, v);
+                }
+                pieces.push(piece);
+            }
+            v += 1.0;
+        }
+        u += 1.0;
+    }
+    pieces
+}
+
+/// A face ready to be projected
+struct Polygon {
+    miptex: usize,
+    sky: bool,
+    /// Has a place in the lightmap atlas
+    lit: bool,
+    normal: Vec3,
+    /// The whole face, for when there's no texture to repeat
+    corners: Vec<Corner>,
+    /// One piece per repeat of the texture
+    tiles: Vec<Vec<Corner>>,
+}
+
+/// A face's corners and the model it belongs to
+type Outline = (usize, Vec<Vec3>);
+
+/// Polygons lining up with `BspFile::faces` and the outline of each face
+fn build(bsp_file: &BspFile, atlas: &LightmapAtlas) -> (Vec<Option<Polygon>>, Vec<Outline>) {
+    let mut polygons: Vec<Option<Polygon>> = (0..bsp_file.faces.len()).map(|_| None).collect();
+    let mut outlines = vec![];
+    for (model_itr, model) in bsp_file.models.iter().enumerate() {
+        let faces = polygons.iter_mut().enumerate().skip(model.first_face as usize).take(model.num_faces as usize);
+        for (face_itr, polygon) in faces {
+            let face = &bsp_file.faces[face_itr];
+            let positions = bsp_file.face_vertices(face);
+            if positions.len() < 3 {
+                continue;
+            }
+            let tex_info = &bsp_file.tex_infos[face.tex_info as usize];
+            let miptex = tex_info.miptex as usize;
+            let header = &bsp_file.textures[miptex].header;
+            let size = [header.n_width.max(1) as f32, header.n_height.max(1) as f32];
+            let lightmap = bsp_file.face_lightmap(face).zip(atlas.offsets[face_itr]);
+            let corners = positions.iter()
+                .map(|position| {
+                    let texel = tex_info.texel(*position);
+                    let (lightmap_uv, light) = match lightmap {
+                        Some(((extents, samples), offset)) => {
+                            let coord = extents.sample_coord(texel);
+                            (egui::pos2(
+                                (coord[0] + offset[0] as f32 + 0.5) / atlas.width as f32,
+                                (coord[1] + offset[1] as f32 + 0.5) / atlas.height as f32),
+                             sample_lightmap(&extents, samples, coord))
+                        },
+                        None => (egui::Pos2::ZERO, [255.0; 3]),
+                    };
+                    Corner {
+                        position: *position,
+                        uv: egui::pos2(texel[0] / size[0], texel[1] / size[1]),
+                        lightmap_uv,
+                        light,
+                    }
+                })
+.collect::<Vec<Corner>>();
+            let sky = bsp_file.textures[miptex].name().eq_ignore_ascii_case("sky");
+            let normal = bsp_file.face_normal(face);
+            let tiles = split_tiles(corners.clone());
+            *polygon = Some(Polygon { miptex, sky, lit: lightmap.is_some(), normal, corners, tiles });
+            outlines.push((model_itr, positions));
+        }
+    }
+    (polygons, outlines)
+}
+
+/// Brush models by the world leaf their middle is in, they get painted when the walk through the
+/// world reaches that leaf. The ones stuck in solid space come last
+fn place_brush_models(bsp_file: &BspFile) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let mut in_leaf = vec![vec![]; bsp_file.leaves.len()];
+    let mut unplaced = vec![];
+    let head_node = bsp_file.models.first().map(|world| world.head_nodes[0]).unwrap_or(-1);
+    for (model_itr, model) in bsp_file.models.iter().enumerate().skip(1) {
+        match bsp_file.leaf_at(head_node, math::lerp(model.mins, model.maxs, 0.5)) {
+            Some(leaf) if leaf != 0 && bsp_file.leaves[leaf].contents != CONTENTS_SOLID => in_leaf[leaf].push(model_itr),
+            _ => unplaced.push(model_itr),
+        }
+    }
+    (in_leaf, unplaced)
+}
+
+/// Software rendered fly-through of a map painted through egui's painter like
+/// `hlmdl::ModelViewport`. There's no depth buffer, so faces are painted back to front in the
+/// order the BSP tree gives, skipping what the visibility lump or the view rules out
+pub struct MapViewport {
+    pub mode: RenderMode,
+    pub cull_back_faces: bool,
+    /// Draws doors, triggers and every other brush entity, not just the world
+    pub show_brush_entities: bool,
+    position: Vec3,
+    yaw: f32,
+    pitch: f32,
+    /// Middle of the top-down view
+    center: [f32; 2],
+    /// Pixels per unit in the top-down view
+    zoom: f32,
+    framed: bool,
+    polygons: Option<Vec<Option<Polygon>>>,
+    outlines: Vec<Outline>,
+    brush_models: (Vec<Vec<usize>>, Vec<usize>),
+    /// For the leaf the camera was last in
+    visibility: Option<(usize, Visibility)>,
+    lightmap: Option<egui::TextureHandle>,
+}
+
+impl Default for MapViewport {
+    fn default() -> Self {
+        Self {
+            mode: RenderMode::Lit,
+            cull_back_faces: true,
+            show_brush_entities: true,
+            position: [0.0; 3],
+            yaw: 0.0,
+            pitch: 0.0,
+            center: [0.0; 2],
+            zoom: 1.0,
+            framed: false,
+            polygons: None,
+            outlines: vec![],
+            brush_models: (vec![], vec![]),
+            visibility: None,
+            lightmap: None,
+        }
+    }
+}
+
+impl MapViewport {
+    /// Starts where the player spawns, or in the middle of the world when there's no spawn point
+    fn frame(&mut self, bsp_file: &BspFile, height: f32) {
+        if let Some(world) = bsp_file.models.first() {
+            let middle = math::lerp(world.mins, world.maxs, 0.5);
+            self.position = middle;
+            self.center = [middle[0], middle[1]];
+            let extent = (world.maxs[0] - world.mins[0]).max(world.maxs[1] - world.mins[1]).max(1.0);
+            self.zoom = height / extent;
+        }
+        self.yaw = 0.0;
+        self.pitch = 0.0;
+        let start = bsp_file.parse_entities().unwrap_or_default().into_iter()
+            .find(|entity| matches!(entity.classname(), "info_player_start" | "info_player_deathmatch"));
+        if let Some(start) = start {
+            if let Some(origin) = start.get_vec3("origin") {
+                self.position = math::add(origin, [0.0, 0.0, EYE_HEIGHT]);
+            }
+            let yaw = start.get_vec3("angles").map(|angles| angles[1])
+                .or_else(|| start.get("angle").and_then(|angle| angle.trim().parse().ok()));
+            if let Some(yaw) = yaw {
+                self.yaw = yaw.to_radians();
+            }
+        }
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, RenderMode::Textured, "Textured");
+            ui.radio_value(&mut self.mode, RenderMode::Lit, "Lit");
+            ui.radio_value(&mut self.mode, RenderMode::Lightmap, "Lightmap");
+            ui.radio_value(&mut self.mode, RenderMode::Wireframe, "Wireframe");
+            ui.radio_value(&mut self.mode, RenderMode::TopDown, "Top down");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.cull_back_faces, "Cull back faces");
+            ui.checkbox(&mut self.show_brush_entities, "Brush entities");
+            if ui.button("Reset view").clicked() {
+                self.framed = false;
+            }
+            if self.mode == RenderMode::TopDown {
+                ui.label("Drag to pan, scroll to zoom");
+            } else {
+                ui.label("Drag to look, WASD to fly, E and Q for up and down, shift to hurry");
+            }
+        });
+    }
+
+    /// Draws the map, `textures` line up with `BspFile::textures` and are `None` for the ones
+    /// that live in WADs
+    pub fn ui(&mut self, ui: &mut egui::Ui, bsp_file: &BspFile, textures: &[Option<egui::TextureHandle>]) {
+        let size = egui::vec2(ui.available_width().max(256.0), 400.0);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::drag());
+
+        if self.polygons.is_none() {
+            let atlas = bsp_file.lightmap_atlas(ATLAS_WIDTH);
+            self.lightmap = Some(ui.ctx().load_texture(
+                "bsp_lightmap",
+                egui::ColorImage::from_rgb([atlas.width, atlas.height], &atlas.pixels),
+                Default::default()));
+            let (polygons, outlines) = build(bsp_file, &atlas);
+            self.polygons = Some(polygons);
+            self.outlines = outlines;
+            self.brush_models = place_brush_models(bsp_file);
+        }
+        if !self.framed {
+            self.frame(bsp_file, rect.height());
+            self.framed = true;
+        }
+        if self.mode == RenderMode::TopDown {
+            self.top_down_ui(ui, bsp_file, rect, &response);
+            return
+        }
+
+        let drag = response.drag_delta();
+        self.yaw -= drag.x * 0.005;
+        self.pitch = (self.pitch - drag.y * 0.005).clamp(-1.5, 1.5);
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let forward = [cos_pitch * cos_yaw, cos_pitch * sin_yaw, sin_pitch];
+        let right = math::normalize(math::cross(forward, [0.0, 0.0, 1.0]));
+        let up = math::cross(right, forward);
+        if response.hovered() {
+            let (keys, dt, fast, scroll) = ui.input(|i| (
+                [egui::Key::W, egui::Key::S, egui::Key::D, egui::Key::A, egui::Key::E, egui::Key::Q].map(|key| i.key_down(key)),
+                i.stable_dt.min(0.1),
+                i.modifiers.shift,
+                i.scroll_delta.y));
+            let directions = [forward, math::scale(forward, -1.0), right, math::scale(right, -1.0), [0.0, 0.0, 1.0], [0.0, 0.0, -1.0]];
+            let mut velocity = [0.0; 3];
+            for (down, direction) in keys.iter().zip(directions) {
+                if *down {
+                    velocity = math::add(velocity, direction);
+                }
+            }
+            let speed = if fast { FLY_SPEED * 3.0 } else { FLY_SPEED };
+            self.position = math::add(self.position, math::scale(velocity, speed * dt));
+            self.position = math::add(self.position, math::scale(forward, scroll * 0.5));
+            if keys.iter().any(|down| *down) {
+                ui.ctx().request_repaint();
+            }
+        }
+
+        let eye = self.position;
+        let focal = rect.height() * 0.5 / (FOV_DEGREES.to_radians() * 0.5).tan();
+        let center = rect.center();
+        let depth = |position: Vec3| math::dot(math::sub(position, eye), forward);
+        let project = |position: Vec3| -> egui::Pos2 {
+            let relative = math::sub(position, eye);
+            let depth = math::dot(relative, forward);
+            egui::pos2(
+                center.x + math::dot(relative, right) / depth * focal,
+                center.y - math::dot(relative, up) / depth * focal)
+        };
+        let visible_model = |model: usize| model == 0 || self.show_brush_entities;
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(32));
+        if self.mode == RenderMode::Wireframe {
+            let stroke = egui::Stroke::new(1.0, egui::Color32::from_gray(200));
+            let mut shapes = vec![];
+            for (model, outline) in self.outlines.iter() {
+                if !visible_model(*model) {
+                    continue;
+                }
+                let corners: Vec<Corner> = outline.iter().map(|position| Corner { position: *position, ..Default::default() }).collect();
+                let clipped = clip(&corners, |corner| depth(corner.position) - NEAR);
+                if clipped.len() >= 2 {
+                    shapes.push(egui::Shape::closed_line(clipped.iter().map(|corner| project(corner.position)).collect(), stroke));
+                }
+            }
+            painter.extend(shapes);
+            return
+        }
+
+        let aspect = rect.width() / rect.height();
+        let half_height = (FOV_DEGREES.to_radians() * 0.5).tan();
+        // planes through the eye along the edges of the view, facing out
+        let frustum = [
+            math::sub(right, math::scale(forward, half_height * aspect)),
+            math::sub(math::scale(right, -1.0), math::scale(forward, half_height * aspect)),
+            math::sub(up, math::scale(forward, half_height)),
+            math::sub(math::scale(up, -1.0), math::scale(forward, half_height)),
+        ];
+        let outside = |node: usize| {
+            let node = &bsp_file.nodes[node];
+            frustum.iter().any(|normal| (0..8).all(|corner: usize| {
+                let position = [0, 1, 2].map(|axis| (if corner & (1 << axis) != 0 { node.maxs[axis] } else { node.mins[axis] }) as f32);
+                math::dot(math::sub(position, eye), *normal) > 0.0
+            }))
+        };
+        let order = self.paint_order(bsp_file, eye, &outside);
+
+        // one egui mesh per run of polygons sharing a texture
+        let polygons = self.polygons.as_ref().unwrap();
+        let mut shapes = vec![];
+        let mut current: Option<(Option<egui::TextureId>, egui::Mesh)> = None;
+        for face in order {
+            let polygon = match &polygons[face] {
+                Some(polygon) => polygon,
+                None => continue,
+            };
+            if self.cull_back_faces && math::dot(polygon.normal, math::sub(polygon.corners[0].position, eye)) >= 0.0 {
+                continue;
+            }
+            let texture = match self.mode {
+                RenderMode::Lightmap if polygon.lit => self.lightmap.as_ref().map(|lightmap| lightmap.id()),
+                RenderMode::Textured | RenderMode::Lit if !polygon.sky => textures.get(polygon.miptex).and_then(|texture| texture.as_ref()).map(|texture| texture.id()),
+                _ => None,
+            };
+            // only a repeating texture needs the face cut into tiles
+            let pieces = if texture.is_some() && self.mode != RenderMode::Lightmap {
+                &polygon.tiles[..]
+            } else {
+                std::slice::from_ref(&polygon.corners)
+            };
+            for corners in pieces {
+                let clipped = clip(corners, |corner| depth(corner.position) - NEAR);
+                if clipped.len() < 3 {
+                    continue;
+                }
+                if !matches!(&current, Some((current, _)) if *current == texture) {
+                    if let Some((_, mesh)) = current.take() {
+                        shapes.push(egui::Shape::mesh(mesh));
+                    }
+                    current = Some((texture, egui::Mesh::with_texture(texture.unwrap_or_default())));
+                }
+                let (_, mesh) = current.as_mut().unwrap();
+                let first = mesh.vertices.len() as u32;
+                for itr in 1..(clipped.len() as u32 - 1) {
+                    mesh.indices.extend([first, first + itr, first + itr + 1]);
+                }
+                mesh.vertices.extend(clipped.iter().map(|corner| {
+                    // textures from WADs that aren't here are drawn grey
+                    let shade = if texture.is_none() && self.mode != RenderMode::Lightmap { 0.5 } else { 1.0 };
+                    let light = corner.light.map(|light| (light * shade).min(255.0) as u8);
+                    let color = match self.mode {
+                        _ if polygon.sky => egui::Color32::from_rgb(96, 160, 224),
+                        RenderMode::Textured => egui::Color32::from_gray((255.0 * shade) as u8),
+                        RenderMode::Lightmap if texture.is_some() => egui::Color32::WHITE,
+                        _ => egui::Color32::from_rgb(light[0], light[1], light[2]),
+                    };
+                    let uv = match (texture, self.mode) {
+                        (None, _) => egui::epaint::WHITE_UV,
+                        (Some(_), RenderMode::Lightmap) => corner.lightmap_uv,
+                        (Some(_), _) => corner.uv,
+                    };
+                    egui::epaint::Vertex { pos: project(corner.position), uv, color }
+                }));
+            }
+        }
+        if let Some((_, mesh)) = current {
+            shapes.push(egui::Shape::mesh(mesh));
+        }
+        painter.extend(shapes);
+    }
+
+    /// Faces back to front from `eye`, walking the world's tree and slotting brush models in as
+    /// their leaf comes up. Nodes `outside` the view are skipped along with everything under them
+    fn paint_order(&mut self, bsp_file: &BspFile, eye: Vec3, outside: &dyn Fn(usize) -> bool) -> Vec<usize> {
+        let head_node = match bsp_file.models.first() {
+            Some(world) => world.head_nodes[0],
+            None => return vec![],
+        };
+        let leaf = match bsp_file.leaf_at(head_node, eye) {
+            Some(leaf) => leaf,
+            // no tree to walk, faces go in the order they're stored
+            None => {
+                let models = if self.show_brush_entities { bsp_file.models.len() } else { 1 };
+                return bsp_file.models[..models].iter()
+                    .flat_map(|model| (model.first_face as usize)..(model.first_face as usize + model.num_faces as usize))
+                    .collect()
+            },
+        };
+        if !matches!(&self.visibility, Some((cached, _)) if *cached == leaf) {
+            self.visibility = Some((leaf, bsp_file.visibility(leaf)));
+        }
+        let visibility = &self.visibility.as_ref().unwrap().1;
+        let (in_leaf, unplaced) = &self.brush_models;
+        let show_brush_entities = self.show_brush_entities;
+        let walk_model = |model: usize, order: &mut Vec<usize>| {
+            bsp_file.walk_back_to_front(bsp_file.models[model].head_nodes[0], eye, &|node| !outside(node), &mut |visit| {
+                if let Visit::Face(face) = visit {
+                    order.push(face);
+                }
+            });
+        };
+
+        let mut order = vec![];
+        bsp_file.walk_back_to_front(head_node, eye, &|node| visibility.nodes[node] && !outside(node), &mut |visit| match visit {
+            Visit::Face(face) if visibility.faces[face] => order.push(face),
+            Visit::Leaf(leaf) if show_brush_entities && visibility.leaves[leaf] => {
+                for model in in_leaf[leaf].iter() {
+                    walk_model(*model, &mut order);
+                }
+            },
+            _ => {},
+        });
+        if show_brush_entities {
+            for model in unplaced.iter() {
+                walk_model(*model, &mut order);
+            }
+        }
+        order
+    }
+
+    /// Every face outline from above, brighter the higher up it is, with the camera marked
+    fn top_down_ui(&mut self, ui: &mut egui::Ui, bsp_file: &BspFile, rect: egui::Rect, response: &egui::Response) {
+        let drag = response.drag_delta();
+        self.center[0] -= drag.x / self.zoom;
+        self.center[1] += drag.y / self.zoom;
+        if response.hovered() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            self.zoom = (self.zoom * (1.0 + scroll * 0.001)).clamp(0.001, 100.0);
+        }
+        let (zoom, center, screen_center) = (self.zoom, self.center, rect.center());
+        let to_screen = |position: Vec3| egui::pos2(
+            screen_center.x + (position[0] - center[0]) * zoom,
+            screen_center.y - (position[1] - center[1]) * zoom);
+        let (bottom, top) = bsp_file.models.first().map(|world| (world.mins[2], world.maxs[2])).unwrap_or((0.0, 1.0));
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(16));
+        let mut shapes = vec![];
+        for (model, outline) in self.outlines.iter() {
+            if *model != 0 && !self.show_brush_entities {
+                continue;
+            }
+            let height = outline.iter().map(|position| position[2]).sum::<f32>() / outline.len() as f32;
+            let shade = 64.0 + 191.0 * ((height - bottom) / (top - bottom).max(1.0)).clamp(0.0, 1.0);
+            let color = if *model == 0 { egui::Color32::from_gray(shade as u8) } else { egui::Color32::from_rgb(shade as u8, (shade * 0.75) as u8, 0) };
+            shapes.push(egui::Shape::closed_line(outline.iter().map(|position| to_screen(*position)).collect(), egui::Stroke::new(1.0, color)));
+        }
+        let camera = to_screen(self.position);
+        let facing = camera + egui::vec2(self.yaw.cos(), -self.yaw.sin()) * 12.0;
+        shapes.push(egui::Shape::circle_stroke(camera, 4.0, egui::Stroke::new(1.5, egui::Color32::RED)));
+        shapes.push(egui::Shape::line_segment([camera, facing], egui::Stroke::new(1.5, egui::Color32::RED)));
+        painter.extend(shapes);
+    }
+}
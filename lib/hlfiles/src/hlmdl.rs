@@ -7,13 +7,114 @@ use std::fmt;
 use std::io::Cursor;
 use std::mem;
 use bytebuffer::ByteBuffer;
+use crate::file_dialog::FileDialog;
 
+mod anim;
+pub use anim::{blend_poses, decode_anim_values, Animation, BoneTransform};
+mod geometry;
+pub use geometry::{BodyPart, Mesh, MeshGeometry, MeshVertex, Model, TriangleCommand, TriangleVertex};
+mod viewport;
+pub use viewport::{ModelViewport, RenderMode};
+mod playback;
+pub use playback::Playback;
+mod companion;
+pub use companion::{Companion, SEQUENCE_GROUP_HEADER_SIZE};
+mod writer;
+mod export;
+mod decompile;
+pub use decompile::{activity_name, texture_bitmap, ACTIVITIES};
+mod smd;
+pub use smd::{Smd, SmdNode, SmdTriangle, SmdVertex};
+mod compile;
+mod validate;
+pub use validate::{limits, Issue, Severity};
+
+#[derive(Debug)]
+pub enum MdlError {
+    BadMagic([u8; 4]),
+    /// Ran out of bytes while reading something
+    UnexpectedEof,
+    /// A count or offset in the file points outside of it
+    OutOfBounds { what: &'static str, offset: i64, size: i64, file_size: usize },
+    /// A count in the file is negative or absurd
+    BadCount { what: &'static str, count: i64 },
+    /// The sequence's animation lives in a `model01.mdl` style file that isn't loaded
+    MissingSequenceGroup { group: usize, name: String },
+    /// Writing an export format failed, e.g. encoding a PNG
+    Export(String),
+    /// A QC or one of the files it uses couldn't be compiled
+    Compile(String),
+    /// A skin ref shows the texture being resized in one skin family and a different texture in
+    /// another, so its texture coordinates can't be rescaled to suit both
+    SkinConflict { skin_ref: usize, texture: usize },
+    /// Rescaling a texture coordinate to the new texture size runs out of 16 bits
+    TexCoordOutOfRange { texture: usize, s: i16, t: i16 },
+}
+
+impl fmt::Display for MdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdlError::BadMagic(magic) => write!(f, "bad magic {:?}, not a Half-Life model", String::from_utf8_lossy(magic)),
+            MdlError::UnexpectedEof => write!(f, "the file ends in the middle of a structure"),
+            MdlError::OutOfBounds { what, offset, size, file_size } => write!(f, "{} ({} bytes at offset {}) is outside of the {} byte file", what, size, offset, file_size),
+            MdlError::BadCount { what, count } => write!(f, "{} has an invalid count of {}", what, count),
+            MdlError::MissingSequenceGroup { group, name } => write!(f, "sequence group {} ({}) is not loaded", group, name),
+            MdlError::Export(err) => write!(f, "export failed: {}", err),
+            MdlError::Compile(err) => write!(f, "compile failed: {}", err),
+            MdlError::SkinConflict { skin_ref, texture } => write!(f, "skin ref {} switches between texture {} and another texture across skin families, resize them together", skin_ref, texture),
+            MdlError::TexCoordOutOfRange { texture, s, t } => write!(f, "texture {} is too big, coordinate ({}, {}) wouldn't fit in 16 bits at that size", texture, s, t),
+        }
+    }
+}
+
+impl std::error::Error for MdlError {}
+
+impl From<std::io::Error> for MdlError {
+    fn from(_: std::io::Error) -> Self {
+        MdlError::UnexpectedEof
+    }
+}
+
+/// Makes sure `count` items of `item_size` bytes at `offset` fit inside a buffer of `file_size` bytes
+fn check_bounds(what: &'static str, offset: i32, count: i32, item_size: usize, file_size: usize) -> Result<(), MdlError> {
+    if count < 0 {
+        return Err(MdlError::BadCount { what, count: count as i64 })
+    }
+    let size = count as i64 * item_size as i64;
+    if offset < 0 || offset as i64 + size > file_size as i64 {
+        return Err(MdlError::OutOfBounds { what, offset: offset as i64, size, file_size })
+    }
+    Ok(())
+}
+
+pub use crate::math::Vec3;
+
+fn read_vec3(reader: &mut ByteBuffer) -> Result<Vec3, MdlError> {
+    Ok([reader.read_f32()?, reader.read_f32()?, reader.read_f32()?])
+}
+
+/// Null terminated name bytes as a string
+pub fn name_str(name: &[u8]) -> String {
+    let len = name.iter().position(|byte| *byte == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).to_string()
+}
+
+pub const MDL_HEADER_SIZE: usize = 244;
+
+/// `studiohdr_t`
 #[derive(Clone, Copy)]
 pub struct MdlHeader {
     pub id: i32,
     pub version: i32,
     pub name: [u8; 64],
     pub data_length: i32,
+
+    pub eye_position: Vec3,
+    pub min: Vec3,
+    pub max: Vec3,
+    pub bbmin: Vec3,
+    pub bbmax: Vec3,
+
     pub flags: i32,
     pub num_bones: i32,
     pub bone_index: i32,
@@ -32,110 +133,90 @@ pub struct MdlHeader {
     pub num_skin_ref: i32,
     pub num_skin_families: i32,
     pub skin_index: i32,
+
+    pub num_body_parts: i32,
+    pub body_part_index: i32,
+    pub num_attachments: i32,
+    pub attachment_index: i32,
+    pub sound_table: i32,
+    pub sound_index: i32,
+    pub sound_groups: i32,
+    pub sound_group_index: i32,
+    pub num_transitions: i32,
+    pub transition_index: i32,
 }
 
 impl MdlHeader {
-    pub fn from_bytes(buf: &[u8]) -> Self {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, MdlError> {
         let mut reader = ByteBuffer::from(buf);
+        Self::from_reader(&mut reader)
+    }
+
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
         reader.set_endian(bytebuffer::Endian::LittleEndian);
-        let id = reader.read_i32().expect("Failed to get value");
-        let version = reader.read_i32().expect("Failed to get value");
-        let name = reader.read_bytes(64).expect("Failed to get value").try_into().expect("failed to convert value");
-        let data_length = reader.read_i32().expect("Failed to get value");
+        let id = reader.read_i32()?;
+        let version = reader.read_i32()?;
+        let name = reader.read_bytes(64)?.try_into().unwrap();
+        let data_length = reader.read_i32()?;
 
-        let _ = reader.read_bytes(60);
+        let eye_position = read_vec3(reader)?;
+        let min = read_vec3(reader)?;
+        let max = read_vec3(reader)?;
+        let bbmin = read_vec3(reader)?;
+        let bbmax = read_vec3(reader)?;
 
-        let flags = reader.read_i32().expect("Failed to get value");
+        let flags = reader.read_i32()?;
 
-        let num_bones = reader.read_i32().expect("Failed to get value");
-        let bone_index = reader.read_i32().expect("Failed to get value");
+        let num_bones = reader.read_i32()?;
+        let bone_index = reader.read_i32()?;
 
-        let num_bone_controllers = reader.read_i32().expect("Failed to get value");
-        let bone_controller_index = reader.read_i32().expect("Failed to get value");
+        let num_bone_controllers = reader.read_i32()?;
+        let bone_controller_index = reader.read_i32()?;
 
-        let num_hit_boxes = reader.read_i32().expect("Failed to get value");
-        let hit_box_index = reader.read_i32().expect("Failed to get value");
+        let num_hit_boxes = reader.read_i32()?;
+        let hit_box_index = reader.read_i32()?;
         
-        let num_seq = reader.read_i32().expect("Failed to get value");
-        let seq_index = reader.read_i32().expect("Failed to get value");
-
-        let num_seq_groups = reader.read_i32().expect("Failed to get value");
-        let seq_group_index = reader.read_i32().expect("Failed to get value");
-
-        let num_textures = reader.read_i32().expect("Failed to get value");
-        let texture_index = reader.read_i32().expect("Failed to get value");
-        let texture_data_index = reader.read_i32().expect("Failed to get value");
-
-        let num_skin_ref = reader.read_i32().expect("Failed to get value");
-        let num_skin_families = reader.read_i32().expect("Failed to get value");
-        let skin_index = reader.read_i32().expect("Failed to get value");
-
-        Self {
-            id,
-            version,
-            name,
-            data_length,
-            flags,
-
-            num_bones,
-            bone_index,
-            num_bone_controllers,
-            bone_controller_index,
-            num_hit_boxes,
-            hit_box_index,
-            num_seq,
-            seq_index,
-            num_seq_groups,
-            seq_group_index,
-            num_textures,
-            texture_index,
-            texture_data_index,
-            num_skin_ref,
-            num_skin_families,
-            skin_index,
-        }
-    }
-    pub fn from_reader(reader: &mut ByteBuffer) -> Self {
-        reader.set_endian(bytebuffer::Endian::LittleEndian);
-        let id = reader.read_i32().expect("Failed to get value");
-        let version = reader.read_i32().expect("Failed to get value");
-        let name = reader.read_bytes(64).expect("Failed to get value").try_into().expect("failed to convert value");
-        let data_length = reader.read_i32().expect("Failed to get value");
+        let num_seq = reader.read_i32()?;
+        let seq_index = reader.read_i32()?;
 
-        let _ = reader.read_bytes(60);
+        let num_seq_groups = reader.read_i32()?;
+        let seq_group_index = reader.read_i32()?;
 
-        let flags = reader.read_i32().expect("Failed to get value");
+        let num_textures = reader.read_i32()?;
+        let texture_index = reader.read_i32()?;
+        let texture_data_index = reader.read_i32()?;
 
-        let num_bones = reader.read_i32().expect("Failed to get value");
-        let bone_index = reader.read_i32().expect("Failed to get value");
+        let num_skin_ref = reader.read_i32()?;
+        let num_skin_families = reader.read_i32()?;
+        let skin_index = reader.read_i32()?;
 
-        let num_bone_controllers = reader.read_i32().expect("Failed to get value");
-        let bone_controller_index = reader.read_i32().expect("Failed to get value");
+        let num_body_parts = reader.read_i32()?;
+        let body_part_index = reader.read_i32()?;
 
-        let num_hit_boxes = reader.read_i32().expect("Failed to get value");
-        let hit_box_index = reader.read_i32().expect("Failed to get value");
-        
-        let num_seq = reader.read_i32().expect("Failed to get value");
-        let seq_index = reader.read_i32().expect("Failed to get value");
-
-        let num_seq_groups = reader.read_i32().expect("Failed to get value");
-        let seq_group_index = reader.read_i32().expect("Failed to get value");
+        let num_attachments = reader.read_i32()?;
+        let attachment_index = reader.read_i32()?;
 
-        let num_textures = reader.read_i32().expect("Failed to get value");
-        let texture_index = reader.read_i32().expect("Failed to get value");
-        let texture_data_index = reader.read_i32().expect("Failed to get value");
+        let sound_table = reader.read_i32()?;
+        let sound_index = reader.read_i32()?;
+        let sound_groups = reader.read_i32()?;
+        let sound_group_index = reader.read_i32()?;
 
-        let num_skin_ref = reader.read_i32().expect("Failed to get value");
-        let num_skin_families = reader.read_i32().expect("Failed to get value");
-        let skin_index = reader.read_i32().expect("Failed to get value");
+        let num_transitions = reader.read_i32()?;
+        let transition_index = reader.read_i32()?;
 
-        Self {
+        Ok(Self {
             id,
             version,
             name,
             data_length,
-            flags,
 
+            eye_position,
+            min,
+            max,
+            bbmin,
+            bbmax,
+
+            flags,
             num_bones,
             bone_index,
             num_bone_controllers,
@@ -146,40 +227,67 @@ impl MdlHeader {
             seq_index,
             num_seq_groups,
             seq_group_index,
+
             num_textures,
             texture_index,
             texture_data_index,
             num_skin_ref,
             num_skin_families,
             skin_index,
-        }
+
+            num_body_parts,
+            body_part_index,
+            num_attachments,
+            attachment_index,
+            sound_table,
+            sound_index,
+            sound_groups,
+            sound_group_index,
+            num_transitions,
+            transition_index,
+        })
     }
 }
 
 impl fmt::Debug for MdlHeader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Header")
-         .field("\n\t id", &std::str::from_utf8(&self.id.to_le_bytes()).unwrap())
-         .field("\n\t version", &self.version)
-         .field("\n\t name", &std::str::from_utf8(&self.name).unwrap())
-         .field("\n\t data_length", &self.data_length)
-         .field("\n\t flags", &self.flags)
-         .field("\n\t num_bones", &self.num_bones)
-         .field("\n\t bones_index", &self.bone_index)
-         .field("\n\t num_bone_controllers", &self.num_bone_controllers)
-         .field("\n\t bone_controller_index", &self.bone_controller_index)
-         .field("\n\t num_hit_boxes", &self.num_hit_boxes)
-         .field("\n\t hit_box_index", &self.hit_box_index)
-         .field("\n\t num_seq", &self.num_seq)
-         .field("\n\t seq_index", &self.seq_index)
-         .field("\n\t num_seq_groups", &self.num_seq_groups)
-         .field("\n\t seq_group_index", &self.seq_group_index)
-         .field("\n\t num_textures", &self.num_textures)
-         .field("\n\t texture_index", &self.texture_index)
-         .field("\n\t texture_data_index", &self.texture_data_index)
-         .field("\n\t num_skin_ref", &self.num_skin_ref)
-         .field("\n\t num_skin_families", &self.num_seq_groups)
-         .field("\n\t skin_index", &self.skin_index)
+        f.debug_struct("MdlHeader")
+         .field("id", &String::from_utf8_lossy(&self.id.to_le_bytes()))
+         .field("version", &self.version)
+         .field("name", &name_str(&self.name))
+         .field("data_length", &self.data_length)
+         .field("eye_position", &self.eye_position)
+         .field("min", &self.min)
+         .field("max", &self.max)
+         .field("bbmin", &self.bbmin)
+         .field("bbmax", &self.bbmax)
+         .field("flags", &self.flags)
+         .field("num_bones", &self.num_bones)
+         .field("bone_index", &self.bone_index)
+         .field("num_bone_controllers", &self.num_bone_controllers)
+         .field("bone_controller_index", &self.bone_controller_index)
+         .field("num_hit_boxes", &self.num_hit_boxes)
+         .field("hit_box_index", &self.hit_box_index)
+         .field("num_seq", &self.num_seq)
+         .field("seq_index", &self.seq_index)
+         .field("num_seq_groups", &self.num_seq_groups)
+         .field("seq_group_index", &self.seq_group_index)
+         .field("num_textures", &self.num_textures)
+         .field("texture_index", &self.texture_index)
+         .field("texture_data_index", &self.texture_data_index)
+         .field("num_skin_ref", &self.num_skin_ref)
+         .field("num_skin_families", &self.num_skin_families)
+         .field("skin_index", &self.skin_index)
+         .field("num_body_parts", &self.num_body_parts)
+         .field("body_part_index", &self.body_part_index)
+         .field("num_attachments", &self.num_attachments)
+         .field("attachment_index", &self.attachment_index)
+         .field("sound_table", &self.sound_table)
+         .field("sound_index", &self.sound_index)
+         .field("sound_groups", &self.sound_groups)
+         .field("sound_group_index", &self.sound_group_index)
+         .field("num_transitions", &self.num_transitions)
+         .field("transition_index", &self.transition_index)
          .finish()
     }
 }
@@ -192,8 +300,71 @@ pub struct ColorRGB {
 }
 
 impl ColorRGB {
-    pub fn new(r: u8, b: u8, g: u8) -> Self {
-        Self { r, b, g }
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// `STUDIO_NF_*` bits of a texture
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextureFlags(pub u32);
+
+impl TextureFlags {
+    pub const FLATSHADE: Self = Self(0x0001);
+    pub const CHROME: Self = Self(0x0002);
+    pub const FULLBRIGHT: Self = Self(0x0004);
+    pub const NOMIPS: Self = Self(0x0008);
+    pub const ALPHA: Self = Self(0x0010);
+    pub const ADDITIVE: Self = Self(0x0020);
+    /// Palette index 255 is see through
+    pub const MASKED: Self = Self(0x0040);
+
+    pub const ALL: [(Self, &'static str); 7] = [
+        (Self::FLATSHADE, "flatshade"),
+        (Self::CHROME, "chrome"),
+        (Self::FULLBRIGHT, "fullbright"),
+        (Self::NOMIPS, "nomips"),
+        (Self::ALPHA, "alpha"),
+        (Self::ADDITIVE, "additive"),
+        (Self::MASKED, "masked"),
+    ];
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// Names of the set bits, e.g. "chrome | masked"
+    pub fn names(&self) -> String {
+        let mut names: Vec<String> = Self::ALL.iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let unknown = self.0 & !Self::ALL.iter().fold(0, |bits, (flag, _)| bits | flag.0);
+        if unknown != 0 {
+            names.push(format!("{:#x}", unknown));
+        }
+        if names.is_empty() {
+            return String::from("none")
+        }
+        names.join(" | ")
+    }
+}
+
+impl fmt::Debug for TextureFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TextureFlags({})", self.names())
     }
 }
 
@@ -202,28 +373,28 @@ const TEXTURE_HEADER_SIZE: usize = 80;
 #[derive(Clone)]
 pub struct TextureHeader {
     pub name: [u8; 64],
-    pub flags: u32,
+    pub flags: TextureFlags,
     pub width: u32,
     pub height: u32,
     pub index: u32,
 }
 
 impl TextureHeader {
-    pub fn from_reader(reader: &mut ByteBuffer) -> Self {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
         reader.set_endian(bytebuffer::Endian::LittleEndian);
-        let name = reader.read_bytes(64).expect("failed to get array");
-        let flags = reader.read_u32().expect("Failed to get u32");
-        let width = reader.read_u32().expect("Failed to get u32");
-        let height = reader.read_u32().expect("Failed to get u32");
-        let index = reader.read_u32().expect("Failed to get u32");
+        let name = reader.read_bytes(64)?;
+        let flags = TextureFlags(reader.read_u32()?);
+        let width = reader.read_u32()?;
+        let height = reader.read_u32()?;
+        let index = reader.read_u32()?;
 
-        Self {
+        Ok(Self {
             name: name.try_into().unwrap(),
             flags,
             width,
             height,
             index,
-        }
+        })
     }
 }
 
@@ -248,29 +419,87 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn from_reader(reader: &mut ByteBuffer, header: TextureHeader) -> Self {
+    pub fn from_reader(reader: &mut ByteBuffer, header: TextureHeader) -> Result<Self, MdlError> {
         reader.set_endian(bytebuffer::Endian::LittleEndian);
-        let img_size: usize = (header.width * header.height) as usize;
-        let raw_data = reader.read_bytes(img_size).expect("Failed to get array").try_into().expect("Failed to convert array");
+        let img_size: usize = (header.width as usize).checked_mul(header.height as usize)
+            .ok_or(MdlError::BadCount { what: "texture size", count: header.width as i64 * header.height as i64 })?;
+        let raw_data = reader.read_bytes(img_size)?;
 
-        let palette_raw = reader.read_bytes(256*3).unwrap();
-        let mut palette_reader = ByteBuffer::from(palette_raw);
-        palette_reader.set_endian(bytebuffer::Endian::LittleEndian);
-        let mut palette = vec![ColorRGB::new(0, 0, 0); 256];
-        for itr in 0..256 {
-            let r = palette_reader.read_u8().expect("Failed to get value");
-            let g = palette_reader.read_u8().expect("Failed to get value");
-            let b = palette_reader.read_u8().expect("Failed to get value");
-            palette[itr] = ColorRGB::new(r, g, b);
+        let palette_raw = reader.read_bytes(256*3)?;
+        let mut palette = [ColorRGB::new(0, 0, 0); 256];
+        for (itr, color) in palette.iter_mut().enumerate() {
+            let r = palette_raw[itr * 3];
+            let g = palette_raw[itr * 3 + 1];
+            let b = palette_raw[itr * 3 + 2];
+            *color = ColorRGB::new(r, g, b);
         }
 
-        Self {
+        Ok(Self {
             raw_data,
-            palette: palette.try_into().unwrap(),
+            palette,
             header,
-        }
+        })
     }
     
+    /// Quantizes an image down to 256 colors, keeping the name and flags of `header`
+    pub fn from_image(image: image::RgbImage, header: &TextureHeader) -> Self {
+        let (width, height) = image.dimensions();
+        let (palette_vec, raw_data) = crate::hlwad::Texture::quantize_images(vec![image]);
+        let mut palette = [ColorRGB::new(0, 0, 0); 256];
+        for (color, quantized) in palette.iter_mut().zip(palette_vec.iter()) {
+            *color = ColorRGB::new(quantized.r, quantized.g, quantized.b);
+        }
+        Self {
+            raw_data,
+            palette,
+            header: TextureHeader {
+                width,
+                height,
+                ..header.clone()
+            },
+        }
+    }
+
+    /// The palette the way it is laid out on disk
+    pub fn palette_bytes(&self) -> Vec<u8> {
+        self.palette.iter().flat_map(|color| [color.r, color.g, color.b]).collect()
+    }
+
+    /// With masked textures see through at index 255
+    pub fn to_rgba_image(&self) -> image::RgbaImage {
+        let masked = self.header.flags.contains(TextureFlags::MASKED);
+        let pixels = self.raw_data.iter()
+            .flat_map(|index| {
+                let color = self.palette[*index as usize];
+                let alpha = if masked && *index == 255 { 0 } else { 255 };
+                [color.r, color.g, color.b, alpha]
+            })
+            .collect();
+        image::RgbaImage::from_vec(self.header.width, self.header.height, pixels)
+            .expect("texture data doesn't match its size")
+    }
+
+    /// For egui, with masked textures see through at index 255 and additive ones left with no
+    /// alpha so they add onto whatever is behind them
+    pub fn to_color_image(&self) -> egui::ColorImage {
+        let size = [self.header.width as usize, self.header.height as usize];
+        let masked = self.header.flags.contains(TextureFlags::MASKED);
+        let additive = self.header.flags.contains(TextureFlags::ADDITIVE);
+        let pixels = self.raw_data.iter()
+            .map(|index| {
+                let color = self.palette[*index as usize];
+                if masked && *index == 255 {
+                    egui::Color32::TRANSPARENT
+                } else if additive {
+                    egui::Color32::from_rgba_premultiplied(color.r, color.g, color.b, 0)
+                } else {
+                    egui::Color32::from_rgb(color.r, color.g, color.b)
+                }
+            })
+            .collect();
+        egui::ColorImage { size, pixels }
+    }
+
     pub fn to_rgb_image_vec(&self) -> Vec<u8> {
         let image_size: usize = (self.header.width * self.header.height) as usize;
 
@@ -280,9 +509,9 @@ impl Texture {
         for itr in 0..image_size {
             let palette_index = self.raw_data[itr] as usize;
             let color = self.palette[palette_index];
-            vec[image_offset + 0] = color.r;
-            vec[image_offset + 2] = color.g;
-            vec[image_offset + 1] = color.b;
+            vec[image_offset] = color.r;
+            vec[image_offset + 1] = color.g;
+            vec[image_offset + 2] = color.b;
             image_offset += 3;
         }
 
@@ -306,59 +535,597 @@ impl Texture {
     */
 }
 
-const MDL_HEADER_SIZE:usize  = 1024;
+pub const BONE_SIZE: usize = 112;
+
+/// `mstudiobone_t`
+#[derive(Clone, Copy)]
+pub struct Bone {
+    pub name: [u8; 32],
+    pub parent: i32,
+    pub flags: i32,
+    /// Controller index per position/rotation axis, -1 when there is none
+    pub bone_controller: [i32; 6],
+    /// Default position xyz then rotation xyz
+    pub value: [f32; 6],
+    /// How much one unit of compressed animation moves each axis
+    pub scale: [f32; 6],
+}
+
+impl Bone {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        let name = reader.read_bytes(32)?.try_into().unwrap();
+        let parent = reader.read_i32()?;
+        let flags = reader.read_i32()?;
+        let mut bone_controller = [0; 6];
+        for controller in bone_controller.iter_mut() {
+            *controller = reader.read_i32()?;
+        }
+        let mut value = [0.0; 6];
+        for value in value.iter_mut() {
+            *value = reader.read_f32()?;
+        }
+        let mut scale = [0.0; 6];
+        for scale in scale.iter_mut() {
+            *scale = reader.read_f32()?;
+        }
+        Ok(Self {
+            name,
+            parent,
+            flags,
+            bone_controller,
+            value,
+            scale,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        name_str(&self.name)
+    }
+}
+
+impl fmt::Debug for Bone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bone")
+         .field("name", &self.name())
+         .field("parent", &self.parent)
+         .field("flags", &self.flags)
+         .field("bone_controller", &self.bone_controller)
+         .field("value", &self.value)
+         .field("scale", &self.scale)
+         .finish()
+    }
+}
+
+pub const BONE_CONTROLLER_SIZE: usize = 24;
+
+/// Motion type bits shared by bone controllers, sequences and blends
+pub mod motion {
+    pub const X: i32 = 0x0001;
+    pub const Y: i32 = 0x0002;
+    pub const Z: i32 = 0x0004;
+    pub const XR: i32 = 0x0008;
+    pub const YR: i32 = 0x0010;
+    pub const ZR: i32 = 0x0020;
+    pub const LX: i32 = 0x0040;
+    pub const LY: i32 = 0x0080;
+    pub const LZ: i32 = 0x0100;
+    pub const AX: i32 = 0x0200;
+    pub const AY: i32 = 0x0400;
+    pub const AZ: i32 = 0x0800;
+    pub const AXR: i32 = 0x1000;
+    pub const AYR: i32 = 0x2000;
+    pub const AZR: i32 = 0x4000;
+    pub const TYPES: i32 = 0x7FFF;
+    pub const RLOOP: i32 = 0x8000;
+
+    const NAMES: [(i32, &str); 16] = [
+        (X, "X"), (Y, "Y"), (Z, "Z"), (XR, "XR"), (YR, "YR"), (ZR, "ZR"),
+        (LX, "LX"), (LY, "LY"), (LZ, "LZ"), (AX, "AX"), (AY, "AY"), (AZ, "AZ"),
+        (AXR, "AXR"), (AYR, "AYR"), (AZR, "AZR"), (RLOOP, "RLOOP"),
+    ];
+
+    /// The set bits as their studiomdl names, e.g. "XR | RLOOP"
+    pub fn to_string(motion_type: i32) -> String {
+        let names: Vec<&str> = NAMES.iter()
+            .filter(|(bit, _)| motion_type & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            return String::from("none")
+        }
+        names.join(" | ")
+    }
+
+    /// The bit for one studiomdl name like "XR", 0 when it isn't one
+    pub fn from_name(name: &str) -> i32 {
+        NAMES.iter()
+            .find(|(_, bit_name)| bit_name.eq_ignore_ascii_case(name))
+            .map(|(bit, _)| *bit)
+            .unwrap_or(0)
+    }
+}
+
+/// `mstudiobonecontroller_t`
+#[derive(Debug, Clone, Copy)]
+pub struct BoneController {
+    pub bone: i32,
+    /// `motion` bits, only one axis is ever set
+    pub controller_type: i32,
+    pub start: f32,
+    pub end: f32,
+    pub rest: i32,
+    /// 0-3 are user set, 4 is the mouth
+    pub index: i32,
+}
+
+impl BoneController {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            bone: reader.read_i32()?,
+            controller_type: reader.read_i32()?,
+            start: reader.read_f32()?,
+            end: reader.read_f32()?,
+            rest: reader.read_i32()?,
+            index: reader.read_i32()?,
+        })
+    }
+}
+
+pub const HIT_BOX_SIZE: usize = 32;
+
+/// `mstudiobbox_t`
+#[derive(Debug, Clone, Copy)]
+pub struct HitBox {
+    pub bone: i32,
+    pub group: i32,
+    pub bbmin: Vec3,
+    pub bbmax: Vec3,
+}
+
+impl HitBox {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            bone: reader.read_i32()?,
+            group: reader.read_i32()?,
+            bbmin: read_vec3(reader)?,
+            bbmax: read_vec3(reader)?,
+        })
+    }
+}
+
+pub const SEQUENCE_SIZE: usize = 176;
+/// `Sequence::flags` bit for sequences that loop
+pub const STUDIO_LOOPING: i32 = 0x0001;
+
+/// `mstudioseqdesc_t`
+#[derive(Clone)]
+pub struct Sequence {
+    pub label: [u8; 32],
+    pub fps: f32,
+    pub flags: i32,
+    pub activity: i32,
+    pub act_weight: i32,
+    pub num_events: i32,
+    pub event_index: i32,
+    pub num_frames: i32,
+    pub num_pivots: i32,
+    pub pivot_index: i32,
+    /// `motion` bits for the movement baked into the root bone
+    pub motion_type: i32,
+    pub motion_bone: i32,
+    pub linear_movement: Vec3,
+    pub automove_pos_index: i32,
+    pub automove_angle_index: i32,
+    pub bbmin: Vec3,
+    pub bbmax: Vec3,
+    pub num_blends: i32,
+    /// Offset of the `mstudioanim_t`s inside the sequence group's data
+    pub anim_index: i32,
+    pub blend_type: [i32; 2],
+    pub blend_start: [f32; 2],
+    pub blend_end: [f32; 2],
+    pub blend_parent: i32,
+    pub seq_group: i32,
+    pub entry_node: i32,
+    pub exit_node: i32,
+    pub node_flags: i32,
+    pub next_seq: i32,
+
+    pub events: Vec<Event>,
+    pub pivots: Vec<Pivot>,
+}
+
+impl Sequence {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            label: reader.read_bytes(32)?.try_into().unwrap(),
+            fps: reader.read_f32()?,
+            flags: reader.read_i32()?,
+            activity: reader.read_i32()?,
+            act_weight: reader.read_i32()?,
+            num_events: reader.read_i32()?,
+            event_index: reader.read_i32()?,
+            num_frames: reader.read_i32()?,
+            num_pivots: reader.read_i32()?,
+            pivot_index: reader.read_i32()?,
+            motion_type: reader.read_i32()?,
+            motion_bone: reader.read_i32()?,
+            linear_movement: read_vec3(reader)?,
+            automove_pos_index: reader.read_i32()?,
+            automove_angle_index: reader.read_i32()?,
+            bbmin: read_vec3(reader)?,
+            bbmax: read_vec3(reader)?,
+            num_blends: reader.read_i32()?,
+            anim_index: reader.read_i32()?,
+            blend_type: [reader.read_i32()?, reader.read_i32()?],
+            blend_start: [reader.read_f32()?, reader.read_f32()?],
+            blend_end: [reader.read_f32()?, reader.read_f32()?],
+            blend_parent: reader.read_i32()?,
+            seq_group: reader.read_i32()?,
+            entry_node: reader.read_i32()?,
+            exit_node: reader.read_i32()?,
+            node_flags: reader.read_i32()?,
+            next_seq: reader.read_i32()?,
+            events: vec![],
+            pivots: vec![],
+        })
+    }
+
+    pub fn label(&self) -> String {
+        name_str(&self.label)
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.flags & STUDIO_LOOPING != 0
+    }
+}
+
+impl fmt::Debug for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sequence")
+         .field("label", &self.label())
+         .field("fps", &self.fps)
+         .field("flags", &self.flags)
+         .field("activity", &self.activity)
+         .field("act_weight", &self.act_weight)
+         .field("num_frames", &self.num_frames)
+         .field("motion_type", &motion::to_string(self.motion_type))
+         .field("motion_bone", &self.motion_bone)
+         .field("linear_movement", &self.linear_movement)
+         .field("bbmin", &self.bbmin)
+         .field("bbmax", &self.bbmax)
+         .field("num_blends", &self.num_blends)
+         .field("anim_index", &self.anim_index)
+         .field("blend_type", &self.blend_type)
+         .field("blend_start", &self.blend_start)
+         .field("blend_end", &self.blend_end)
+         .field("seq_group", &self.seq_group)
+         .field("entry_node", &self.entry_node)
+         .field("exit_node", &self.exit_node)
+         .field("node_flags", &self.node_flags)
+         .field("next_seq", &self.next_seq)
+         .field("events", &self.events)
+         .field("pivots", &self.pivots)
+         .finish()
+    }
+}
+
+pub const EVENT_SIZE: usize = 76;
+
+/// `mstudioevent_t`
+#[derive(Clone, Copy)]
+pub struct Event {
+    pub frame: i32,
+    pub event: i32,
+    pub event_type: i32,
+    pub options: [u8; 64],
+}
+
+impl Event {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            frame: reader.read_i32()?,
+            event: reader.read_i32()?,
+            event_type: reader.read_i32()?,
+            options: reader.read_bytes(64)?.try_into().unwrap(),
+        })
+    }
+
+    pub fn options(&self) -> String {
+        name_str(&self.options)
+    }
+
+    /// What the game and client dlls do with the well known event numbers
+    pub fn name(&self) -> Option<&'static str> {
+        match self.event {
+            1000 => Some("dead"),
+            1001 => Some("no interrupt"),
+            1002 => Some("can interrupt"),
+            1003 => Some("fire event"),
+            1004 => Some("sound"),
+            1005 => Some("sentence"),
+            1006 => Some("inline"),
+            1007 => Some("voice sound"),
+            1008 => Some("random sentence"),
+            1009 => Some("not dead"),
+            2001 => Some("body drop light"),
+            2002 => Some("body drop heavy"),
+            2010 => Some("swish sound"),
+            5001 => Some("muzzle flash 1"),
+            5002 => Some("spark"),
+            5004 => Some("client sound"),
+            5011 => Some("muzzle flash 2"),
+            5021 => Some("muzzle flash 3"),
+            5031 => Some("muzzle flash 4"),
+            _ => None,
+        }
+    }
+
+    /// The wav an event plays, sentences are left out as they go through sentences.txt
+    pub fn sound(&self) -> Option<String> {
+        match self.event {
+            1004 | 1007 | 5004 => Some(self.options()).filter(|options| !options.is_empty()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event")
+         .field("frame", &self.frame)
+         .field("event", &self.event)
+         .field("event_type", &self.event_type)
+         .field("options", &self.options())
+         .finish()
+    }
+}
+
+pub const PIVOT_SIZE: usize = 20;
+
+/// `mstudiopivot_t`, foot pivots
+#[derive(Debug, Clone, Copy)]
+pub struct Pivot {
+    pub org: Vec3,
+    pub start: i32,
+    pub end: i32,
+}
+
+impl Pivot {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            org: read_vec3(reader)?,
+            start: reader.read_i32()?,
+            end: reader.read_i32()?,
+        })
+    }
+}
+
+pub const SEQUENCE_GROUP_SIZE: usize = 104;
+
+/// `mstudioseqgroup_t`
+#[derive(Clone, Copy)]
+pub struct SequenceGroup {
+    pub label: [u8; 32],
+    /// File the group's animation is in, e.g. "models/scientist01.mdl"
+    pub name: [u8; 64],
+    /// Cache pointer, meaningless on disk
+    pub unused1: i32,
+    /// Offset of the animation data, always 0 in practice
+    pub data: i32,
+}
+
+impl SequenceGroup {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            label: reader.read_bytes(32)?.try_into().unwrap(),
+            name: reader.read_bytes(64)?.try_into().unwrap(),
+            unused1: reader.read_i32()?,
+            data: reader.read_i32()?,
+        })
+    }
+
+    pub fn label(&self) -> String {
+        name_str(&self.label)
+    }
+
+    pub fn name(&self) -> String {
+        name_str(&self.name)
+    }
+}
+
+impl fmt::Debug for SequenceGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SequenceGroup")
+         .field("label", &self.label())
+         .field("name", &self.name())
+         .field("data", &self.data)
+         .finish()
+    }
+}
+
+pub const ATTACHMENT_SIZE: usize = 88;
+
+/// `mstudioattachment_t`, a point on a bone for muzzle flashes, sprites and the like
+#[derive(Clone, Copy)]
+pub struct Attachment {
+    pub name: [u8; 32],
+    pub attachment_type: i32,
+    pub bone: i32,
+    /// Position relative to the bone
+    pub org: Vec3,
+    /// Never set by studiomdl
+    pub vectors: [Vec3; 3],
+}
+
+impl Attachment {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            name: reader.read_bytes(32)?.try_into().unwrap(),
+            attachment_type: reader.read_i32()?,
+            bone: reader.read_i32()?,
+            org: read_vec3(reader)?,
+            vectors: [read_vec3(reader)?, read_vec3(reader)?, read_vec3(reader)?],
+        })
+    }
+
+    pub fn name(&self) -> String {
+        name_str(&self.name)
+    }
+}
+
+impl fmt::Debug for Attachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attachment")
+         .field("name", &self.name())
+         .field("attachment_type", &self.attachment_type)
+         .field("bone", &self.bone)
+         .field("org", &self.org)
+         .finish()
+    }
+}
+
+/// Reads `count` structs of `item_size` bytes at `offset` after checking they fit in the file
+fn read_array<T>(
+    what: &'static str,
+    reader: &mut ByteBuffer,
+    offset: i32,
+    count: i32,
+    item_size: usize,
+    from_reader: fn(&mut ByteBuffer) -> Result<T, MdlError>,
+) -> Result<Vec<T>, MdlError> {
+    check_bounds(what, offset, count, item_size, reader.len())?;
+    reader.set_endian(bytebuffer::Endian::LittleEndian);
+    reader.set_rpos(offset as usize);
+    (0..count).map(|_| from_reader(reader)).collect()
+}
+
+/// Makes sure a bone index read out of the file points at a bone
+fn check_bone(what: &'static str, bone: i32, num_bones: usize) -> Result<(), MdlError> {
+    if bone < 0 || bone as usize >= num_bones {
+        return Err(MdlError::BadCount { what, count: bone as i64 })
+    }
+    Ok(())
+}
 
 pub struct MdlFile {
-    header: MdlHeader,
-    textures: Vec<Texture>,
+    pub header: MdlHeader,
+    pub textures: Vec<Texture>,
+    pub bones: Vec<Bone>,
+    pub bone_controllers: Vec<BoneController>,
+    pub hit_boxes: Vec<HitBox>,
+    pub sequences: Vec<Sequence>,
+    pub sequence_groups: Vec<SequenceGroup>,
+    pub body_parts: Vec<BodyPart>,
+    pub attachments: Vec<Attachment>,
+    /// `[family][skin ref]` texture index
+    pub skin_families: Vec<Vec<i16>>,
+    /// The whole file, animation and geometry are decoded out of it on demand
+    pub data: Vec<u8>,
+    /// The `modelT.mdl` the textures came from, if they live in one
+    pub texture_data: Option<Vec<u8>>,
+    /// `model01.mdl` and up by sequence group, group 0 is always `data`
+    pub sequence_group_files: Vec<Option<Vec<u8>>>,
 }
 
 impl MdlFile {
-    pub fn from_bytes(buf: &Vec::<u8>) -> Option<Self> {
-        if !Self::validate_header(buf) {
-            return None
+    pub fn from_bytes(buf: &Vec::<u8>) -> Result<Self, MdlError> {
+        let mut reader = ByteBuffer::from_bytes(buf);
+        let header = MdlHeader::from_reader(&mut reader)?;
+        let id_bytes = header.id.to_le_bytes();
+        if &id_bytes != b"IDST" {
+            return Err(MdlError::BadMagic(id_bytes))
+        }
+        let textures = Self::get_textures_from_header(header, &mut reader)?;
+        let bones = read_array("bones", &mut reader, header.bone_index, header.num_bones, BONE_SIZE, Bone::from_reader)?;
+        for (itr, bone) in bones.iter().enumerate() {
+            // studiomdl always writes parents before their children
+            if bone.parent != -1 {
+                check_bone("bone parent", bone.parent, itr)?;
+            }
+        }
+        let bone_controllers = read_array("bone controllers", &mut reader, header.bone_controller_index, header.num_bone_controllers, BONE_CONTROLLER_SIZE, BoneController::from_reader)?;
+        for bone_controller in bone_controllers.iter() {
+            check_bone("bone controller bone", bone_controller.bone, bones.len())?;
+        }
+        let hit_boxes = read_array("hit boxes", &mut reader, header.hit_box_index, header.num_hit_boxes, HIT_BOX_SIZE, HitBox::from_reader)?;
+        for hit_box in hit_boxes.iter() {
+            check_bone("hit box bone", hit_box.bone, bones.len())?;
+        }
+        let mut sequences = read_array("sequences", &mut reader, header.seq_index, header.num_seq, SEQUENCE_SIZE, Sequence::from_reader)?;
+        for sequence in sequences.iter_mut() {
+            sequence.events = read_array("sequence events", &mut reader, sequence.event_index, sequence.num_events, EVENT_SIZE, Event::from_reader)?;
+            sequence.pivots = read_array("sequence pivots", &mut reader, sequence.pivot_index, sequence.num_pivots, PIVOT_SIZE, Pivot::from_reader)?;
+        }
+        let sequence_groups = read_array("sequence groups", &mut reader, header.seq_group_index, header.num_seq_groups, SEQUENCE_GROUP_SIZE, SequenceGroup::from_reader)?;
+        let body_parts = geometry::read_body_parts(&mut reader, header.body_part_index, header.num_body_parts, bones.len())?;
+        let skin_families = Self::read_skin_families(header, &mut reader)?;
+        let attachments = read_array("attachments", &mut reader, header.attachment_index, header.num_attachments, ATTACHMENT_SIZE, Attachment::from_reader)?;
+        for attachment in attachments.iter() {
+            check_bone("attachment bone", attachment.bone, bones.len())?;
         }
 
-        let mut reader = ByteBuffer::from_vec(buf.to_owned());
-        let header = MdlHeader::from_reader(&mut reader);
-        let textures = Self::get_textures_from_header(header, &mut reader);
-
-        Some(Self {
+        Ok(Self {
             header,
             textures,
+            bones,
+            bone_controllers,
+            hit_boxes,
+            sequences,
+            sequence_groups,
+            body_parts,
+            attachments,
+            skin_families,
+            data: buf.to_owned(),
+            texture_data: None,
+            sequence_group_files: vec![],
         })
     }
 
-    fn get_textures_from_header(header: MdlHeader, reader: &mut ByteBuffer) -> Vec<Texture> {
-        let mut ret_vec = vec![];
-        let texture_offset = header.texture_index as usize;
-        let num_textures = header.num_textures as usize;
-        reader.set_endian(bytebuffer::Endian::LittleEndian);
-        reader.set_rpos(texture_offset);
-        let mut header_vec = vec![];
-        for _itr in 0..num_textures {
-            header_vec.push(TextureHeader::from_reader(reader));
+    fn read_skin_families(header: MdlHeader, reader: &mut ByteBuffer) -> Result<Vec<Vec<i16>>, MdlError> {
+        if header.num_skin_ref < 0 {
+            return Err(MdlError::BadCount { what: "skin refs", count: header.num_skin_ref as i64 })
         }
+        let num_skin_ref = header.num_skin_ref as usize;
+        let skins = read_array("skin families", reader, header.skin_index, header.num_skin_families.saturating_mul(header.num_skin_ref), 2, |reader| Ok(reader.read_i16()?))?;
+        Ok(skins.chunks(num_skin_ref.max(1)).map(|family| family.to_vec()).collect())
+    }
+
+    /// The texture a mesh's skin ref points at in one skin family
+    pub fn texture_for_skin_ref(&self, skin_family: usize, skin_ref: usize) -> Option<&Texture> {
+        self.textures.get(self.skin_texture_index(skin_family, skin_ref))
+    }
+
+    /// Index into `textures`, falls back to the skin ref itself when there is no skin table
+    pub fn skin_texture_index(&self, skin_family: usize, skin_ref: usize) -> usize {
+        self.skin_families.get(skin_family)
+            .and_then(|family| family.get(skin_ref))
+            .map(|texture| *texture as usize)
+            .unwrap_or(skin_ref)
+    }
+
+    fn get_textures_from_header(header: MdlHeader, reader: &mut ByteBuffer) -> Result<Vec<Texture>, MdlError> {
+        let mut ret_vec = vec![];
+        let file_size = reader.len();
+        let header_vec = read_array("texture headers", reader, header.texture_index, header.num_textures, TEXTURE_HEADER_SIZE, TextureHeader::from_reader)?;
         for texture_header in header_vec.iter() {
+            let texture_size = texture_header.width as i64 * texture_header.height as i64 + 256 * 3;
+            if texture_header.index as i64 + texture_size > file_size as i64 {
+                return Err(MdlError::OutOfBounds { what: "texture data", offset: texture_header.index as i64, size: texture_size, file_size })
+            }
             let data_offset = texture_header.index as usize;
             reader.set_rpos(data_offset);
-            ret_vec.push(Texture::from_reader(reader, texture_header.to_owned()));
+            ret_vec.push(Texture::from_reader(reader, texture_header.to_owned())?);
         }
 
-        ret_vec
+        Ok(ret_vec)
     }
 
     pub fn validate_header(buf: &Vec::<u8>) -> bool {
         if buf.len() < MDL_HEADER_SIZE {
             return false
         }
-        let header = MdlHeader::from_bytes(buf[0..MDL_HEADER_SIZE].try_into().unwrap());
-        let id_bytes = header.id.to_le_bytes();
-        let id =  std::str::from_utf8(&id_bytes).unwrap();
-        if id != "IDST" {
-            return false
-        }
-        true
+        &buf[0..4] == b"IDST"
     }
 }
 
@@ -369,30 +1136,389 @@ pub struct MdlFileWidget {
     pub texture_index: usize,
     pub update_texture: bool,
     pub init_textures: bool,
+    pub viewport: ModelViewport,
+    pub playback: Playback,
+    companion_dialog: FileDialog,
+    companion_error: Option<String>,
+    texture_dialog: FileDialog,
+    texture_error: Option<String>,
+    /// Saves the model and what it exports to
+    export_dialog: FileDialog,
+    export_error: Option<String>,
+    /// `MdlFile::validate`, worked out again whenever the textures or companions change
+    validation: Option<Vec<Issue>>,
     pub name: String,
     pub visible: bool,
     pub id: usize,
 }
 
 impl MdlFileWidget {
-    pub fn from_bytes(buf: &Vec<u8>, id: usize) -> Self {
-        let mdl_file = MdlFile::from_bytes(buf).expect("When they enter the last level, it's exciting");
+    pub fn from_bytes(buf: &Vec<u8>, id: usize) -> Result<Self, MdlError> {
+        let mdl_file = MdlFile::from_bytes(buf)?;
         let mdl_image = None;
         let textures = vec![];
         let texture_index = 0;
         let update_texture = true;
         let init_textures = true;
-        let name = String::from_utf8_lossy(&mdl_file.header.name).to_string();
-        Self {
+        let name = name_str(&mdl_file.header.name);
+        let playback = Playback::new(&mdl_file);
+        Ok(Self {
             mdl_file,
             mdl_image,
             textures,
             texture_index,
             update_texture,
             init_textures,
+            viewport: ModelViewport::default(),
+            playback,
+            companion_dialog: FileDialog::default().multiple(true).accept(&["mdl"]),
+            companion_error: None,
+            texture_dialog: FileDialog::default().accept(&["bmp", "png", "jpg", "jpeg", "tga"]),
+            texture_error: None,
+            export_dialog: FileDialog::default(),
+            export_error: None,
+            validation: None,
             name,
             visible: true,
             id,
+        })
+    }
+}
+
+impl MdlFileWidget {
+    fn load_companion(&mut self, name: &str, file: Vec<u8>) -> bool {
+        match self.mdl_file.add_companion(name, file) {
+            Ok(Some(Companion::Textures)) => {
+                self.init_textures = true;
+                self.update_texture = true;
+                self.texture_index = 0;
+                self.viewport.reload();
+            },
+            Ok(Some(Companion::SequenceGroup(_))) => self.playback.reload(),
+            Ok(None) => return false,
+            Err(err) => self.companion_error = Some(format!("{}: {}", name, err)),
+        }
+        true
+    }
+
+    fn companions_ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(files) = self.companion_dialog.get() {
+            self.companion_error = None;
+            for (name, file) in files {
+                if !self.load_companion(&name, file) && self.companion_error.is_none() {
+                    self.companion_error = Some(format!("{} isn't one of this model's files", name));
+                }
+            }
+        }
+        let missing = self.mdl_file.missing_companions();
+        if missing.is_empty() && self.companion_error.is_none() {
+            return
+        }
+        ui.horizontal_wrapped(|ui| {
+            if !missing.is_empty() {
+                let names: Vec<String> = missing.iter().map(|companion| self.mdl_file.companion_name(*companion)).collect();
+                ui.label(format!("Missing {}", names.join(", ")));
+                if ui.button("Load…").clicked() {
+                    self.companion_dialog.open();
+                }
+            }
+            if let Some(err) = &self.companion_error {
+                ui.colored_label(egui::Color32::RED, err.as_str());
+            }
+        });
+    }
+
+    /// Which texture every skin ref uses in each family, picking a family shows it in the preview
+    fn skin_families_ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("skin_families").striped(true).show(ui, |ui| {
+            ui.label("");
+            for skin_ref in 0..self.mdl_file.header.num_skin_ref.max(0) {
+                ui.label(format!("ref {}", skin_ref));
+            }
+            ui.end_row();
+            for (itr, family) in self.mdl_file.skin_families.iter().enumerate() {
+                ui.radio_value(&mut self.viewport.skin_family, itr, format!("skin {}", itr));
+                for texture in family.iter() {
+                    let name = self.mdl_file.textures.get(*texture as usize)
+                        .map(|texture| name_str(&texture.header.name))
+                        .unwrap_or_else(|| format!("#{}", texture));
+                    ui.label(name);
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    /// Replacing the selected texture and saving the model back out
+    fn texture_edit_ui(&mut self, ui: &mut egui::Ui) {
+        if let Some((name, file)) = self.texture_dialog.get().and_then(|files| files.into_iter().next()) {
+            let replaced = image::load_from_memory(&file)
+                .map_err(|err| err.to_string())
+                .and_then(|image| self.mdl_file.replace_texture(self.texture_index, image.to_rgb8()).map_err(|err| err.to_string()));
+            match replaced {
+                Ok(()) => {
+                    self.texture_error = None;
+                    self.init_textures = true;
+                    self.update_texture = true;
+                    self.viewport.reload();
+                },
+                Err(err) => self.texture_error = Some(format!("{}: {}", name, err)),
+            }
+        }
+        ui.horizontal(|ui| {
+            if let Some(texture) = self.mdl_file.textures.get(self.texture_index) {
+                ui.label(format!("{} {}x{}", name_str(&texture.header.name), texture.header.width, texture.header.height));
+                if ui.button("Replace…").clicked() {
+                    self.texture_dialog.open();
+                }
+            }
+            if let Some(texture) = self.mdl_file.textures.get_mut(self.texture_index) {
+                ui.menu_button(format!("Flags: {}", texture.header.flags.names()), |ui| {
+                    for (flag, name) in TextureFlags::ALL {
+                        let mut set = texture.header.flags.contains(flag);
+                        if ui.checkbox(&mut set, name).changed() {
+                            if set {
+                                texture.header.flags.insert(flag);
+                            } else {
+                                texture.header.flags.remove(flag);
+                            }
+                            self.init_textures = true;
+                            self.update_texture = true;
+                        }
+                    }
+                });
+            }
+            if let Some(err) = &self.texture_error {
+                ui.colored_label(egui::Color32::RED, err.as_str());
+            }
+        });
+    }
+
+    fn export_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Download MDL").clicked() {
+                let saved = self.mdl_file.to_bytes().and_then(|model| {
+                    self.export_dialog.save(&self.mdl_file.file_name(), model);
+                    if let Some(texture_file) = self.mdl_file.texture_file_to_bytes() {
+                        self.export_dialog.save(&self.mdl_file.companion_name(Companion::Textures), texture_file?);
+                    }
+                    Ok(())
+                });
+                if let Err(err) = saved {
+                    self.export_error = Some(err.to_string());
+                }
+            }
+            let file_name = self.mdl_file.file_name();
+            let stem = file_name.strip_suffix(".mdl").unwrap_or(&file_name);
+            if ui.button("Export glTF").on_hover_text("Current bodygroups, skeleton and sequences").clicked() {
+                match self.mdl_file.to_glb(self.viewport.body) {
+                    Ok(glb) => self.export_dialog.save(&format!("{}.glb", stem), glb),
+                    Err(err) => self.export_error = Some(err.to_string()),
+                }
+            }
+            if ui.button("Decompile").on_hover_text("QC, SMDs and bitmaps for studiomdl, as a zip").clicked() {
+                match self.mdl_file.decompile(stem) {
+                    Ok(files) => self.export_dialog.save(&format!("{}_decompiled.zip", stem), crate::archive::write_zip(&files)),
+                    Err(err) => self.export_error = Some(err.to_string()),
+                }
+            }
+            if ui.button("Export OBJ").on_hover_text("Current bodygroups in the bind pose").clicked() {
+                match self.mdl_file.to_obj(self.viewport.body, stem) {
+                    Ok(files) => {
+                        for (name, file) in files {
+                            self.export_dialog.save(&name, file);
+                        }
+                    },
+                    Err(err) => self.export_error = Some(err.to_string()),
+                }
+            }
+            if let Some(err) = &self.export_error {
+                ui.colored_label(egui::Color32::RED, err.as_str());
+            }
+        });
+    }
+
+    fn bone_name(&self, bone: i32) -> String {
+        self.mdl_file.bones.get(bone as usize).map(|bone| bone.name()).unwrap_or_default()
+    }
+
+    fn bone_tree_ui(&self, ui: &mut egui::Ui, parent: i32) {
+        let children = self.mdl_file.bones.iter()
+            .enumerate()
+            .filter(|(_, bone)| bone.parent == parent);
+        for (itr, bone) in children {
+            egui::CollapsingHeader::new(format!("{}: {}", itr, bone.name()))
+                .id_source(("bone", itr))
+                .default_open(true)
+                .show(ui, |ui| {
+                    let value = bone.value;
+                    ui.monospace(format!("position {:>9.3} {:>9.3} {:>9.3}", value[0], value[1], value[2]));
+                    ui.monospace(format!("rotation {:>9.3} {:>9.3} {:>9.3}", value[3], value[4], value[5]));
+                    for (controller_itr, controller) in bone.bone_controller.iter().enumerate() {
+                        if *controller != -1 {
+                            ui.monospace(format!("controller {} on axis {}", controller, controller_itr));
+                        }
+                    }
+                    for hit_box in self.mdl_file.hit_boxes.iter().filter(|hit_box| hit_box.bone as usize == itr) {
+                        ui.monospace(format!("hit box group {} {:?} {:?}", hit_box.group, hit_box.bbmin, hit_box.bbmax));
+                    }
+                    self.bone_tree_ui(ui, itr as i32);
+                });
+        }
+    }
+
+    fn bone_controllers_ui(&self, ui: &mut egui::Ui) {
+        egui::Grid::new("bone_controllers_grid").striped(true).show(ui, |ui| {
+            ui.label("index");
+            ui.label("bone");
+            ui.label("type");
+            ui.label("range");
+            ui.label("rest");
+            ui.end_row();
+            for bone_controller in self.mdl_file.bone_controllers.iter() {
+                ui.label(bone_controller.index.to_string());
+                ui.label(self.bone_name(bone_controller.bone));
+                ui.label(motion::to_string(bone_controller.controller_type));
+                ui.label(format!("{} to {}", bone_controller.start, bone_controller.end));
+                ui.label(bone_controller.rest.to_string());
+                ui.end_row();
+            }
+        });
+    }
+
+    fn sequences_ui(&self, ui: &mut egui::Ui) {
+        for (itr, sequence_group) in self.mdl_file.sequence_groups.iter().enumerate() {
+            ui.label(format!("group {}: {} {}", itr, sequence_group.label(), sequence_group.name()));
+        }
+        ui.separator();
+        for (itr, sequence) in self.mdl_file.sequences.iter().enumerate() {
+            let looping = if sequence.is_looping() { " loop" } else { "" };
+            egui::CollapsingHeader::new(format!("{}: {} ({} frames @ {} fps{})", itr, sequence.label(), sequence.num_frames, sequence.fps, looping))
+                .id_source(("sequence", itr))
+                .show(ui, |ui| {
+                    egui::Grid::new(("sequence_grid", itr)).show(ui, |ui| {
+                        ui.label("activity");
+                        ui.label(format!("{} (weight {})", sequence.activity, sequence.act_weight));
+                        ui.end_row();
+                        ui.label("motion");
+                        ui.label(format!("{} on bone {}", motion::to_string(sequence.motion_type), sequence.motion_bone));
+                        ui.end_row();
+                        ui.label("linear movement");
+                        ui.label(format!("{:?}", sequence.linear_movement));
+                        ui.end_row();
+                        ui.label("bounds");
+                        ui.label(format!("{:?} {:?}", sequence.bbmin, sequence.bbmax));
+                        ui.end_row();
+                        ui.label("blends");
+                        ui.label(format!("{}", sequence.num_blends));
+                        ui.end_row();
+                        for blend in 0..2 {
+                            if sequence.blend_type[blend] != 0 {
+                                ui.label(format!("blend {}", blend));
+                                ui.label(format!("{} from {} to {}", motion::to_string(sequence.blend_type[blend]), sequence.blend_start[blend], sequence.blend_end[blend]));
+                                ui.end_row();
+                            }
+                        }
+                        ui.label("group");
+                        ui.label(format!("{}", sequence.seq_group));
+                        ui.end_row();
+                        ui.label("transition");
+                        ui.label(format!("{} -> {}", sequence.entry_node, sequence.exit_node));
+                        ui.end_row();
+                    });
+                    for event in sequence.events.iter() {
+                        ui.monospace(format!("frame {:>4} event {:>5} {:<16} {}", event.frame, event.event, event.name().unwrap_or_default(), event.options()));
+                    }
+                    for pivot in sequence.pivots.iter() {
+                        ui.monospace(format!("pivot {:?} frames {} to {}", pivot.org, pivot.start, pivot.end));
+                    }
+                });
+        }
+    }
+
+    fn body_parts_ui(&self, ui: &mut egui::Ui) {
+        for (itr, body_part) in self.mdl_file.body_parts.iter().enumerate() {
+            egui::CollapsingHeader::new(format!("{}: {} ({} models)", itr, body_part.name(), body_part.models.len()))
+                .id_source(("body_part", itr))
+                .show(ui, |ui| {
+                    for model in body_part.models.iter() {
+                        ui.monospace(format!("{:<24} {} verts, {} normals, {} triangles", model.name(), model.vertices.len(), model.normals.len(), model.num_triangles()));
+                        for (mesh_itr, mesh) in model.meshes.iter().enumerate() {
+                            let texture = self.mdl_file.texture_for_skin_ref(0, mesh.skin_ref.max(0) as usize)
+                                .map(|texture| name_str(&texture.header.name))
+                                .unwrap_or_default();
+                            ui.monospace(format!("    mesh {}: {} triangles, skin ref {} {}", mesh_itr, mesh.num_triangles(), mesh.skin_ref, texture));
+                        }
+                    }
+                });
+        }
+    }
+
+    fn attachments_ui(&self, ui: &mut egui::Ui) {
+        for (itr, attachment) in self.mdl_file.attachments.iter().enumerate() {
+            ui.monospace(format!("{:>2} {:<16} {:<24} {:?}", itr, attachment.name(), self.bone_name(attachment.bone), attachment.org));
+        }
+    }
+
+    /// Every event of every sequence in one place, with the sounds they play gathered at the end
+    fn events_ui(&self, ui: &mut egui::Ui) {
+        let mut sounds = vec![];
+        egui::Grid::new("events_grid").striped(true).show(ui, |ui| {
+            for sequence in self.mdl_file.sequences.iter() {
+                for event in sequence.events.iter() {
+                    ui.monospace(sequence.label());
+                    ui.monospace(format!("{}", event.frame));
+                    ui.monospace(format!("{}", event.event));
+                    ui.label(event.name().unwrap_or_default());
+                    ui.monospace(event.options());
+                    ui.end_row();
+                    sounds.extend(event.sound());
+                }
+            }
+        });
+        sounds.sort();
+        sounds.dedup();
+        if !sounds.is_empty() {
+            ui.separator();
+            let sound_list = sounds.join("\n");
+            ui.horizontal(|ui| {
+                ui.label(format!("{} sounds", sounds.len()));
+                if ui.button("📋Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = sound_list.clone());
+                }
+            });
+            ui.monospace(sound_list);
+        }
+    }
+
+    fn validation_ui(&mut self, ui: &mut egui::Ui) {
+        let issues = self.validation.get_or_insert_with(|| self.mdl_file.validate());
+        if issues.is_empty() {
+            ui.label("Nothing the engine would object to");
+            return
+        }
+        let report = issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>().join("\n");
+        if ui.button("📋Copy").clicked() {
+            ui.output_mut(|o| o.copied_text = report);
+        }
+        for issue in issues.iter() {
+            let color = match issue.severity {
+                Severity::Error => ui.visuals().error_fg_color,
+                Severity::Warning => ui.visuals().warn_fg_color,
+            };
+            ui.colored_label(color, &issue.message).on_hover_text(issue.reference);
+        }
+    }
+
+    fn hit_boxes_ui(&self, ui: &mut egui::Ui) {
+        let mut groups: Vec<i32> = self.mdl_file.hit_boxes.iter().map(|hit_box| hit_box.group).collect();
+        groups.sort();
+        groups.dedup();
+        for group in groups {
+            ui.label(format!("group {}", group));
+            for hit_box in self.mdl_file.hit_boxes.iter().filter(|hit_box| hit_box.group == group) {
+                ui.monospace(format!("    {:<24} {:?} {:?}", self.bone_name(hit_box.bone), hit_box.bbmin, hit_box.bbmax));
+            }
         }
     }
 }
@@ -419,24 +1545,75 @@ impl super::HlFileWidget for MdlFileWidget {
     fn get_visibility(&mut self) -> bool {
         self.visible 
     }
+
+    fn add_companion_file(&mut self, name: &str, file: &[u8]) -> bool {
+        self.load_companion(name, file.to_vec())
+    }
 }
 
 
 impl super::View for MdlFileWidget {
     fn ui(&mut self, ui: &mut egui::Ui) {
+        self.companions_ui(ui);
+        egui::CollapsingHeader::new("Header")
+            .id_source("header")
+            .show(ui, |ui| {
+                let header_dump = format!("{:#?}", self.mdl_file.header);
+                if ui.button("📋Copy").clicked() {
+                    ui.output_mut(|o| o.copied_text = header_dump.clone());
+                }
+                ui.monospace(header_dump);
+            });
+        let num_issues = self.validation.get_or_insert_with(|| self.mdl_file.validate()).len();
+        egui::CollapsingHeader::new(format!("Validation ({} issues)", num_issues))
+            .id_source("validation")
+            .show(ui, |ui| self.validation_ui(ui));
+        egui::CollapsingHeader::new(format!("Skeleton ({} bones)", self.mdl_file.bones.len()))
+            .id_source("skeleton")
+            .show(ui, |ui| self.bone_tree_ui(ui, -1));
+        egui::CollapsingHeader::new(format!("Bone controllers ({})", self.mdl_file.bone_controllers.len()))
+            .id_source("bone_controllers")
+            .show(ui, |ui| self.bone_controllers_ui(ui));
+        egui::CollapsingHeader::new(format!("Body parts ({})", self.mdl_file.body_parts.len()))
+            .id_source("body_parts")
+            .show(ui, |ui| self.body_parts_ui(ui));
+        egui::CollapsingHeader::new(format!("Hit boxes ({})", self.mdl_file.hit_boxes.len()))
+            .id_source("hit_boxes")
+            .show(ui, |ui| self.hit_boxes_ui(ui));
+        egui::CollapsingHeader::new(format!("Sequences ({})", self.mdl_file.sequences.len()))
+            .id_source("sequences")
+            .show(ui, |ui| self.sequences_ui(ui));
+        egui::CollapsingHeader::new(format!("Attachments ({})", self.mdl_file.attachments.len()))
+            .id_source("attachments")
+            .show(ui, |ui| self.attachments_ui(ui));
+        let num_events: usize = self.mdl_file.sequences.iter().map(|sequence| sequence.events.len()).sum();
+        egui::CollapsingHeader::new(format!("Events ({})", num_events))
+            .id_source("events")
+            .show(ui, |ui| self.events_ui(ui));
         if self.init_textures {
+            self.validation = None;
             self.textures.clear();
             for texture in self.mdl_file.textures.iter() {
                 let egui_image = ui.ctx().load_texture(
                     "my-image", 
-                    egui::ColorImage::from_rgb(
-                        [texture.header.width as usize, texture.header.height as usize], 
-                        &texture.to_rgb_image_vec()),
+                    texture.to_color_image(),
                     Default::default());
                 self.textures.push(egui_image);
             }
             self.init_textures = false;
         }
+        egui::CollapsingHeader::new("Preview")
+            .id_source("preview")
+            .default_open(true)
+            .show(ui, |ui| {
+                self.viewport.controls_ui(ui, &self.mdl_file);
+                self.playback.ui(ui, &self.mdl_file);
+                let bones = self.mdl_file.bone_matrices(&self.playback.pose(&self.mdl_file));
+                self.viewport.ui(ui, &self.mdl_file, &self.textures, &bones);
+            });
+        egui::CollapsingHeader::new("Export")
+            .id_source("export")
+            .show(ui, |ui| self.export_ui(ui));
 
         ui.horizontal(|ui| {
             match &self.mdl_image {
@@ -461,7 +1638,9 @@ impl super::View for MdlFileWidget {
             .show(ui, |ui| {
                 ui.horizontal(|ui| {
                     for (itr, texture) in self.textures.iter().enumerate() {
-                        let response = ui.add(egui::ImageButton::new(texture, texture.size_vec2()));
+                        let flags = self.mdl_file.textures.get(itr).map(|texture| texture.header.flags).unwrap_or_default();
+                        let response = ui.add(egui::ImageButton::new(texture, texture.size_vec2()))
+                            .on_hover_text(flags.names());
                         if response.clicked() {
                             self.texture_index = itr;
                             self.update_texture = true;
@@ -469,9 +1648,408 @@ impl super::View for MdlFileWidget {
                     }
                 });
             });
+        self.texture_edit_ui(ui);
+        if self.mdl_file.skin_families.len() > 1 {
+            egui::CollapsingHeader::new(format!("Skin families ({})", self.mdl_file.skin_families.len()))
+                .id_source("skin_families")
+                .show(ui, |ui| self.skin_families_ui(ui));
+        }
         if self.update_texture {
-            self.mdl_image = Some(self.textures[self.texture_index].to_owned());
+            // models with their textures in a companion file have none until it is loaded
+            self.mdl_image = self.textures.get(self.texture_index).cloned();
             self.update_texture = false;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math;
+
+    fn test_header() -> Vec<u8> {
+        let mut buf = b"IDST".to_vec();
+        buf.extend(10i32.to_le_bytes());
+        let mut name = [0u8; 64];
+        name[..8].copy_from_slice(b"test.mdl");
+        buf.extend(name);
+        buf.extend((MDL_HEADER_SIZE as i32).to_le_bytes());
+        for itr in 0..15 {
+            buf.extend((itr as f32).to_le_bytes());
+        }
+        // flags through transition_index
+        for itr in 0..27i32 {
+            buf.extend((100 + itr).to_le_bytes());
+        }
+        buf
+    }
+
+    /// A model with no bones or meshes, just one 2x2 texture and its skin table
+    fn test_texture_model() -> Vec<u8> {
+        let mut buf = test_header();
+        for itr in 0..27 {
+            let value: i32 = match itr {
+                11 => 1,
+                12 => MDL_HEADER_SIZE as i32,
+                13 => 324,
+                14 | 15 => 1,
+                16 => 324 + 4 + 256 * 3,
+                _ => 0,
+            };
+            let offset = MDL_HEADER_SIZE - 27 * 4 + itr * 4;
+            buf[offset..(offset + 4)].copy_from_slice(&value.to_le_bytes());
+        }
+        let mut name = [0u8; 64];
+        name[..3].copy_from_slice(b"tex");
+        buf.extend(name);
+        for value in [0u32, 2, 2, 324] {
+            buf.extend(value.to_le_bytes());
+        }
+        buf.extend([1, 2, 3, 4]);
+        buf.extend([7; 256 * 3]);
+        buf.extend(0i16.to_le_bytes());
+        let length = buf.len() as i32;
+        buf[72..76].copy_from_slice(&length.to_le_bytes());
+        buf
+    }
+
+    /// Compiles `qc` with `reference` as `ref.smd`, `test_texture_model`'s texture as `tex.bmp`
+    /// and whatever else the test needs in `extra_files`
+    fn compile_test_model(qc: &str, reference: &str, extra_files: Vec<(&str, Vec<u8>)>) -> MdlFile {
+        let texture = MdlFile::from_bytes(&test_texture_model()).unwrap().textures.remove(0);
+        let mut files = vec![
+            (String::from("ref.smd"), reference.as_bytes().to_vec()),
+            (String::from("tex.bmp"), texture_bitmap(&texture)),
+        ];
+        files.extend(extra_files.into_iter().map(|(name, data)| (name.to_string(), data)));
+        MdlFile::compile(qc, &files).unwrap()
+    }
+
+    #[test]
+    fn texture_write_in_place_or_relocated() {
+        let buf = test_texture_model();
+        let mut mdl_file = MdlFile::from_bytes(&buf).unwrap();
+        assert_eq!(mdl_file.skin_families, vec![vec![0]]);
+        assert_eq!(mdl_file.to_bytes().unwrap(), buf);
+
+        mdl_file.replace_texture(0, image::RgbImage::new(2, 2)).unwrap();
+        let same_size = mdl_file.to_bytes().unwrap();
+        assert_eq!(same_size.len(), buf.len());
+        assert_eq!(MdlFile::from_bytes(&same_size).unwrap().textures[0].header.index, 324);
+
+        mdl_file.replace_texture(0, image::RgbImage::new(4, 4)).unwrap();
+        let bigger = MdlFile::from_bytes(&mdl_file.to_bytes().unwrap()).unwrap();
+        assert_eq!(name_str(&bigger.textures[0].header.name), "tex");
+        assert_eq!(bigger.textures[0].header.width, 4);
+        assert!(bigger.textures[0].header.index as usize >= buf.len());
+        assert_eq!(bigger.textures[0].raw_data.len(), 16);
+    }
+
+    #[test]
+    fn glb_framing() {
+        let mdl_file = MdlFile::from_bytes(&test_texture_model()).unwrap();
+        let glb = mdl_file.to_glb(0).unwrap();
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[4..8].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(json_length % 4, 0);
+        assert_eq!(&glb[16..20], b"JSON");
+        assert_eq!(&glb[(20 + json_length + 4)..(20 + json_length + 8)], b"BIN\0");
+    }
+
+    #[test]
+    fn decompile_bundles_qc_and_bitmaps() {
+        let mdl_file = MdlFile::from_bytes(&test_texture_model()).unwrap();
+        let files = mdl_file.decompile("test").unwrap();
+        assert_eq!(files[0].0, "test.qc");
+        assert!(String::from_utf8_lossy(&files[0].1).contains("$modelname \"test.mdl\""));
+        let (name, bmp) = files.last().unwrap();
+        assert_eq!(name, "tex.bmp");
+        // rows pad to 4 bytes and go bottom up
+        assert_eq!(bmp.len(), 14 + 40 + 256 * 4 + 2 * 4);
+        assert_eq!(&bmp[(bmp.len() - 8)..], &[3, 4, 0, 0, 1, 2, 0, 0]);
+
+        assert_eq!(crate::archive::crc32(b"123456789"), 0xCBF43926);
+        let zip = crate::archive::write_zip(&files);
+        assert_eq!(&zip[0..4], b"PK\x03\x04");
+    }
+
+    #[test]
+    fn compile_round_trips() {
+        let texture = MdlFile::from_bytes(&test_texture_model()).unwrap().textures.remove(0);
+        let qc = "$modelname \"box.mdl\"\n$body studio \"ref\"\n$sequence idle \"idle\" fps 10 loop\n";
+        let reference = "version 1\nnodes\n0 \"root\" -1\n1 \"arm\" 0\nend\nskeleton\ntime 0\n0 0 0 0 0 0 0\n1 4 0 0 0 0 0\nend\n\
+            triangles\ntex.bmp\n0 0 0 0 0 0 1 0 0\n1 4 0 0 0 0 1 1 0\n1 4 4 0 0 0 1 1 1\nend\n";
+        let idle = "version 1\nnodes\n0 \"root\" -1\n1 \"arm\" 0\nend\nskeleton\ntime 0\n0 0 0 0 0 0 0\n1 4 0 0 0 0 0\n\
+            time 1\n1 4 0 0 0 0 0.5\nend\n";
+        let mdl_file = compile_test_model(qc, reference, vec![("idle.smd", idle.as_bytes().to_vec())]);
+        assert_eq!(name_str(&mdl_file.header.name), "box.mdl");
+        assert_eq!(mdl_file.bones.len(), 2);
+        assert_eq!(mdl_file.bones[1].parent, 0);
+        assert_eq!(mdl_file.textures[0].raw_data, texture.raw_data);
+
+        let animation = mdl_file.decode_animation(0, 0).unwrap();
+        assert_eq!(animation.num_frames(), 2);
+        assert!((animation.frames[1][1][5] - 0.5).abs() < 0.01);
+        assert!((animation.frames[1][1][0] - 4.0).abs() < 0.01);
+
+        let geometry = mdl_file.model_geometry(0, 0, 0);
+        assert_eq!(geometry.len(), 1);
+        assert_eq!(geometry[0].indices.len(), 3);
+
+        // only what the qc names counts as a source, the rest opens on its own
+        let files: Vec<(String, Vec<u8>)> = vec![
+            (String::from("REF.smd"), reference.as_bytes().to_vec()),
+            (String::from("photo.png"), vec![]),
+            (String::from("idle.smd"), idle.as_bytes().to_vec()),
+            (String::from("tex.bmp"), texture_bitmap(&texture)),
+        ];
+        let (compiled, used) = MdlFile::compile_tracked(qc, &files);
+        assert!(compiled.is_ok());
+        assert_eq!(used, vec![true, false, true, true]);
+
+        // what would change the model is an error rather than skipped
+        for unsupported in [
+            "$origin 0 0 8\n$body studio \"ref\"\n",
+            "$renamebone \"arm\" \"hand\"\n$body studio \"ref\"\n",
+            "$body studio \"ref\" reverse\n",
+            "$body studio \"ref\" scale 2\n",
+            "$body studio \"ref\"\n$sequence walk \"idle\" frame 0 1\n",
+            "$body studio \"ref\"\n$sequence walk \"idle\" rotate 90\n",
+        ] {
+            let qc = format!("{}$sequence idle \"idle\"\n", unsupported);
+            assert!(matches!(MdlFile::compile(&qc, &[]), Err(MdlError::Compile(message)) if message.contains("isn't supported")), "{}", unsupported);
+        }
+    }
+
+    #[test]
+    fn exports_wind_counter_clockwise() {
+        let qc = "$modelname \"tri.mdl\"\n$body studio \"ref\"\n$sequence idle \"ref\"\n";
+        // smd triangles are counter-clockwise seen from where their normals point
+        let reference = "version 1\nnodes\n0 \"root\" -1\nend\nskeleton\ntime 0\n0 0 0 0 0 0 0\nend\n\
+            triangles\ntex.bmp\n0 0 0 0 0 0 1 0 0\n0 4 0 0 0 0 1 1 0\n0 4 4 0 0 0 1 1 1\nend\n";
+        let mdl_file = compile_test_model(qc, reference, vec![]);
+        let obj = mdl_file.to_obj(0, "tri").unwrap().remove(0).1;
+        let obj = String::from_utf8(obj).unwrap();
+        let floats = |line: &str| -> Vec3 {
+            let values: Vec<f32> = line.split_whitespace().skip(1).map(|value| value.parse().unwrap()).collect();
+            [values[0], values[1], values[2]]
+        };
+        let positions: Vec<Vec3> = obj.lines().filter(|line| line.starts_with("v ")).map(floats).collect();
+        let normals: Vec<Vec3> = obj.lines().filter(|line| line.starts_with("vn ")).map(floats).collect();
+        let face: Vec<usize> = obj.lines().find(|line| line.starts_with("f ")).unwrap()
+            .split_whitespace().skip(1)
+            .map(|corner| corner.split('/').next().unwrap().parse::<usize>().unwrap() - 1)
+            .collect();
+        let winding = math::cross(
+            math::sub(positions[face[1]], positions[face[0]]),
+            math::sub(positions[face[2]], positions[face[0]]));
+        assert!(math::dot(winding, normals[face[0]]) > 0.0);
+    }
+
+    #[test]
+    fn texture_resize_checks_every_skin_family() {
+        let texture = MdlFile::from_bytes(&test_texture_model()).unwrap().textures.remove(0);
+        let qc = "$modelname \"tri.mdl\"\n$body studio \"ref\"\n\
+            $texturegroup skins\n{\n{ \"tex.bmp\" }\n{ \"alt.bmp\" }\n}\n$sequence idle \"ref\"\n";
+        let reference = "version 1\nnodes\n0 \"root\" -1\nend\nskeleton\ntime 0\n0 0 0 0 0 0 0\nend\n\
+            triangles\ntex.bmp\n0 0 0 0 0 0 1 0 0\n0 4 0 0 0 0 1 1 0\n0 4 4 0 0 0 1 1 1\nend\n";
+        let mut mdl_file = compile_test_model(qc, reference, vec![("alt.bmp", texture_bitmap(&texture))]);
+        assert_eq!(mdl_file.skin_families, vec![vec![0], vec![1]]);
+        let coords = |mdl_file: &MdlFile| -> Vec<(i16, i16)> {
+            mdl_file.body_parts[0].models[0].meshes[0].commands.iter()
+                .flat_map(|command| command.vertices.iter())
+                .map(|vertex| (vertex.s, vertex.t))
+                .collect()
+        };
+        let before = coords(&mdl_file);
+        assert!(before.iter().any(|coord| *coord != (0, 0)));
+
+        // skin 1 puts the other texture on the same mesh, it can't be resized on its own
+        let result = mdl_file.replace_texture(1, image::RgbImage::new(4, 4));
+        assert!(matches!(result, Err(MdlError::SkinConflict { skin_ref: 0, texture: 1 })));
+        assert_eq!(coords(&mdl_file), before);
+        assert_eq!(mdl_file.textures[1].header.width, 2);
+
+        mdl_file.skin_families = vec![vec![1], vec![1]];
+        mdl_file.replace_texture(1, image::RgbImage::new(4, 4)).unwrap();
+        assert_eq!(coords(&mdl_file), before.iter().map(|(s, t)| (s * 2, t * 2)).collect::<Vec<_>>());
+
+        // coordinates that would go past i16 are an error, not clamped
+        let result = mdl_file.replace_texture(1, image::RgbImage::new(4 * 16384, 4));
+        assert!(matches!(result, Err(MdlError::TexCoordOutOfRange { texture: 1, .. })));
+        assert_eq!(coords(&mdl_file), before.iter().map(|(s, t)| (s * 2, t * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn geometry_uvs_follow_the_skin_family() {
+        let texture = MdlFile::from_bytes(&test_texture_model()).unwrap().textures.remove(0);
+        let bigger = Texture::from_image(image::RgbImage::new(4, 4), &texture.header);
+        let qc = "$modelname \"tri.mdl\"\n$body studio \"ref\"\n\
+            $texturegroup skins\n{\n{ \"tex.bmp\" }\n{ \"big.bmp\" }\n}\n$sequence idle \"ref\"\n";
+        let reference = "version 1\nnodes\n0 \"root\" -1\nend\nskeleton\ntime 0\n0 0 0 0 0 0 0\nend\n\
+            triangles\ntex.bmp\n0 0 0 0 0 0 1 0 0\n0 4 0 0 0 0 1 1 0\n0 4 4 0 0 0 1 1 1\nend\n";
+        let mdl_file = compile_test_model(qc, reference, vec![("big.bmp", texture_bitmap(&bigger))]);
+        let uvs = |skin_family: usize| -> Vec<[f32; 2]> {
+            mdl_file.model_geometry(0, 0, skin_family)[0].vertices.iter().map(|vertex| vertex.uv).collect()
+        };
+        let (small, big) = (uvs(0), uvs(1));
+        assert!(small.iter().any(|uv| uv[0] > 0.0));
+        for (small, big) in small.iter().zip(big.iter()) {
+            assert_eq!([small[0] * 0.5, small[1] * 0.5], *big);
+        }
+    }
+
+    /// `test_texture_model` with a root bone, an arm bone under it, a controller turning the arm
+    /// and a hit box around it
+    fn test_bone_model() -> Vec<u8> {
+        let mut buf = test_texture_model();
+        let bone_index = buf.len();
+        for (name, parent, controllers) in [(&b"root"[..], -1i32, [-1i32; 6]), (&b"arm"[..], 0, [-1, -1, -1, 0, -1, -1])] {
+            let mut bone_name = [0u8; 32];
+            bone_name[..name.len()].copy_from_slice(name);
+            buf.extend(bone_name);
+            buf.extend(parent.to_le_bytes());
+            buf.extend(0i32.to_le_bytes());
+            for controller in controllers {
+                buf.extend(controller.to_le_bytes());
+            }
+            for value in [4.0f32, 0.0, 0.0, 0.0, 0.0, 0.0] {
+                buf.extend(value.to_le_bytes());
+            }
+            for scale in [1.0f32; 6] {
+                buf.extend(scale.to_le_bytes());
+            }
+        }
+        let bone_controller_index = buf.len();
+        buf.extend(1i32.to_le_bytes());
+        buf.extend(motion::XR.to_le_bytes());
+        buf.extend((-90.0f32).to_le_bytes());
+        buf.extend(90.0f32.to_le_bytes());
+        buf.extend(0i32.to_le_bytes());
+        buf.extend(0i32.to_le_bytes());
+        let hit_box_index = buf.len();
+        buf.extend(1i32.to_le_bytes());
+        buf.extend(2i32.to_le_bytes());
+        for value in [-1.0f32, -2.0, -3.0, 1.0, 2.0, 3.0] {
+            buf.extend(value.to_le_bytes());
+        }
+        // num_bones through hit_box_index
+        let counts = [2, bone_index, 1, bone_controller_index, 1, hit_box_index];
+        for (itr, value) in counts.iter().enumerate() {
+            let offset = MDL_HEADER_SIZE - 26 * 4 + itr * 4;
+            buf[offset..(offset + 4)].copy_from_slice(&(*value as i32).to_le_bytes());
+        }
+        let length = buf.len() as i32;
+        buf[72..76].copy_from_slice(&length.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn reads_bones_controllers_and_hit_boxes() {
+        let buf = test_bone_model();
+        let mdl_file = MdlFile::from_bytes(&buf).unwrap();
+        assert_eq!(mdl_file.bones.iter().map(|bone| bone.name()).collect::<Vec<_>>(), vec!["root", "arm"]);
+        assert_eq!(mdl_file.bones[1].parent, 0);
+        assert_eq!(mdl_file.bones[1].bone_controller, [-1, -1, -1, 0, -1, -1]);
+
+        assert_eq!(mdl_file.bone_controllers.len(), 1);
+        let controller = mdl_file.bone_controllers[0];
+        assert_eq!((controller.bone, controller.index), (1, 0));
+        assert_eq!((controller.start, controller.end), (-90.0, 90.0));
+        assert_eq!(motion::to_string(controller.controller_type), "XR");
+        assert_eq!(motion::to_string(motion::YR | motion::RLOOP), "YR | RLOOP");
+        assert_eq!(motion::to_string(0), "none");
+
+        assert_eq!(mdl_file.hit_boxes.len(), 1);
+        let hit_box = mdl_file.hit_boxes[0];
+        assert_eq!((hit_box.bone, hit_box.group), (1, 2));
+        assert_eq!((hit_box.bbmin, hit_box.bbmax), ([-1.0, -2.0, -3.0], [1.0, 2.0, 3.0]));
+
+        // parents come first and every bone index has to point at a bone
+        let mut bad_parent = buf.clone();
+        let parent = mdl_file.header.bone_index as usize + 32;
+        bad_parent[parent..(parent + 4)].copy_from_slice(&1i32.to_le_bytes());
+        assert!(matches!(MdlFile::from_bytes(&bad_parent), Err(MdlError::BadCount { what: "bone parent", .. })));
+        let mut bad_hit_box = buf;
+        let bone = mdl_file.header.hit_box_index as usize;
+        bad_hit_box[bone..(bone + 4)].copy_from_slice(&2i32.to_le_bytes());
+        assert!(matches!(MdlFile::from_bytes(&bad_hit_box), Err(MdlError::BadCount { what: "hit box bone", .. })));
+    }
+
+    #[test]
+    fn event_sounds() {
+        let mut options = [0u8; 64];
+        options[..17].copy_from_slice(b"weapons/shot1.wav");
+        let event = Event { frame: 2, event: 5004, event_type: 0, options };
+        assert_eq!(event.name(), Some("client sound"));
+        assert_eq!(event.sound().as_deref(), Some("weapons/shot1.wav"));
+        let sentence = Event { event: 1008, ..event };
+        assert_eq!(sentence.sound(), None);
+    }
+
+    #[test]
+    fn validate_flags_odd_textures() {
+        let mut mdl_file = MdlFile::from_bytes(&test_texture_model()).unwrap();
+        mdl_file.header.data_length = mdl_file.data.len() as i32 + 4;
+        let issues = mdl_file.validate();
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[0].reference, "studiohdr_t length");
+        assert!(issues.iter().any(|issue| issue.severity == Severity::Warning && issue.message.contains("multiple of 8")));
+    }
+
+    #[test]
+    fn header_layout() {
+        let buf = test_header();
+        assert_eq!(buf.len(), MDL_HEADER_SIZE);
+        let header = MdlHeader::from_bytes(&buf).unwrap();
+        assert_eq!(header.version, 10);
+        assert_eq!(name_str(&header.name), "test.mdl");
+        assert_eq!(header.eye_position, [0.0, 1.0, 2.0]);
+        assert_eq!(header.bbmax, [12.0, 13.0, 14.0]);
+        assert_eq!(header.flags, 100);
+        assert_eq!(header.skin_index, 116);
+        assert_eq!(header.num_body_parts, 117);
+        assert_eq!(header.transition_index, 126);
+    }
+
+    #[test]
+    fn anim_values_repeat_last_valid() {
+        let mut data = vec![0xFF];
+        // 2 valid values over 4 frames, then 1 over 2
+        data.extend([2, 4]);
+        data.extend(10i16.to_le_bytes());
+        data.extend((-20i16).to_le_bytes());
+        data.extend([1, 2]);
+        data.extend(30i16.to_le_bytes());
+        assert_eq!(decode_anim_values(&data, 1, 6).unwrap(), vec![10, -20, -20, -20, 30, 30]);
+        assert_eq!(decode_anim_values(&data, 1, 3).unwrap(), vec![10, -20, -20]);
+        assert!(decode_anim_values(&data, 1, 7).is_err());
+    }
+
+    #[test]
+    fn last_frame_never_blends_into_the_first() {
+        let animation = Animation {
+            frames: (0..3).map(|frame| vec![[frame as f32 * 10.0, 0.0, 0.0, 0.0, 0.0, 0.0]]).collect(),
+        };
+        assert_eq!(animation.interpolated(1.5)[0].position, [15.0, 0.0, 0.0]);
+        assert_eq!(animation.interpolated(2.5)[0].position, [20.0, 0.0, 0.0]);
+        assert_eq!(animation.interpolated(7.0)[0].position, [20.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn strips_and_fans_keep_gl_winding() {
+        let vertex = |itr| TriangleVertex { vertex: itr, normal: 0, s: 0, t: 0 };
+        let strip = TriangleCommand { fan: false, vertices: (0..5).map(vertex).collect() };
+        assert_eq!(strip.triangles(), vec![[0, 1, 2], [2, 1, 3], [2, 3, 4]]);
+        let fan = TriangleCommand { fan: true, vertices: (0..4).map(vertex).collect() };
+        assert_eq!(fan.triangles(), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn truncated_header_is_an_error() {
+        let buf = test_header();
+        assert!(matches!(MdlHeader::from_bytes(&buf[..100]), Err(MdlError::UnexpectedEof)));
+    }
+}
@@ -0,0 +1,223 @@
+use super::{motion, MdlError, MdlFile};
+use crate::math::{self, Mat3x4, Quat, Vec3};
+
+/// `mstudioanim_t`, six u16 offsets per bone per blend
+pub const ANIM_SIZE: usize = 12;
+
+/// Where a bone is relative to its parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoneTransform {
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// Every frame of one blend of a sequence, decoded
+#[derive(Debug, Clone)]
+pub struct Animation {
+    /// `[frame][bone]` position xyz then euler rotation xyz in radians, bone controllers not applied
+    pub frames: Vec<Vec<[f32; 6]>>,
+}
+
+impl Animation {
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn transforms(&self, frame: usize) -> Vec<BoneTransform> {
+        self.transforms_adjusted(frame, &[])
+    }
+
+    /// Like `transforms` with per bone offsets added to the raw values, see `MdlFile::controller_adjustments`
+    pub fn transforms_adjusted(&self, frame: usize, adjustments: &[[f32; 6]]) -> Vec<BoneTransform> {
+        self.frames[frame].iter()
+            .enumerate()
+            .map(|(itr, value)| {
+                let mut value = *value;
+                if let Some(adjustment) = adjustments.get(itr) {
+                    for axis in 0..6 {
+                        value[axis] += adjustment[axis];
+                    }
+                }
+                BoneTransform {
+                    position: [value[0], value[1], value[2]],
+                    rotation: math::angle_quaternion([value[3], value[4], value[5]]),
+                }
+            })
+            .collect()
+    }
+
+    /// Blends between the two frames either side of `frame`. Looping sequences repeat their first
+    /// frame as the last one, so the last frame never blends back into the first
+    pub fn interpolated(&self, frame: f32) -> Vec<BoneTransform> {
+        self.interpolated_adjusted(frame, &[])
+    }
+
+    pub fn interpolated_adjusted(&self, frame: f32, adjustments: &[[f32; 6]]) -> Vec<BoneTransform> {
+        let num_frames = self.num_frames();
+        if num_frames == 0 {
+            return vec![]
+        }
+        let frame = frame.max(0.0);
+        let first = (frame.floor() as usize).min(num_frames - 1);
+        let second = (first + 1).min(num_frames - 1);
+        let t = frame - frame.floor();
+        blend_poses(&self.transforms_adjusted(first, adjustments), &self.transforms_adjusted(second, adjustments), t)
+    }
+}
+
+/// Per bone blend from `a` to `b`
+pub fn blend_poses(a: &[BoneTransform], b: &[BoneTransform], t: f32) -> Vec<BoneTransform> {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| BoneTransform {
+            position: math::lerp(a.position, b.position, t),
+            rotation: math::quaternion_slerp(a.rotation, b.rotation, t),
+        })
+        .collect()
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Result<i16, MdlError> {
+    match data.get(offset..(offset + 2)) {
+        Some(bytes) => Ok(i16::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(MdlError::UnexpectedEof),
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, MdlError> {
+    Ok(read_i16(data, offset)? as u16)
+}
+
+/// Expands one run length encoded `mstudioanimvalue_t` stream into one value per frame.
+///
+/// The stream is a list of runs, each starting with a `valid`/`total` byte pair followed by
+/// `valid` shorts. A run covers `total` frames and repeats its last value once the valid ones
+/// run out.
+pub fn decode_anim_values(data: &[u8], offset: usize, num_frames: usize) -> Result<Vec<i16>, MdlError> {
+    let mut values = Vec::with_capacity(num_frames);
+    let mut run_offset = offset;
+    while values.len() < num_frames {
+        let valid = *data.get(run_offset).ok_or(MdlError::UnexpectedEof)? as usize;
+        let total = *data.get(run_offset + 1).ok_or(MdlError::UnexpectedEof)? as usize;
+        if total == 0 || valid == 0 {
+            return Err(MdlError::BadCount { what: "animation run", count: total as i64 })
+        }
+        for itr in 0..total {
+            let value_itr = itr.min(valid - 1);
+            values.push(read_i16(data, run_offset + 2 + value_itr * 2)?);
+        }
+        run_offset += (valid + 1) * 2;
+    }
+    values.truncate(num_frames);
+    Ok(values)
+}
+
+impl MdlFile {
+    /// The bytes a sequence group's animation is stored in, group 0 lives in the model itself and
+    /// the rest in companion files once they are loaded
+    pub fn sequence_group_data(&self, group: usize) -> Option<&[u8]> {
+        if group == 0 {
+            return Some(&self.data)
+        }
+        self.sequence_group_files.get(group)?.as_deref()
+    }
+
+    /// The reference pose the model was built in, out of every bone's default values
+    pub fn bind_pose(&self) -> Vec<BoneTransform> {
+        self.bones.iter()
+            .map(|bone| BoneTransform {
+                position: [bone.value[0], bone.value[1], bone.value[2]],
+                rotation: math::angle_quaternion([bone.value[3], bone.value[4], bone.value[5]]),
+            })
+            .collect()
+    }
+
+    /// Bone to model space matrices for a pose, relies on parents coming before their children
+    pub fn bone_matrices(&self, pose: &[BoneTransform]) -> Vec<Mat3x4> {
+        let mut matrices: Vec<Mat3x4> = Vec::with_capacity(self.bones.len());
+        for (bone, transform) in self.bones.iter().zip(pose.iter()) {
+            let local = math::transform_matrix(transform.rotation, transform.position);
+            let matrix = match matrices.get(bone.parent.max(0) as usize) {
+                Some(parent) if bone.parent >= 0 => math::concat_transforms(parent, &local),
+                _ => local,
+            };
+            matrices.push(matrix);
+        }
+        matrices
+    }
+
+    /// Where every attachment is in model space for bones from `bone_matrices`
+    pub fn attachment_positions(&self, bones: &[Mat3x4]) -> Vec<Vec3> {
+        self.attachments.iter()
+            .map(|attachment| match bones.get(attachment.bone.max(0) as usize) {
+                Some(bone) => math::transform_point(bone, attachment.org),
+                None => attachment.org,
+            })
+            .collect()
+    }
+
+    /// Turns bone controller values, in the controller's own units (degrees for rotations), into
+    /// offsets for every bone axis they drive
+    pub fn controller_adjustments(&self, values: &[f32]) -> Vec<[f32; 6]> {
+        self.bones.iter()
+            .map(|bone| {
+                let mut adjustment = [0.0; 6];
+                for (axis, controller) in bone.bone_controller.iter().enumerate() {
+                    let (bone_controller, value) = match (self.bone_controllers.get(*controller as usize), values.get(*controller as usize)) {
+                        (Some(bone_controller), Some(value)) if *controller >= 0 => (bone_controller, *value),
+                        _ => continue,
+                    };
+                    adjustment[axis] = if bone_controller.controller_type & (motion::XR | motion::YR | motion::ZR) != 0 {
+                        value.to_radians()
+                    } else {
+                        value
+                    };
+                }
+                adjustment
+            })
+            .collect()
+    }
+
+    /// Decodes every frame of `blend` of `sequence`
+    pub fn decode_animation(&self, sequence: usize, blend: usize) -> Result<Animation, MdlError> {
+        let sequence = self.sequences.get(sequence)
+            .ok_or(MdlError::BadCount { what: "sequence", count: sequence as i64 })?;
+        if blend >= sequence.num_blends.max(1) as usize {
+            return Err(MdlError::BadCount { what: "blend", count: blend as i64 })
+        }
+        let group = sequence.seq_group.max(0) as usize;
+        let data = self.sequence_group_data(group).ok_or_else(|| MdlError::MissingSequenceGroup {
+            group,
+            name: self.sequence_groups.get(group).map(|group| group.name()).unwrap_or_default(),
+        })?;
+        let num_frames = sequence.num_frames.max(0) as usize;
+        let num_bones = self.bones.len();
+        let anim_base = sequence.anim_index as i64 + (blend * num_bones * ANIM_SIZE) as i64;
+        if sequence.anim_index < 0 || anim_base + (num_bones * ANIM_SIZE) as i64 > data.len() as i64 {
+            return Err(MdlError::OutOfBounds {
+                what: "sequence animation",
+                offset: anim_base,
+                size: (num_bones * ANIM_SIZE) as i64,
+                file_size: data.len(),
+            })
+        }
+
+        let mut frames = vec![vec![[0.0; 6]; num_bones]; num_frames];
+        for (bone_itr, bone) in self.bones.iter().enumerate() {
+            let anim_offset = anim_base as usize + bone_itr * ANIM_SIZE;
+            for axis in 0..6 {
+                let value_offset = read_u16(data, anim_offset + axis * 2)? as usize;
+                if value_offset == 0 {
+                    for frame in frames.iter_mut() {
+                        frame[bone_itr][axis] = bone.value[axis];
+                    }
+                    continue;
+                }
+                let values = decode_anim_values(data, anim_offset + value_offset, num_frames)?;
+                for (frame, value) in frames.iter_mut().zip(values.iter()) {
+                    frame[bone_itr][axis] = bone.value[axis] + *value as f32 * bone.scale[axis];
+                }
+            }
+        }
+        Ok(Animation { frames })
+    }
+}
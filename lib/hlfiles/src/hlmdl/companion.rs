@@ -0,0 +1,127 @@
+use bytebuffer::ByteBuffer;
+
+use super::{name_str, MdlError, MdlFile, MdlHeader};
+
+/// `studioseqhdr_t`, the header of a `*01.mdl` sequence group file
+pub const SEQUENCE_GROUP_HEADER_SIZE: usize = 76;
+
+/// A file a model keeps part of itself in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Companion {
+    /// `modelT.mdl`, the textures and skin table
+    Textures,
+    /// `model01.mdl` and up, the animation of one sequence group
+    SequenceGroup(usize),
+}
+
+/// Just the file name out of a path stored in a model, compared case insensitively
+fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or_default().to_lowercase()
+}
+
+impl MdlFile {
+    /// IDSQ sequence group files and IDST files with textures but no bones, both only make
+    /// sense next to another model
+    pub fn is_companion_file(buf: &[u8]) -> bool {
+        if buf.len() >= SEQUENCE_GROUP_HEADER_SIZE && &buf[0..4] == b"IDSQ" {
+            return true
+        }
+        match MdlHeader::from_bytes(buf) {
+            Ok(header) => &buf[0..4] == b"IDST" && header.num_bones == 0 && header.num_textures > 0,
+            Err(_) => false,
+        }
+    }
+
+    /// The model's own file name, out of the path in its header
+    pub fn file_name(&self) -> String {
+        file_name(&name_str(&self.header.name))
+    }
+
+    /// File name the engine would look for, "models/barney.mdl" wants "barneyT.mdl"
+    pub fn companion_name(&self, companion: Companion) -> String {
+        match companion {
+            Companion::Textures => {
+                let name = file_name(&name_str(&self.header.name));
+                format!("{}T.mdl", name.strip_suffix(".mdl").unwrap_or(&name))
+            },
+            Companion::SequenceGroup(group) => self.sequence_groups.get(group)
+                .map(|sequence_group| file_name(&sequence_group.name()))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Companion files this model refers to that haven't been loaded yet
+    pub fn missing_companions(&self) -> Vec<Companion> {
+        let mut missing = vec![];
+        if self.header.num_textures == 0 && self.texture_data.is_none() && self.body_parts.iter().any(|body_part| !body_part.models.is_empty()) {
+            missing.push(Companion::Textures);
+        }
+        for group in 1..self.sequence_groups.len() {
+            if self.sequence_group_data(group).is_none() {
+                missing.push(Companion::SequenceGroup(group));
+            }
+        }
+        missing
+    }
+
+    /// Works out which of the missing companions a file is, by name first and by content
+    /// when the file was renamed
+    pub fn companion_for(&self, name: &str, buf: &[u8]) -> Option<Companion> {
+        let name = file_name(name);
+        let missing = self.missing_companions();
+        if let Some(companion) = missing.iter().find(|companion| self.companion_name(**companion).to_lowercase() == name) {
+            return Some(*companion)
+        }
+        if buf.len() >= SEQUENCE_GROUP_HEADER_SIZE && &buf[0..4] == b"IDSQ" {
+            let seq_name = file_name(&name_str(&buf[8..72]));
+            return missing.into_iter().find(|companion| matches!(companion, Companion::SequenceGroup(_)) && self.companion_name(*companion) == seq_name)
+        }
+        if missing.contains(&Companion::Textures) && Self::is_companion_file(buf) {
+            return Some(Companion::Textures)
+        }
+        None
+    }
+
+    /// Takes the textures and skin table out of a `modelT.mdl`
+    pub fn load_texture_file(&mut self, buf: &[u8]) -> Result<(), MdlError> {
+        let mut reader = ByteBuffer::from_bytes(buf);
+        let header = MdlHeader::from_reader(&mut reader)?;
+        if &buf[0..4] != b"IDST" {
+            return Err(MdlError::BadMagic(buf[0..4].try_into().unwrap()))
+        }
+        self.textures = Self::get_textures_from_header(header, &mut reader)?;
+        self.skin_families = Self::read_skin_families(header, &mut reader)?;
+        self.texture_data = Some(buf.to_vec());
+        Ok(())
+    }
+
+    /// Keeps a `model01.mdl` around to decode the group's sequences out of
+    pub fn load_sequence_group(&mut self, group: usize, buf: Vec<u8>) -> Result<(), MdlError> {
+        if group == 0 || group >= self.sequence_groups.len() {
+            return Err(MdlError::BadCount { what: "sequence group", count: group as i64 })
+        }
+        if buf.len() < SEQUENCE_GROUP_HEADER_SIZE {
+            return Err(MdlError::UnexpectedEof)
+        }
+        if &buf[0..4] != b"IDSQ" {
+            return Err(MdlError::BadMagic(buf[0..4].try_into().unwrap()))
+        }
+        self.sequence_group_files.resize(self.sequence_groups.len(), None);
+        self.sequence_group_files[group] = Some(buf);
+        Ok(())
+    }
+
+    /// Loads a companion file, returns which one it was or `None` if it isn't one of ours
+    pub fn add_companion(&mut self, name: &str, buf: Vec<u8>) -> Result<Option<Companion>, MdlError> {
+        let companion = match self.companion_for(name, &buf) {
+            Some(companion) => companion,
+            None => return Ok(None),
+        };
+        match companion {
+            Companion::Textures => self.load_texture_file(&buf)?,
+            Companion::SequenceGroup(group) => self.load_sequence_group(group, buf)?,
+        }
+        Ok(Some(companion))
+    }
+}
+
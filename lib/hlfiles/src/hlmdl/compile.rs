@@ -0,0 +1,1288 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use super::smd::Smd;
+use super::{
+    motion, ColorRGB, MdlError, MdlFile, Texture, TextureFlags, TextureHeader, Vec3, ACTIVITIES,
+    ATTACHMENT_SIZE, BONE_CONTROLLER_SIZE, BONE_SIZE, EVENT_SIZE, HIT_BOX_SIZE, MDL_HEADER_SIZE,
+    SEQUENCE_GROUP_SIZE, SEQUENCE_SIZE, STUDIO_LOOPING, TEXTURE_HEADER_SIZE,
+};
+use super::anim::ANIM_SIZE;
+use super::geometry::{BODY_PART_SIZE, MESH_SIZE, MODEL_SIZE};
+use crate::math::{self, Mat3x4};
+
+/// Largest value the run length encoded animation stores
+const ANIM_VALUE_MAX: f32 = 32767.0;
+
+fn compile_error(message: String) -> MdlError {
+    MdlError::Compile(message)
+}
+
+/// A word or quoted string out of a QC
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {},
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            },
+            '"' => {
+                let mut text = String::new();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    text.push(c);
+                }
+                tokens.push(Token { text, line });
+            },
+            '{' | '}' => tokens.push(Token { text: c.to_string(), line }),
+            c => {
+                let mut text = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || *c == '{' || *c == '}' || *c == '"' {
+                        break;
+                    }
+                    text.push(*c);
+                    chars.next();
+                }
+                tokens.push(Token { text, line });
+            },
+        }
+    }
+    tokens
+}
+
+/// studiomdl's reading rules, outside of braces a command only goes on until the end of its line
+struct Tokens {
+    tokens: Vec<Token>,
+    pos: usize,
+    line: usize,
+}
+
+impl Tokens {
+    fn error(&self, message: &str) -> MdlError {
+        compile_error(format!("qc line {}: {}", self.line, message))
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        self.line = token.line;
+        Some(token.text.clone())
+    }
+
+    /// The next token if it is on the same line as the last one
+    fn next_on_line(&mut self) -> Option<String> {
+        if self.tokens.get(self.pos)?.line != self.line {
+            return None
+        }
+        self.next()
+    }
+
+    /// Next token within a command, anywhere when inside braces
+    fn next_in(&mut self, depth: i32) -> Option<String> {
+        if depth > 0 { self.next() } else { self.next_on_line() }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, MdlError> {
+        self.next().ok_or_else(|| self.error(&format!("expected {}", what)))
+    }
+
+    fn float(&mut self, what: &str) -> Result<f32, MdlError> {
+        let token = self.string(what)?;
+        token.parse().map_err(|_| self.error(&format!("expected {}, got \"{}\"", what, token)))
+    }
+
+    fn int(&mut self, what: &str) -> Result<i32, MdlError> {
+        let token = self.string(what)?;
+        token.parse().map_err(|_| self.error(&format!("expected {}, got \"{}\"", what, token)))
+    }
+
+    fn vec3(&mut self, what: &str) -> Result<Vec3, MdlError> {
+        Ok([self.float(what)?, self.float(what)?, self.float(what)?])
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), MdlError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(self.error(&format!("expected \"{}\", got \"{}\"", expected, token))),
+            None => Err(self.error(&format!("expected \"{}\"", expected))),
+        }
+    }
+
+    /// Skips the rest of a command we don't support, braces and all
+    fn skip_command(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.next_in(depth) {
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {},
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct QcModel {
+    name: String,
+    /// `None` for `blank`
+    file: Option<String>,
+    scale: f32,
+}
+
+#[derive(Debug, Clone)]
+struct QcBodyPart {
+    name: String,
+    models: Vec<QcModel>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct QcEvent {
+    event: i32,
+    frame: i32,
+    options: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct QcSequence {
+    name: String,
+    files: Vec<String>,
+    scale: f32,
+    fps: f32,
+    looping: bool,
+    activity: i32,
+    act_weight: i32,
+    motion_type: i32,
+    blends: Vec<(i32, f32, f32)>,
+    entry_node: i32,
+    exit_node: i32,
+    node_flags: i32,
+    events: Vec<QcEvent>,
+}
+
+#[derive(Debug, Clone)]
+struct QcController {
+    index: i32,
+    bone: String,
+    controller_type: i32,
+    start: f32,
+    end: f32,
+}
+
+#[derive(Debug, Clone)]
+struct QcHitBox {
+    group: i32,
+    bone: String,
+    bbmin: Vec3,
+    bbmax: Vec3,
+}
+
+#[derive(Debug, Clone)]
+struct QcAttachment {
+    index: i32,
+    bone: String,
+    org: Vec3,
+}
+
+/// The parts of a QC that end up in the model
+#[derive(Debug, Clone, Default)]
+struct Qc {
+    model_name: String,
+    eye_position: Vec3,
+    bbox: [Vec3; 2],
+    cbox: [Vec3; 2],
+    flags: i32,
+    body_parts: Vec<QcBodyPart>,
+    /// `[family][column]` texture names
+    texture_groups: Vec<Vec<String>>,
+    render_modes: Vec<(String, TextureFlags)>,
+    attachments: Vec<QcAttachment>,
+    controllers: Vec<QcController>,
+    hit_boxes: Vec<QcHitBox>,
+    sequences: Vec<QcSequence>,
+}
+
+impl Qc {
+    fn parse(text: &str) -> Result<Self, MdlError> {
+        let mut tokens = Tokens { tokens: tokenize(text), pos: 0, line: 1 };
+        let mut qc = Qc::default();
+        let mut scale = 1.0;
+        while let Some(command) = tokens.next() {
+            match command.to_lowercase().as_str() {
+                "$modelname" => qc.model_name = tokens.string("a model name")?,
+                "$scale" => scale = tokens.float("a scale")?,
+                "$eyeposition" => qc.eye_position = tokens.vec3("an eye position")?,
+                "$bbox" => qc.bbox = [tokens.vec3("a bbox")?, tokens.vec3("a bbox")?],
+                "$cbox" => qc.cbox = [tokens.vec3("a cbox")?, tokens.vec3("a cbox")?],
+                "$flags" => qc.flags = tokens.int("flags")?,
+                "$body" => {
+                    let name = tokens.string("a body name")?;
+                    let file = tokens.string("an SMD name")?;
+                    Self::model_options(&mut tokens)?;
+                    qc.body_parts.push(QcBodyPart { name: name.clone(), models: vec![QcModel { name: file.clone(), file: Some(file), scale }] });
+                },
+                "$bodygroup" => {
+                    let name = tokens.string("a bodygroup name")?;
+                    tokens.expect("{")?;
+                    let mut models = vec![];
+                    loop {
+                        match tokens.string("\"}\"")?.to_lowercase().as_str() {
+                            "}" => break,
+                            "studio" => {
+                                let file = tokens.string("an SMD name")?;
+                                Self::model_options(&mut tokens)?;
+                                models.push(QcModel { name: file.clone(), file: Some(file), scale });
+                            },
+                            "blank" => models.push(QcModel { name: String::from("blank"), file: None, scale }),
+                            other => return Err(tokens.error(&format!("unexpected \"{}\" in $bodygroup", other))),
+                        }
+                    }
+                    qc.body_parts.push(QcBodyPart { name, models });
+                },
+                "$texturegroup" => {
+                    tokens.string("a texture group name")?;
+                    tokens.expect("{")?;
+                    loop {
+                        match tokens.string("\"}\"")?.as_str() {
+                            "}" => break,
+                            "{" => {
+                                let mut family = vec![];
+                                loop {
+                                    match tokens.string("\"}\"")? {
+                                        token if token == "}" => break,
+                                        token => family.push(token),
+                                    }
+                                }
+                                qc.texture_groups.push(family);
+                            },
+                            other => return Err(tokens.error(&format!("unexpected \"{}\" in $texturegroup", other))),
+                        }
+                    }
+                },
+                "$texrendermode" => {
+                    let texture = tokens.string("a texture name")?;
+                    let mode = tokens.string("a render mode")?;
+                    let flag = TextureFlags::ALL.iter()
+                        .find(|(_, name)| name.eq_ignore_ascii_case(&mode))
+                        .map(|(flag, _)| *flag)
+                        .ok_or_else(|| tokens.error(&format!("unknown render mode \"{}\"", mode)))?;
+                    qc.render_modes.push((texture, flag));
+                },
+                "$attachment" => {
+                    let index = tokens.int("an attachment index")?;
+                    let bone = tokens.string("a bone name")?;
+                    let org = tokens.vec3("an attachment position")?;
+                    while tokens.next_on_line().is_some() {}
+                    qc.attachments.push(QcAttachment { index, bone, org });
+                },
+                "$controller" => {
+                    let index = match tokens.string("a controller index")?.to_lowercase().as_str() {
+                        "mouth" => 4,
+                        index => index.parse().map_err(|_| tokens.error("expected a controller index"))?,
+                    };
+                    let bone = tokens.string("a bone name")?;
+                    let type_name = tokens.string("a controller type")?;
+                    let controller_type = motion::from_name(&type_name);
+                    if controller_type == 0 {
+                        return Err(tokens.error(&format!("unknown controller type \"{}\"", type_name)))
+                    }
+                    let start = tokens.float("a controller start")?;
+                    let end = tokens.float("a controller end")?;
+                    qc.controllers.push(QcController { index, bone, controller_type, start, end });
+                },
+                "$hbox" => {
+                    let group = tokens.int("a hit group")?;
+                    let bone = tokens.string("a bone name")?;
+                    let bbmin = tokens.vec3("a hit box corner")?;
+                    let bbmax = tokens.vec3("a hit box corner")?;
+                    qc.hit_boxes.push(QcHitBox { group, bone, bbmin, bbmax });
+                },
+                // these move or rename bones, skipping them would build a different model
+                "$origin" | "$rotate" | "$renamebone" => {
+                    return Err(tokens.error(&format!("{} isn't supported", command)))
+                },
+                "$sequence" => {
+                    let mut sequence = Self::sequence(&mut tokens)?;
+                    sequence.scale = scale;
+                    qc.sequences.push(sequence);
+                },
+                // $cd, $cdtexture, $externaltextures, $sequencegroupsize and the like, everything
+                // ends up in one file and paths come from the files handed over
+                other if other.starts_with('$') => tokens.skip_command(),
+                other => return Err(tokens.error(&format!("expected a $command, got \"{}\"", other))),
+            }
+        }
+        if qc.body_parts.is_empty() {
+            return Err(compile_error(String::from("the qc has no $body or $bodygroup")))
+        }
+        if qc.sequences.is_empty() {
+            return Err(compile_error(String::from("the qc has no $sequence")))
+        }
+        Ok(qc)
+    }
+
+    /// `reverse` and `scale` after a reference would change the mesh, which isn't done yet, so
+    /// they are an error rather than a model that silently comes out different
+    fn model_options(tokens: &mut Tokens) -> Result<(), MdlError> {
+        match tokens.peek().map(|option| option.to_lowercase()) {
+            Some(option) if option == "reverse" || option == "scale" => {
+                tokens.next();
+                Err(tokens.error(&format!("the model option \"{}\" isn't supported", option)))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn sequence(tokens: &mut Tokens) -> Result<QcSequence, MdlError> {
+        let mut sequence = QcSequence {
+            name: tokens.string("a sequence name")?,
+            fps: 30.0,
+            ..Default::default()
+        };
+        let mut depth = 0;
+        while let Some(token) = tokens.next_in(depth) {
+            match token.to_lowercase().as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                "fps" => sequence.fps = tokens.float("fps")?,
+                "loop" => sequence.looping = true,
+                "blend" => {
+                    let type_name = tokens.string("a blend type")?;
+                    sequence.blends.push((motion::from_name(&type_name), tokens.float("a blend start")?, tokens.float("a blend end")?));
+                },
+                "node" => {
+                    sequence.entry_node = tokens.int("a node")?;
+                    sequence.exit_node = sequence.entry_node;
+                },
+                "transition" | "rtransition" => {
+                    sequence.entry_node = tokens.int("a node")?;
+                    sequence.exit_node = tokens.int("a node")?;
+                    sequence.node_flags = (token.to_lowercase() == "rtransition") as i32;
+                },
+                "event" => {
+                    let mut event = QcEvent { event: tokens.int("an event number")?, frame: tokens.int("an event frame")?, ..Default::default() };
+                    match tokens.next_on_line() {
+                        Some(token) if token == "}" => depth -= 1,
+                        Some(options) => event.options = options,
+                        None => {},
+                    }
+                    sequence.events.push(event);
+                },
+                "origin" | "rotate" | "scale" | "frame" => {
+                    return Err(tokens.error(&format!("the sequence option \"{}\" isn't supported", token)))
+                },
+                "pivot" => {
+                    for _ in 0..3 {
+                        tokens.int("a pivot")?;
+                    }
+                },
+                activity if activity.starts_with("act_") => {
+                    sequence.activity = ACTIVITIES.iter()
+                        .position(|name| name.eq_ignore_ascii_case(activity))
+                        .ok_or_else(|| tokens.error(&format!("unknown activity \"{}\"", token)))? as i32;
+                    sequence.act_weight = tokens.int("an activity weight")?;
+                },
+                other => match motion::from_name(other) {
+                    0 => sequence.files.push(token),
+                    bit => sequence.motion_type |= bit,
+                },
+            }
+        }
+        if sequence.files.is_empty() {
+            return Err(tokens.error(&format!("sequence \"{}\" has no animation file", sequence.name)))
+        }
+        Ok(sequence)
+    }
+}
+
+/// Looks files up the way the QC names them, ignoring case, folders and a missing extension
+struct Sources<'a> {
+    files: &'a [(String, Vec<u8>)],
+    /// Set for every file `find` hands out
+    used: &'a RefCell<Vec<bool>>,
+}
+
+impl<'a> Sources<'a> {
+    fn key(name: &str) -> String {
+        name.rsplit(['/', '\\']).next().unwrap_or_default().to_lowercase()
+    }
+
+    fn find(&self, name: &str, extension: &str) -> Result<&'a [u8], MdlError> {
+        let key = Self::key(name);
+        let with_extension = format!("{}.{}", key, extension);
+        let itr = self.files.iter()
+            .position(|(file_name, _)| {
+                let file_key = Self::key(file_name);
+                file_key == key || file_key == with_extension
+            })
+            .ok_or_else(|| compile_error(format!("{} was not among the files", name)))?;
+        self.used.borrow_mut()[itr] = true;
+        Ok(self.files[itr].1.as_slice())
+    }
+
+    fn smd(&self, name: &str) -> Result<Smd, MdlError> {
+        let file = self.find(name, "smd")?;
+        Smd::parse(&String::from_utf8_lossy(file)).map_err(|err| compile_error(format!("{}: {}", name, err)))
+    }
+}
+
+/// An 8 bit paletted BMP as is, anything else the image crate can read gets quantized
+fn load_texture(name: &str, file: &[u8]) -> Result<Texture, MdlError> {
+    let mut header_name = [0u8; 64];
+    let name_bytes = name.as_bytes();
+    header_name[..name_bytes.len().min(63)].copy_from_slice(&name_bytes[..name_bytes.len().min(63)]);
+    let header = TextureHeader { name: header_name, flags: TextureFlags::default(), width: 0, height: 0, index: 0 };
+
+    let read_u32 = |offset: usize| file.get(offset..(offset + 4)).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+    let read_u16 = |offset: usize| file.get(offset..(offset + 2)).map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()));
+    if file.starts_with(b"BM") && read_u16(28) == Some(8) && read_u32(30) == Some(0) {
+        let data_offset = read_u32(10).unwrap_or(0) as usize;
+        let info_size = read_u32(14).unwrap_or(0) as usize;
+        let width = read_u32(18).unwrap_or(0) as i32;
+        let height = read_u32(22).unwrap_or(0) as i32;
+        let colors = match read_u32(46).unwrap_or(0) {
+            0 => 256,
+            colors => colors.min(256) as usize,
+        };
+        let stride = (width.unsigned_abs() as usize + 3) & !3;
+        let palette_offset = 14 + info_size;
+        if width > 0 && height != 0 && data_offset + stride * height.unsigned_abs() as usize <= file.len() && palette_offset + colors * 4 <= file.len() {
+            let mut palette = [ColorRGB::new(0, 0, 0); 256];
+            for (itr, color) in palette.iter_mut().take(colors).enumerate() {
+                let bgr = &file[(palette_offset + itr * 4)..(palette_offset + itr * 4 + 3)];
+                *color = ColorRGB::new(bgr[2], bgr[1], bgr[0]);
+            }
+            let width = width as usize;
+            let mut raw_data = Vec::with_capacity(width * height.unsigned_abs() as usize);
+            for row in 0..height.unsigned_abs() as usize {
+                // positive heights are stored bottom up
+                let row = if height > 0 { height as usize - 1 - row } else { row };
+                let start = data_offset + row * stride;
+                raw_data.extend_from_slice(&file[start..(start + width)]);
+            }
+            return Ok(Texture {
+                raw_data,
+                palette,
+                header: TextureHeader { width: width as u32, height: height.unsigned_abs(), ..header },
+            })
+        }
+    }
+    let image = image::load_from_memory(file).map_err(|err| compile_error(format!("{}: {}", name, err)))?;
+    Ok(Texture::from_image(image.to_rgb8(), &header))
+}
+
+/// A bone of the compiled model
+#[derive(Debug, Clone)]
+struct CompileBone {
+    name: String,
+    parent: i32,
+    value: [f32; 6],
+    scale: [f32; 6],
+    bone_controller: [i32; 6],
+}
+
+/// A triangle corner with its vertex and normal already numbered
+#[derive(Debug, Clone, Copy)]
+struct CompileCorner {
+    vertex: usize,
+    normal: usize,
+    uv: [f32; 2],
+}
+
+#[derive(Debug, Clone)]
+struct CompileMesh {
+    skin_ref: usize,
+    num_norms: usize,
+    triangles: Vec<[CompileCorner; 3]>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CompileModel {
+    name: String,
+    /// In the space of `vertex_bones`
+    vertices: Vec<Vec3>,
+    vertex_bones: Vec<u8>,
+    normals: Vec<Vec3>,
+    normal_bones: Vec<u8>,
+    meshes: Vec<CompileMesh>,
+}
+
+/// A blend of a sequence, `[frame][bone]` like `Animation`
+type CompileFrames = Vec<Vec<[f32; 6]>>;
+
+fn name_bytes<const N: usize>(name: &str) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    let len = name.len().min(N - 1);
+    bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+    bytes
+}
+
+fn put_i32(buf: &mut Vec<u8>, value: i32) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_f32(buf: &mut Vec<u8>, value: f32) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_vec3(buf: &mut Vec<u8>, value: Vec3) {
+    for value in value {
+        put_f32(buf, value);
+    }
+}
+
+fn align(buf: &mut Vec<u8>) {
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+/// Runs of up to 255 frames, each with its trailing repeats left for the decoder to fill in
+fn encode_anim_values(values: &[i16]) -> Vec<u8> {
+    let mut encoded = vec![];
+    for run in values.chunks(255) {
+        let mut valid = run.len();
+        while valid > 1 && run[valid - 1] == run[valid - 2] {
+            valid -= 1;
+        }
+        encoded.push(valid as u8);
+        encoded.push(run.len() as u8);
+        for value in &run[..valid] {
+            encoded.extend(value.to_le_bytes());
+        }
+    }
+    encoded
+}
+
+/// Angles stored relative to the bind pose take the short way round
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
+}
+
+fn scale_position(mut value: [f32; 6], scale: f32) -> [f32; 6] {
+    for position in value.iter_mut().take(3) {
+        *position *= scale;
+    }
+    value
+}
+
+/// Bone to model space matrices for per bone values, parents before children
+fn value_matrices(bones: &[CompileBone], values: &[[f32; 6]]) -> Vec<Mat3x4> {
+    let mut matrices: Vec<Mat3x4> = Vec::with_capacity(bones.len());
+    for (bone, value) in bones.iter().zip(values.iter()) {
+        let local = math::transform_matrix(math::angle_quaternion([value[3], value[4], value[5]]), [value[0], value[1], value[2]]);
+        let matrix = match bone.parent {
+            parent if parent >= 0 => math::concat_transforms(&matrices[parent as usize], &local),
+            _ => local,
+        };
+        matrices.push(matrix);
+    }
+    matrices
+}
+
+struct Compiler<'a> {
+    sources: Sources<'a>,
+    bones: Vec<CompileBone>,
+    /// Textures in the order meshes first use them, then the extra ones of texture groups
+    texture_names: Vec<String>,
+}
+
+impl<'a> Compiler<'a> {
+    /// Maps an SMD's nodes onto the model's bones, adding the ones that are new
+    fn merge_bones(&mut self, smd: &Smd, file: &str, scale: f32) -> Result<Vec<usize>, MdlError> {
+        let mut node_bones: Vec<usize> = Vec::with_capacity(smd.nodes.len());
+        for (itr, node) in smd.nodes.iter().enumerate() {
+            if let Some(bone) = self.bones.iter().position(|bone| bone.name.eq_ignore_ascii_case(&node.name)) {
+                node_bones.push(bone);
+                continue
+            }
+            let parent = match node.parent {
+                parent if parent < 0 => -1,
+                parent => *node_bones.get(parent as usize)
+                    .ok_or_else(|| compile_error(format!("{}: bone {} comes before its parent", file, node.name)))? as i32,
+            };
+            self.bones.push(CompileBone {
+                name: node.name.clone(),
+                parent,
+                value: scale_position(smd.frames.first().and_then(|frame| frame.get(itr).copied()).unwrap_or_default(), scale),
+                scale: [0.0; 6],
+                bone_controller: [-1; 6],
+            });
+            node_bones.push(self.bones.len() - 1);
+        }
+        Ok(node_bones)
+    }
+
+    fn texture_index(&mut self, name: &str) -> usize {
+        match self.texture_names.iter().position(|texture| texture.eq_ignore_ascii_case(name)) {
+            Some(index) => index,
+            None => {
+                self.texture_names.push(name.to_string());
+                self.texture_names.len() - 1
+            },
+        }
+    }
+
+
+    fn reference(&mut self, model: &QcModel) -> Result<CompileModel, MdlError> {
+        let mut compiled = CompileModel { name: model.name.clone(), ..Default::default() };
+        let file = match &model.file {
+            Some(file) => file,
+            None => return Ok(compiled),
+        };
+        let smd = self.sources.smd(file)?;
+        if smd.triangles.is_empty() {
+            return Err(compile_error(format!("{} has no triangles", file)))
+        }
+        let node_bones = self.merge_bones(&smd, file, model.scale)?;
+        // vertices are relative to the SMD's own skeleton, which can differ from the model's
+        let smd_bones: Vec<CompileBone> = smd.nodes.iter()
+            .zip(node_bones.iter())
+            .map(|(node, bone)| CompileBone { parent: node.parent, ..self.bones[*bone].clone() })
+            .collect();
+        let smd_values: Vec<[f32; 6]> = (0..smd.nodes.len())
+            .map(|itr| scale_position(smd.frames.first().and_then(|frame| frame.get(itr).copied()).unwrap_or_default(), model.scale))
+            .collect();
+        let inverse: Vec<Mat3x4> = value_matrices(&smd_bones, &smd_values).iter().map(math::invert_transform).collect();
+
+        /// Normals are numbered per mesh, the engine lights them mesh by mesh
+        struct MeshBuild {
+            skin_ref: usize,
+            triangles: Vec<[CompileCorner; 3]>,
+            normal_map: HashMap<(usize, [u32; 3]), usize>,
+            normals: Vec<Vec3>,
+            normal_bones: Vec<u8>,
+        }
+        let mut vertex_map: HashMap<(usize, [u32; 3]), usize> = HashMap::new();
+        let mut meshes: Vec<MeshBuild> = vec![];
+        for triangle in smd.triangles.iter() {
+            let skin_ref = self.texture_index(&triangle.texture);
+            let mesh = match meshes.iter().position(|mesh| mesh.skin_ref == skin_ref) {
+                Some(mesh_itr) => &mut meshes[mesh_itr],
+                None => {
+                    meshes.push(MeshBuild { skin_ref, triangles: vec![], normal_map: HashMap::new(), normals: vec![], normal_bones: vec![] });
+                    meshes.last_mut().unwrap()
+                },
+            };
+            // studiomdl flips every triangle it reads
+            let corners = [2, 1, 0].map(|corner| {
+                let vertex = triangle.vertices[corner];
+                let bone = node_bones[vertex.bone] as u8;
+                let position = math::transform_point(&inverse[vertex.bone], math::scale(vertex.position, model.scale));
+                let normal = math::normalize(math::rotate_vector(&inverse[vertex.bone], vertex.normal));
+                let vertex_itr = *vertex_map.entry((vertex.bone, position.map(f32::to_bits))).or_insert_with(|| {
+                    compiled.vertices.push(position);
+                    compiled.vertex_bones.push(bone);
+                    compiled.vertices.len() - 1
+                });
+                let normal_itr = *mesh.normal_map.entry((vertex.bone, normal.map(f32::to_bits))).or_insert_with(|| {
+                    mesh.normals.push(normal);
+                    mesh.normal_bones.push(bone);
+                    mesh.normals.len() - 1
+                });
+                CompileCorner { vertex: vertex_itr, normal: normal_itr, uv: vertex.uv }
+            });
+            mesh.triangles.push(corners);
+        }
+        for mesh in meshes {
+            let base = compiled.normals.len();
+            compiled.normals.extend(mesh.normals.iter());
+            compiled.normal_bones.extend(mesh.normal_bones.iter());
+            compiled.meshes.push(CompileMesh {
+                skin_ref: mesh.skin_ref,
+                num_norms: mesh.normals.len(),
+                triangles: mesh.triangles.iter()
+                    .map(|triangle| triangle.map(|corner| CompileCorner { normal: base + corner.normal, ..corner }))
+                    .collect(),
+            });
+        }
+        if compiled.vertices.len() > i16::MAX as usize || compiled.normals.len() > i16::MAX as usize {
+            return Err(compile_error(format!("{} has more vertices than a model can hold", file)))
+        }
+        Ok(compiled)
+    }
+
+    /// Every frame of an animation SMD, bones it leaves out get filled in later
+    fn animation(&mut self, file: &str, scale: f32) -> Result<CompileFrames, MdlError> {
+        let smd = self.sources.smd(file)?;
+        if smd.frames.is_empty() {
+            return Err(compile_error(format!("{} has no skeleton frames", file)))
+        }
+        let node_bones = self.merge_bones(&smd, file, scale)?;
+        Ok(smd.frames.iter()
+            .map(|frame| {
+                let mut values: Vec<[f32; 6]> = self.bones.iter().map(|bone| bone.value).collect();
+                for (node, value) in frame.iter().enumerate() {
+                    values[node_bones[node]] = scale_position(*value, scale);
+                }
+                values
+            })
+            .collect())
+    }
+}
+
+/// Takes the root bones' movement along the `LX`/`LY`/`LZ` axes out of the frames, returns the
+/// movement over the whole sequence
+fn extract_motion(bones: &[CompileBone], blends: &mut [CompileFrames], motion_type: i32) -> Vec3 {
+    let mut movement = [0.0; 3];
+    let root = match bones.iter().position(|bone| bone.parent < 0) {
+        Some(root) => root,
+        None => return movement,
+    };
+    let num_frames = blends.first().map(|frames| frames.len()).unwrap_or(0);
+    if num_frames < 2 {
+        return movement
+    }
+    for (axis, bit) in [motion::LX, motion::LY, motion::LZ].iter().enumerate() {
+        if motion_type & bit != 0 {
+            movement[axis] = blends[0][num_frames - 1][root][axis] - blends[0][0][root][axis];
+        }
+    }
+    for frames in blends.iter_mut() {
+        for (frame_itr, frame) in frames.iter_mut().enumerate() {
+            let t = frame_itr as f32 / (num_frames - 1) as f32;
+            for (bone, value) in bones.iter().zip(frame.iter_mut()) {
+                if bone.parent < 0 {
+                    for axis in 0..3 {
+                        value[axis] -= movement[axis] * t;
+                    }
+                }
+            }
+        }
+    }
+    movement
+}
+
+/// Offset of an animation value from the bind pose
+fn anim_delta(value: f32, default: f32, axis: usize) -> f32 {
+    if axis < 3 { value - default } else { wrap_angle(value - default) }
+}
+
+/// Everything that goes in the file, worked out and waiting to be laid out
+struct Compiled<'a> {
+    qc: &'a Qc,
+    bones: Vec<CompileBone>,
+    /// `(bone, controller)`
+    controllers: Vec<(usize, &'a QcController)>,
+    /// `(bone, group, bbmin, bbmax)`
+    hit_boxes: Vec<(usize, i32, Vec3, Vec3)>,
+    /// `(bone, org)`
+    attachments: Vec<(usize, Vec3)>,
+    sequences: Vec<CompileSequence<'a>>,
+    body_parts: Vec<(&'a str, Vec<CompileModel>)>,
+    textures: Vec<Texture>,
+    num_skin_ref: usize,
+    skin_families: Vec<Vec<usize>>,
+}
+
+/// A sequence ready to be written
+struct CompileSequence<'a> {
+    qc: &'a QcSequence,
+    blends: Vec<CompileFrames>,
+    linear_movement: Vec3,
+    bbox: [Vec3; 2],
+}
+
+impl MdlFile {
+    /// Builds a model out of a QC and the SMDs and bitmaps it names. `files` are
+    /// `(name, contents)` and get matched to the QC by file name alone. Everything ends up in the
+    /// one file, there is no `modelT.mdl` or sequence group files.
+    pub fn compile(qc: &str, files: &[(String, Vec<u8>)]) -> Result<MdlFile, MdlError> {
+        Self::compile_tracked(qc, files).0
+    }
+
+    /// Like `compile`, and says which of `files` the QC used, as far as it got when it fails
+    pub fn compile_tracked(qc: &str, files: &[(String, Vec<u8>)]) -> (Result<MdlFile, MdlError>, Vec<bool>) {
+        let used = RefCell::new(vec![false; files.len()]);
+        let mdl_file = Self::compile_from(qc, Sources { files, used: &used });
+        (mdl_file, used.into_inner())
+    }
+
+    fn compile_from(qc: &str, sources: Sources) -> Result<MdlFile, MdlError> {
+        let qc = Qc::parse(qc)?;
+        let mut compiler = Compiler { sources, bones: vec![], texture_names: vec![] };
+
+        let mut body_parts = vec![];
+        for body_part in qc.body_parts.iter() {
+            let models = body_part.models.iter()
+                .map(|model| compiler.reference(model))
+                .collect::<Result<Vec<_>, _>>()?;
+            body_parts.push((body_part.name.as_str(), models));
+        }
+        if compiler.bones.is_empty() {
+            return Err(compile_error(String::from("none of the bodygroups have a reference")))
+        }
+
+        // family 0 is the textures the meshes use, texture groups swap columns of it
+        let num_skin_ref = compiler.texture_names.len();
+        let mut skin_families = vec![(0..num_skin_ref).collect::<Vec<usize>>()];
+        if let Some(first) = qc.texture_groups.first() {
+            let columns: Vec<usize> = first.iter().map(|name| compiler.texture_index(name)).collect();
+            for family in qc.texture_groups.iter().skip(1) {
+                if family.len() != columns.len() {
+                    return Err(compile_error(String::from("the rows of $texturegroup aren't all the same length")))
+                }
+                let mut skins = skin_families[0].clone();
+                for (column, name) in columns.iter().zip(family.iter()) {
+                    let texture = compiler.texture_index(name);
+                    if let Some(skin) = skins.get_mut(*column) {
+                        *skin = texture;
+                    }
+                }
+                skin_families.push(skins);
+            }
+        }
+        let mut textures = vec![];
+        for name in compiler.texture_names.iter() {
+            let mut texture = load_texture(name, compiler.sources.find(name, "bmp")?)?;
+            // studiomdl goes by name for chrome
+            if name.to_lowercase().contains("chrome") {
+                texture.header.flags.insert(TextureFlags::CHROME);
+            }
+            for (texture_name, flag) in qc.render_modes.iter() {
+                if texture_name.eq_ignore_ascii_case(name) {
+                    texture.header.flags.insert(*flag);
+                }
+            }
+            textures.push(texture);
+        }
+
+        let mut sequences = vec![];
+        for sequence in qc.sequences.iter() {
+            let mut blends = sequence.files.iter()
+                .map(|file| compiler.animation(file, sequence.scale))
+                .collect::<Result<Vec<_>, _>>()?;
+            // blends share a frame count
+            let num_frames = blends.iter().map(|frames| frames.len()).min().unwrap_or(0);
+            for frames in blends.iter_mut() {
+                frames.truncate(num_frames);
+            }
+            sequences.push(CompileSequence { qc: sequence, blends, linear_movement: [0.0; 3], bbox: [[0.0; 3]; 2] });
+        }
+        let mut bones = compiler.bones;
+        if bones.len() > u8::MAX as usize {
+            return Err(compile_error(format!("{} bones, vertices can only point at 256", bones.len())))
+        }
+        // later SMDs may have added bones the earlier frames don't have
+        for sequence in sequences.iter_mut() {
+            for frame in sequence.blends.iter_mut().flatten() {
+                let filled = frame.len();
+                frame.extend(bones[filled..].iter().map(|bone| bone.value));
+            }
+            sequence.linear_movement = extract_motion(&bones, &mut sequence.blends, sequence.qc.motion_type);
+        }
+
+        let mut controllers = vec![];
+        for controller in qc.controllers.iter() {
+            let bone = bones.iter().position(|bone| bone.name.eq_ignore_ascii_case(&controller.bone))
+                .ok_or_else(|| compile_error(format!("$controller bone \"{}\" isn't in the skeleton", controller.bone)))?;
+            let axis = [motion::X, motion::Y, motion::Z, motion::XR, motion::YR, motion::ZR].iter()
+                .position(|bit| controller.controller_type & bit != 0)
+                .ok_or_else(|| compile_error(format!("$controller {} doesn't move along an axis", controller.index)))?;
+            bones[bone].bone_controller[axis] = controllers.len() as i32;
+            controllers.push((bone, controller));
+        }
+
+        for (bone_itr, bone) in bones.iter_mut().enumerate() {
+            for axis in 0..6 {
+                let max = sequences.iter()
+                    .flat_map(|sequence| sequence.blends.iter().flatten())
+                    .map(|frame| anim_delta(frame[bone_itr][axis], bone.value[axis], axis).abs())
+                    .fold(0.0, f32::max);
+                bone.scale[axis] = if max > 0.0 {
+                    max / ANIM_VALUE_MAX
+                } else if axis < 3 {
+                    1.0 / 32.0
+                } else {
+                    std::f32::consts::PI / ANIM_VALUE_MAX
+                };
+            }
+        }
+
+        let models: Vec<&CompileModel> = body_parts.iter().flat_map(|(_, models)| models.iter()).collect();
+        for sequence in sequences.iter_mut() {
+            let mut bbox = [[f32::MAX; 3], [f32::MIN; 3]];
+            for frame in sequence.blends[0].iter() {
+                let matrices = value_matrices(&bones, frame);
+                for model in models.iter() {
+                    for (vertex, bone) in model.vertices.iter().zip(model.vertex_bones.iter()) {
+                        let position = math::transform_point(&matrices[*bone as usize], *vertex);
+                        for axis in 0..3 {
+                            bbox[0][axis] = bbox[0][axis].min(position[axis]);
+                            bbox[1][axis] = bbox[1][axis].max(position[axis]);
+                        }
+                    }
+                }
+            }
+            if bbox[0][0] <= bbox[1][0] {
+                sequence.bbox = bbox;
+            }
+        }
+
+        let hit_boxes: Vec<(usize, i32, Vec3, Vec3)> = if qc.hit_boxes.is_empty() {
+            // one box per bone around the vertices it moves, like studiomdl does
+            (0..bones.len())
+                .filter_map(|bone| {
+                    let mut bbox = [[f32::MAX; 3], [f32::MIN; 3]];
+                    for model in models.iter() {
+                        for (vertex, _) in model.vertices.iter().zip(model.vertex_bones.iter()).filter(|(_, vertex_bone)| **vertex_bone as usize == bone) {
+                            for axis in 0..3 {
+                                bbox[0][axis] = bbox[0][axis].min(vertex[axis]);
+                                bbox[1][axis] = bbox[1][axis].max(vertex[axis]);
+                            }
+                        }
+                    }
+                    if bbox[0][0] > bbox[1][0] { None } else { Some((bone, 0, bbox[0], bbox[1])) }
+                })
+                .collect()
+        } else {
+            qc.hit_boxes.iter()
+                .map(|hit_box| {
+                    let bone = bones.iter().position(|bone| bone.name.eq_ignore_ascii_case(&hit_box.bone))
+                        .ok_or_else(|| compile_error(format!("$hbox bone \"{}\" isn't in the skeleton", hit_box.bone)))?;
+                    Ok((bone, hit_box.group, hit_box.bbmin, hit_box.bbmax))
+                })
+                .collect::<Result<_, MdlError>>()?
+        };
+
+        let mut attachments = qc.attachments.clone();
+        attachments.sort_by_key(|attachment| attachment.index);
+        let attachments = attachments.iter()
+            .map(|attachment| {
+                let bone = bones.iter().position(|bone| bone.name.eq_ignore_ascii_case(&attachment.bone))
+                    .ok_or_else(|| compile_error(format!("$attachment bone \"{}\" isn't in the skeleton", attachment.bone)))?;
+                Ok((bone, attachment.org))
+            })
+            .collect::<Result<Vec<_>, MdlError>>()?;
+
+        let compiled = Compiled {
+            qc: &qc,
+            bones,
+            controllers,
+            hit_boxes,
+            attachments,
+            sequences,
+            body_parts,
+            textures,
+            num_skin_ref,
+            skin_families,
+        };
+        MdlFile::from_bytes(&compiled.write()?)
+    }
+}
+
+impl<'a> Compiled<'a> {
+    /// Lays the model out the way studiomdl does, everything a struct points at is written
+    /// before it so offsets are known, the header goes in last
+    fn write(&self) -> Result<Vec<u8>, MdlError> {
+        let mut buf = vec![0u8; MDL_HEADER_SIZE];
+
+        let bone_index = buf.len();
+        for bone in self.bones.iter() {
+            buf.extend(name_bytes::<32>(&bone.name));
+            put_i32(&mut buf, bone.parent);
+            put_i32(&mut buf, 0);
+            for controller in bone.bone_controller {
+                put_i32(&mut buf, controller);
+            }
+            for value in bone.value.iter().chain(bone.scale.iter()) {
+                put_f32(&mut buf, *value);
+            }
+        }
+        debug_assert_eq!(buf.len() - bone_index, self.bones.len() * BONE_SIZE);
+
+        let bone_controller_index = buf.len();
+        for (bone, controller) in self.controllers.iter() {
+            put_i32(&mut buf, *bone as i32);
+            put_i32(&mut buf, controller.controller_type);
+            put_f32(&mut buf, controller.start);
+            put_f32(&mut buf, controller.end);
+            // where 0 sits in the controller's 0-255 range
+            let range = controller.end - controller.start;
+            let rest = if range == 0.0 { 0.0 } else { -controller.start / range * 255.0 };
+            put_i32(&mut buf, rest.round().clamp(0.0, 255.0) as i32);
+            put_i32(&mut buf, controller.index);
+        }
+        debug_assert_eq!(buf.len() - bone_controller_index, self.controllers.len() * BONE_CONTROLLER_SIZE);
+
+        let hit_box_index = buf.len();
+        for (bone, group, bbmin, bbmax) in self.hit_boxes.iter() {
+            put_i32(&mut buf, *bone as i32);
+            put_i32(&mut buf, *group);
+            put_vec3(&mut buf, *bbmin);
+            put_vec3(&mut buf, *bbmax);
+        }
+        debug_assert_eq!(buf.len() - hit_box_index, self.hit_boxes.len() * HIT_BOX_SIZE);
+
+        let attachment_index = buf.len();
+        for (bone, org) in self.attachments.iter() {
+            buf.extend([0u8; 32]);
+            put_i32(&mut buf, 0);
+            put_i32(&mut buf, *bone as i32);
+            put_vec3(&mut buf, *org);
+            buf.extend([0u8; 36]);
+        }
+        debug_assert_eq!(buf.len() - attachment_index, self.attachments.len() * ATTACHMENT_SIZE);
+
+        let mut event_indexes = vec![];
+        for sequence in self.sequences.iter() {
+            event_indexes.push(buf.len());
+            for event in sequence.qc.events.iter() {
+                put_i32(&mut buf, event.frame);
+                put_i32(&mut buf, event.event);
+                put_i32(&mut buf, 0);
+                buf.extend(name_bytes::<64>(&event.options));
+            }
+            debug_assert_eq!(buf.len() - event_indexes.last().unwrap(), sequence.qc.events.len() * EVENT_SIZE);
+        }
+
+        let mut anim_indexes = vec![];
+        for sequence in self.sequences.iter() {
+            align(&mut buf);
+            let anim_index = buf.len();
+            anim_indexes.push(anim_index);
+            // the mstudioanim_t of every blend come first, their values after
+            buf.resize(anim_index + sequence.blends.len() * self.bones.len() * ANIM_SIZE, 0);
+            for (blend_itr, frames) in sequence.blends.iter().enumerate() {
+                for (bone_itr, bone) in self.bones.iter().enumerate() {
+                    let anim_offset = anim_index + (blend_itr * self.bones.len() + bone_itr) * ANIM_SIZE;
+                    for axis in 0..6 {
+                        let values: Vec<i16> = frames.iter()
+                            .map(|frame| (anim_delta(frame[bone_itr][axis], bone.value[axis], axis) / bone.scale[axis]).round() as i16)
+                            .collect();
+                        if values.iter().all(|value| *value == 0) {
+                            continue
+                        }
+                        let value_offset = buf.len() - anim_offset;
+                        if value_offset > u16::MAX as usize {
+                            return Err(compile_error(format!("sequence \"{}\" has too much animation for one block", sequence.qc.name)))
+                        }
+                        buf[(anim_offset + axis * 2)..(anim_offset + axis * 2 + 2)].copy_from_slice(&(value_offset as u16).to_le_bytes());
+                        buf.extend(encode_anim_values(&values));
+                    }
+                }
+            }
+        }
+        align(&mut buf);
+
+        let seq_index = buf.len();
+        for (itr, sequence) in self.sequences.iter().enumerate() {
+            let qc = sequence.qc;
+            buf.extend(name_bytes::<32>(&qc.name));
+            put_f32(&mut buf, qc.fps);
+            put_i32(&mut buf, if qc.looping { STUDIO_LOOPING } else { 0 });
+            put_i32(&mut buf, qc.activity);
+            put_i32(&mut buf, qc.act_weight);
+            put_i32(&mut buf, qc.events.len() as i32);
+            put_i32(&mut buf, event_indexes[itr] as i32);
+            put_i32(&mut buf, sequence.blends[0].len() as i32);
+            // no pivots
+            put_i32(&mut buf, 0);
+            put_i32(&mut buf, 0);
+            put_i32(&mut buf, qc.motion_type);
+            put_i32(&mut buf, 0);
+            put_vec3(&mut buf, sequence.linear_movement);
+            put_i32(&mut buf, 0);
+            put_i32(&mut buf, 0);
+            put_vec3(&mut buf, sequence.bbox[0]);
+            put_vec3(&mut buf, sequence.bbox[1]);
+            put_i32(&mut buf, sequence.blends.len() as i32);
+            put_i32(&mut buf, anim_indexes[itr] as i32);
+            for axis in 0..2 {
+                put_i32(&mut buf, qc.blends.get(axis).map(|blend| blend.0).unwrap_or(0));
+            }
+            for axis in 0..2 {
+                put_f32(&mut buf, qc.blends.get(axis).map(|blend| blend.1).unwrap_or(0.0));
+            }
+            for axis in 0..2 {
+                put_f32(&mut buf, qc.blends.get(axis).map(|blend| blend.2).unwrap_or(0.0));
+            }
+            put_i32(&mut buf, 0);
+            // everything is in group 0
+            put_i32(&mut buf, 0);
+            put_i32(&mut buf, qc.entry_node);
+            put_i32(&mut buf, qc.exit_node);
+            put_i32(&mut buf, qc.node_flags);
+            put_i32(&mut buf, 0);
+        }
+        debug_assert_eq!(buf.len() - seq_index, self.sequences.len() * SEQUENCE_SIZE);
+
+        let seq_group_index = buf.len();
+        buf.extend(name_bytes::<32>("default"));
+        buf.extend([0u8; 64]);
+        put_i32(&mut buf, 0);
+        put_i32(&mut buf, 0);
+        debug_assert_eq!(buf.len() - seq_group_index, SEQUENCE_GROUP_SIZE);
+
+        // every node can go straight to every other one
+        let num_transitions = self.sequences.iter().map(|sequence| sequence.qc.entry_node.max(sequence.qc.exit_node)).max().unwrap_or(0).max(0) as usize;
+        let transition_index = buf.len();
+        for _ in 0..num_transitions {
+            buf.extend((1..=num_transitions).map(|node| node as u8));
+        }
+        align(&mut buf);
+
+        let mut body_part_models = vec![];
+        for (_, models) in self.body_parts.iter() {
+            let mut model_structs = vec![];
+            for model in models.iter() {
+                let vert_info_index = buf.len();
+                buf.extend(model.vertex_bones.iter());
+                let norm_info_index = buf.len();
+                buf.extend(model.normal_bones.iter());
+                align(&mut buf);
+                let vert_index = buf.len();
+                for vertex in model.vertices.iter() {
+                    put_vec3(&mut buf, *vertex);
+                }
+                let norm_index = buf.len();
+                for normal in model.normals.iter() {
+                    put_vec3(&mut buf, *normal);
+                }
+                let mut mesh_structs = vec![];
+                let mut mesh_norm_index = norm_index;
+                for mesh in model.meshes.iter() {
+                    let (width, height) = match self.textures.get(mesh.skin_ref) {
+                        Some(texture) => (texture.header.width as f32, texture.header.height as f32),
+                        None => (1.0, 1.0),
+                    };
+                    let tri_index = buf.len();
+                    for triangle in mesh.triangles.iter() {
+                        buf.extend(3i16.to_le_bytes());
+                        for corner in triangle.iter() {
+                            let s = (corner.uv[0] * width).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                            let t = ((1.0 - corner.uv[1]) * height).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                            for value in [corner.vertex as i16, corner.normal as i16, s, t] {
+                                buf.extend(value.to_le_bytes());
+                            }
+                        }
+                    }
+                    buf.extend(0i16.to_le_bytes());
+                    align(&mut buf);
+                    mesh_structs.push((mesh.triangles.len(), tri_index, mesh.skin_ref, mesh.num_norms, mesh_norm_index));
+                    mesh_norm_index += mesh.num_norms * 12;
+                }
+                let mesh_index = buf.len();
+                for (num_tris, tri_index, skin_ref, num_norms, norm_index) in mesh_structs {
+                    for value in [num_tris, tri_index, skin_ref, num_norms, norm_index] {
+                        put_i32(&mut buf, value as i32);
+                    }
+                }
+                debug_assert_eq!(buf.len() - mesh_index, model.meshes.len() * MESH_SIZE);
+                model_structs.push((model, mesh_index, vert_info_index, vert_index, norm_info_index, norm_index));
+            }
+
+            let model_index = buf.len();
+            for (model, mesh_index, vert_info_index, vert_index, norm_info_index, norm_index) in model_structs {
+                buf.extend(name_bytes::<64>(&model.name));
+                put_i32(&mut buf, 0);
+                let radius = model.vertices.iter().map(|vertex| math::length(*vertex)).fold(0.0, f32::max);
+                put_f32(&mut buf, radius);
+                put_i32(&mut buf, model.meshes.len() as i32);
+                put_i32(&mut buf, mesh_index as i32);
+                put_i32(&mut buf, model.vertices.len() as i32);
+                put_i32(&mut buf, vert_info_index as i32);
+                put_i32(&mut buf, vert_index as i32);
+                put_i32(&mut buf, model.normals.len() as i32);
+                put_i32(&mut buf, norm_info_index as i32);
+                put_i32(&mut buf, norm_index as i32);
+                put_i32(&mut buf, 0);
+                put_i32(&mut buf, 0);
+            }
+            debug_assert_eq!(buf.len() - model_index, models.len() * MODEL_SIZE);
+            body_part_models.push(model_index);
+        }
+        let body_part_index = buf.len();
+        let mut base = 1;
+        for ((name, models), model_index) in self.body_parts.iter().zip(body_part_models.iter()) {
+            buf.extend(name_bytes::<64>(name));
+            put_i32(&mut buf, models.len() as i32);
+            put_i32(&mut buf, base);
+            put_i32(&mut buf, *model_index as i32);
+            base *= models.len().max(1) as i32;
+        }
+        debug_assert_eq!(buf.len() - body_part_index, self.body_parts.len() * BODY_PART_SIZE);
+
+        let mut texture_data = vec![];
+        for texture in self.textures.iter() {
+            align(&mut buf);
+            texture_data.push(buf.len());
+            buf.extend(texture.raw_data.iter());
+            buf.extend(texture.palette_bytes());
+        }
+        align(&mut buf);
+        let texture_index = buf.len();
+        for (texture, index) in self.textures.iter().zip(texture_data.iter()) {
+            buf.extend(texture.header.name);
+            for value in [texture.header.flags.bits(), texture.header.width, texture.header.height, *index as u32] {
+                buf.extend(value.to_le_bytes());
+            }
+        }
+        debug_assert_eq!(buf.len() - texture_index, self.textures.len() * TEXTURE_HEADER_SIZE);
+        let skin_index = buf.len();
+        for family in self.skin_families.iter() {
+            for texture in family.iter() {
+                buf.extend((*texture as i16).to_le_bytes());
+            }
+        }
+        align(&mut buf);
+
+        let qc = self.qc;
+        let mut header = b"IDST".to_vec();
+        put_i32(&mut header, 10);
+        header.extend(name_bytes::<64>(&qc.model_name));
+        put_i32(&mut header, buf.len() as i32);
+        put_vec3(&mut header, qc.eye_position);
+        put_vec3(&mut header, qc.bbox[0]);
+        put_vec3(&mut header, qc.bbox[1]);
+        put_vec3(&mut header, qc.cbox[0]);
+        put_vec3(&mut header, qc.cbox[1]);
+        put_i32(&mut header, qc.flags);
+        for value in [
+            self.bones.len(), bone_index,
+            self.controllers.len(), bone_controller_index,
+            self.hit_boxes.len(), hit_box_index,
+            self.sequences.len(), seq_index,
+            1, seq_group_index,
+            self.textures.len(), texture_index, texture_data.first().copied().unwrap_or(texture_index),
+            self.num_skin_ref, self.skin_families.len(), skin_index,
+            self.body_parts.len(), body_part_index,
+            self.attachments.len(), attachment_index,
+            0, 0, 0, 0,
+            num_transitions, transition_index,
+        ] {
+            put_i32(&mut header, value as i32);
+        }
+        buf[..MDL_HEADER_SIZE].copy_from_slice(&header);
+        Ok(buf)
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt::Write as _;
+
+use super::{motion, name_str, MdlError, MdlFile, Texture, TextureFlags};
+use crate::math;
+
+/// `Activity` from the SDK's activity.h, indexed by value
+pub const ACTIVITIES: [&str; 77] = [
+    "ACT_RESET", "ACT_IDLE", "ACT_GUARD", "ACT_WALK", "ACT_RUN", "ACT_FLY", "ACT_SWIM", "ACT_HOP",
+    "ACT_LEAP", "ACT_FALL", "ACT_LAND", "ACT_STRAFE_LEFT", "ACT_STRAFE_RIGHT", "ACT_ROLL_LEFT",
+    "ACT_ROLL_RIGHT", "ACT_TURN_LEFT", "ACT_TURN_RIGHT", "ACT_CROUCH", "ACT_CROUCHIDLE", "ACT_STAND",
+    "ACT_USE", "ACT_SIGNAL1", "ACT_SIGNAL2", "ACT_SIGNAL3", "ACT_TWITCH", "ACT_COWER",
+    "ACT_SMALL_FLINCH", "ACT_BIG_FLINCH", "ACT_RANGE_ATTACK1", "ACT_RANGE_ATTACK2",
+    "ACT_MELEE_ATTACK1", "ACT_MELEE_ATTACK2", "ACT_RELOAD", "ACT_ARM", "ACT_DISARM", "ACT_EAT",
+    "ACT_DIESIMPLE", "ACT_DIEBACKWARD", "ACT_DIEFORWARD", "ACT_DIEVIOLENT", "ACT_BARNACLE_HIT",
+    "ACT_BARNACLE_PULL", "ACT_BARNACLE_CHOMP", "ACT_BARNACLE_CHEW", "ACT_SLEEP", "ACT_INSPECT_FLOOR",
+    "ACT_INSPECT_WALL", "ACT_IDLE_ANGRY", "ACT_WALK_HURT", "ACT_RUN_HURT", "ACT_HOVER", "ACT_GLIDE",
+    "ACT_FLY_LEFT", "ACT_FLY_RIGHT", "ACT_DETECT_SCENT", "ACT_SNIFF", "ACT_BITE",
+    "ACT_THREAT_DISPLAY", "ACT_FEAR_DISPLAY", "ACT_EXCITED", "ACT_SPECIAL_ATTACK1",
+    "ACT_SPECIAL_ATTACK2", "ACT_COMBAT_IDLE", "ACT_WALK_SCARED", "ACT_RUN_SCARED",
+    "ACT_VICTORY_DANCE", "ACT_DIE_HEADSHOT", "ACT_DIE_CHESTSHOT", "ACT_DIE_GUTSHOT",
+    "ACT_DIE_BACKSHOT", "ACT_FLINCH_HEAD", "ACT_FLINCH_CHEST", "ACT_FLINCH_STOMACH",
+    "ACT_FLINCH_LEFTARM", "ACT_FLINCH_RIGHTARM", "ACT_FLINCH_LEFTLEG", "ACT_FLINCH_RIGHTLEG",
+];
+
+/// The `$texrendermode` names studiomdl understands
+const RENDER_MODES: [(TextureFlags, &str); 5] = [
+    (TextureFlags::MASKED, "masked"),
+    (TextureFlags::ADDITIVE, "additive"),
+    (TextureFlags::FULLBRIGHT, "fullbright"),
+    (TextureFlags::FLATSHADE, "flatshade"),
+    (TextureFlags::CHROME, "chrome"),
+];
+
+pub fn activity_name(activity: i32) -> Option<&'static str> {
+    ACTIVITIES.get(usize::try_from(activity).ok()?).copied()
+}
+
+/// A name out of the model that is safe to use as a file name
+fn file_stem(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name = name.strip_suffix(".smd").unwrap_or(name);
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || "_-.".contains(c) { c } else { '_' }).collect()
+}
+
+/// Texture names are usually already "something.bmp"
+fn bitmap_name(texture: &Texture) -> String {
+    let name = file_stem(&name_str(&texture.header.name));
+    if name.to_lowercase().ends_with(".bmp") { name } else { format!("{}.bmp", name) }
+}
+
+/// The 8 bit paletted bitmap studiomdl wants for a texture
+pub fn texture_bitmap(texture: &Texture) -> Vec<u8> {
+    let width = texture.header.width as usize;
+    let height = texture.header.height as usize;
+    let stride = (width + 3) & !3;
+    let data_offset = 14 + 40 + 256 * 4;
+    let size = data_offset + stride * height;
+
+    let mut bmp = b"BM".to_vec();
+    bmp.extend((size as u32).to_le_bytes());
+    bmp.extend(0u32.to_le_bytes());
+    bmp.extend((data_offset as u32).to_le_bytes());
+    bmp.extend(40u32.to_le_bytes());
+    bmp.extend((width as i32).to_le_bytes());
+    bmp.extend((height as i32).to_le_bytes());
+    bmp.extend(1u16.to_le_bytes());
+    bmp.extend(8u16.to_le_bytes());
+    bmp.extend(0u32.to_le_bytes());
+    bmp.extend(((stride * height) as u32).to_le_bytes());
+    bmp.extend([0u8; 8]);
+    bmp.extend(256u32.to_le_bytes());
+    bmp.extend(0u32.to_le_bytes());
+    for color in texture.palette.iter() {
+        bmp.extend([color.b, color.g, color.r, 0]);
+    }
+    // bottom row first
+    for row in texture.raw_data.chunks(width.max(1)).rev() {
+        bmp.extend(row);
+        bmp.resize(bmp.len() + stride - row.len(), 0);
+    }
+    bmp
+}
+
+impl MdlFile {
+    fn smd_skeleton_header(&self, smd: &mut String) {
+        smd.push_str("version 1\nnodes\n");
+        for (itr, bone) in self.bones.iter().enumerate() {
+            let _ = writeln!(smd, "{} \"{}\" {}", itr, bone.name(), bone.parent);
+        }
+        smd.push_str("end\nskeleton\n");
+    }
+
+    fn reference_file_name(&self, body_part: usize, model: usize) -> String {
+        let name = self.body_parts[body_part].models[model].name();
+        format!("{}.smd", file_stem(&name))
+    }
+
+    fn animation_file_name(&self, sequence: usize, blend: usize) -> String {
+        let label = file_stem(&self.sequences[sequence].label());
+        match blend {
+            0 => format!("anims/{}.smd", label),
+            _ => format!("anims/{}_blend{}.smd", label, blend + 1),
+        }
+    }
+
+    /// One model as a reference SMD, in the bind pose with vertices in model space
+    pub fn reference_smd(&self, body_part: usize, model: usize) -> String {
+        let mut smd = String::new();
+        self.smd_skeleton_header(&mut smd);
+        smd.push_str("time 0\n");
+        for (itr, bone) in self.bones.iter().enumerate() {
+            let value = bone.value;
+            let _ = writeln!(smd, "{} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6}", itr, value[0], value[1], value[2], value[3], value[4], value[5]);
+        }
+        smd.push_str("end\ntriangles\n");
+
+        let bones = self.bone_matrices(&self.bind_pose());
+        let model = &self.body_parts[body_part].models[model];
+        for mesh in model.meshes.iter() {
+            let skin_ref = mesh.skin_ref.max(0) as usize;
+            let texture = self.texture_for_skin_ref(0, skin_ref);
+            let (texture_name, width, height) = match texture {
+                Some(texture) => (bitmap_name(texture), texture.header.width.max(1) as f32, texture.header.height.max(1) as f32),
+                None => (format!("skin{}.bmp", skin_ref), 1.0, 1.0),
+            };
+            for command in mesh.commands.iter() {
+                // studiomdl flips every triangle it reads, so the source order is the reverse
+                for triangle in command.triangles() {
+                    let _ = writeln!(smd, "{}", texture_name);
+                    for corner in triangle.iter().rev() {
+                        let vertex = command.vertices[*corner];
+                        let (position, normal) = match (model.vertices.get(vertex.vertex as usize), model.normals.get(vertex.normal as usize)) {
+                            (Some(position), Some(normal)) => (position, normal),
+                            _ => continue,
+                        };
+                        let bone = model.vertex_bones[vertex.vertex as usize];
+                        let normal_bone = model.normal_bones[vertex.normal as usize];
+                        let position = bones.get(bone as usize).map(|matrix| math::transform_point(matrix, *position)).unwrap_or(*position);
+                        let normal = bones.get(normal_bone as usize).map(|matrix| math::normalize(math::rotate_vector(matrix, *normal))).unwrap_or(*normal);
+                        let u = vertex.s as f32 / width;
+                        let v = 1.0 - vertex.t as f32 / height;
+                        let _ = writeln!(smd, "{} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6}",
+                            bone, position[0], position[1], position[2], normal[0], normal[1], normal[2], u, v);
+                    }
+                }
+            }
+        }
+        smd.push_str("end\n");
+        smd
+    }
+
+    /// One blend of a sequence as an animation SMD, with the linear movement studiomdl took out
+    /// of the root bones put back in
+    pub fn animation_smd(&self, sequence: usize, blend: usize) -> Result<String, MdlError> {
+        let animation = self.decode_animation(sequence, blend)?;
+        let sequence = &self.sequences[sequence];
+        let mut smd = String::new();
+        self.smd_skeleton_header(&mut smd);
+        let last_frame = animation.num_frames().saturating_sub(1).max(1) as f32;
+        for (frame, values) in animation.frames.iter().enumerate() {
+            let _ = writeln!(smd, "time {}", frame);
+            let movement = math::scale(sequence.linear_movement, frame as f32 / last_frame);
+            for (itr, (bone, value)) in self.bones.iter().zip(values.iter()).enumerate() {
+                let mut value = *value;
+                if bone.parent < 0 {
+                    for axis in 0..3 {
+                        value[axis] += movement[axis];
+                    }
+                }
+                let _ = writeln!(smd, "{} {:.6} {:.6} {:.6} {:.6} {:.6} {:.6}", itr, value[0], value[1], value[2], value[3], value[4], value[5]);
+            }
+        }
+        smd.push_str("end\n");
+        Ok(smd)
+    }
+
+    /// A `.qc` that recompiles into something close to this model, refers to the files
+    /// `decompile` writes
+    pub fn to_qc(&self, name: &str) -> String {
+        let mut qc = format!("// decompiled from {}\n\n", self.file_name());
+        let _ = writeln!(qc, "$modelname \"{}.mdl\"", name);
+        qc.push_str("$cd \".\"\n$cdtexture \".\"\n$scale 1.0\n$cliptotextures\n\n");
+        let header = &self.header;
+        let _ = writeln!(qc, "$eyeposition {} {} {}", header.eye_position[0], header.eye_position[1], header.eye_position[2]);
+        let _ = writeln!(qc, "$bbox {} {} {} {} {} {}", header.min[0], header.min[1], header.min[2], header.max[0], header.max[1], header.max[2]);
+        let _ = writeln!(qc, "$cbox {} {} {} {} {} {}", header.bbmin[0], header.bbmin[1], header.bbmin[2], header.bbmax[0], header.bbmax[1], header.bbmax[2]);
+        if header.flags != 0 {
+            let _ = writeln!(qc, "$flags {}", header.flags);
+        }
+        if self.header.num_textures == 0 && !self.body_parts.is_empty() {
+            qc.push_str("$externaltextures\n");
+        }
+        qc.push('\n');
+
+        for (itr, body_part) in self.body_parts.iter().enumerate() {
+            if body_part.models.len() == 1 {
+                let _ = writeln!(qc, "$body \"{}\" \"{}\"", body_part.name(), self.reference_file_name(itr, 0).trim_end_matches(".smd"));
+                continue
+            }
+            let _ = writeln!(qc, "$bodygroup \"{}\"\n{{", body_part.name());
+            for (model_itr, model) in body_part.models.iter().enumerate() {
+                if model.meshes.is_empty() {
+                    qc.push_str("\tblank\n");
+                } else {
+                    let _ = writeln!(qc, "\tstudio \"{}\"", self.reference_file_name(itr, model_itr).trim_end_matches(".smd"));
+                }
+            }
+            qc.push_str("}\n");
+        }
+        qc.push('\n');
+
+        // only the skin refs that change between families go in the texture group
+        let columns: Vec<usize> = match self.skin_families.first() {
+            Some(first) => (0..first.len())
+                .filter(|column| self.skin_families.iter().any(|family| family.get(*column) != first.get(*column)))
+                .collect(),
+            None => vec![],
+        };
+        if self.skin_families.len() > 1 && !columns.is_empty() {
+            qc.push_str("$texturegroup \"skinfamilies\"\n{\n");
+            for family in 0..self.skin_families.len() {
+                let names: Vec<String> = columns.iter()
+                    .filter_map(|column| self.texture_for_skin_ref(family, *column))
+                    .map(|texture| format!("\"{}\"", bitmap_name(texture)))
+                    .collect();
+                let _ = writeln!(qc, "\t{{ {} }}", names.join(" "));
+            }
+            qc.push_str("}\n\n");
+        }
+        for texture in self.textures.iter() {
+            for (flag, mode) in RENDER_MODES {
+                if texture.header.flags.contains(flag) {
+                    let _ = writeln!(qc, "$texrendermode \"{}\" \"{}\"", bitmap_name(texture), mode);
+                }
+            }
+        }
+
+        let bone_name = |bone: i32| self.bones.get(bone as usize).map(|bone| bone.name()).unwrap_or_default();
+        for (itr, attachment) in self.attachments.iter().enumerate() {
+            let _ = writeln!(qc, "$attachment {} \"{}\" {} {} {}", itr, bone_name(attachment.bone), attachment.org[0], attachment.org[1], attachment.org[2]);
+        }
+        for controller in self.bone_controllers.iter() {
+            // controller 4 is the mouth, the engine drives it from speech
+            let index = if controller.index == 4 { String::from("mouth") } else { controller.index.to_string() };
+            let _ = writeln!(qc, "$controller {} \"{}\" {} {} {}", index, bone_name(controller.bone), motion::to_string(controller.controller_type & motion::TYPES), controller.start, controller.end);
+        }
+        for hit_box in self.hit_boxes.iter() {
+            let _ = writeln!(qc, "$hbox {} \"{}\" {} {} {} {} {} {}", hit_box.group, bone_name(hit_box.bone),
+                hit_box.bbmin[0], hit_box.bbmin[1], hit_box.bbmin[2], hit_box.bbmax[0], hit_box.bbmax[1], hit_box.bbmax[2]);
+        }
+        qc.push('\n');
+
+        if self.sequence_groups.len() > 1 {
+            qc.push_str("// the animations were split into sequence group files\n$sequencegroupsize 64\n\n");
+        }
+        for (itr, sequence) in self.sequences.iter().enumerate() {
+            let num_blends = sequence.num_blends.max(1) as usize;
+            let files: Vec<String> = (0..num_blends)
+                .map(|blend| format!("\"{}\"", self.animation_file_name(itr, blend).trim_end_matches(".smd")))
+                .collect();
+            let _ = write!(qc, "$sequence \"{}\" {} fps {}", sequence.label(), files.join(" "), sequence.fps);
+            if sequence.is_looping() {
+                qc.push_str(" loop");
+            }
+            if sequence.activity != 0 {
+                match activity_name(sequence.activity) {
+                    Some(activity) => { let _ = write!(qc, " {} {}", activity, sequence.act_weight); },
+                    None => { let _ = write!(qc, " /* unknown activity {} */", sequence.activity); },
+                }
+            }
+            let motion_type = sequence.motion_type & motion::TYPES;
+            if motion_type != 0 {
+                let _ = write!(qc, " {}", motion::to_string(motion_type).replace(" | ", " "));
+            }
+            for axis in 0..(num_blends / 2).min(2) {
+                let _ = write!(qc, " blend {} {} {}", motion::to_string(sequence.blend_type[axis] & motion::TYPES), sequence.blend_start[axis], sequence.blend_end[axis]);
+            }
+            if sequence.entry_node != 0 || sequence.exit_node != 0 {
+                if sequence.entry_node == sequence.exit_node {
+                    let _ = write!(qc, " node {}", sequence.entry_node);
+                } else {
+                    let keyword = if sequence.node_flags != 0 { "rtransition" } else { "transition" };
+                    let _ = write!(qc, " {} {} {}", keyword, sequence.entry_node, sequence.exit_node);
+                }
+            }
+            if sequence.events.is_empty() {
+                qc.push('\n');
+                continue
+            }
+            qc.push_str(" {\n");
+            for event in sequence.events.iter() {
+                let options = event.options();
+                if options.is_empty() {
+                    let _ = writeln!(qc, "\t{{ event {} {} }}", event.event, event.frame);
+                } else {
+                    let _ = writeln!(qc, "\t{{ event {} {} \"{}\" }}", event.event, event.frame, options);
+                }
+            }
+            qc.push_str("}\n");
+        }
+        qc
+    }
+
+    /// The `.qc`, reference and animation SMDs and texture bitmaps to rebuild the model with
+    /// studiomdl, as `(path, contents)`. Sequences in sequence group files that aren't loaded
+    /// are left out of the files but still listed in the `.qc`.
+    pub fn decompile(&self, name: &str) -> Result<Vec<(String, Vec<u8>)>, MdlError> {
+        let mut files = vec![(format!("{}.qc", name), self.to_qc(name).into_bytes())];
+        for (itr, body_part) in self.body_parts.iter().enumerate() {
+            for (model_itr, model) in body_part.models.iter().enumerate() {
+                if model.meshes.is_empty() && body_part.models.len() > 1 {
+                    continue
+                }
+                files.push((self.reference_file_name(itr, model_itr), self.reference_smd(itr, model_itr).into_bytes()));
+            }
+        }
+        for (itr, sequence) in self.sequences.iter().enumerate() {
+            for blend in 0..sequence.num_blends.max(1) as usize {
+                match self.animation_smd(itr, blend) {
+                    Ok(smd) => files.push((self.animation_file_name(itr, blend), smd.into_bytes())),
+                    Err(MdlError::MissingSequenceGroup { .. }) => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        for texture in self.textures.iter() {
+            files.push((bitmap_name(texture), texture_bitmap(texture)));
+        }
+        Ok(files)
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt::Write as _;
+use std::io::Cursor;
+
+use super::{name_str, MdlError, MdlFile, MeshGeometry, Texture, TextureFlags};
+use crate::math::{self, Mat3x4, Vec3};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BIN_CHUNK: u32 = 0x004E4942;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Half-Life is z up, glTF and most OBJ importers are y up
+const Z_UP_TO_Y_UP: [f32; 4] = [-std::f32::consts::FRAC_1_SQRT_2, 0.0, 0.0, std::f32::consts::FRAC_1_SQRT_2];
+
+fn json_string(string: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_floats(values: &[f32]) -> String {
+    let values: Vec<String> = values.iter().map(|value| format!("{}", value)).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn png_bytes(texture: &Texture) -> Result<Vec<u8>, MdlError> {
+    let mut png = Cursor::new(vec![]);
+    texture.to_rgba_image()
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(|err| MdlError::Export(err.to_string()))?;
+    Ok(png.into_inner())
+}
+
+/// "DM_Base.bmp" to "DM_Base.png"
+fn png_name(texture: &Texture) -> String {
+    let name = name_str(&texture.header.name);
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) => stem.to_string(),
+        None => name,
+    };
+    format!("{}.png", stem)
+}
+
+/// Column major 4x4, the way glTF stores matrices
+fn column_major(matrix: &Mat3x4) -> [f32; 16] {
+    [
+        matrix[0][0], matrix[1][0], matrix[2][0], 0.0,
+        matrix[0][1], matrix[1][1], matrix[2][1], 0.0,
+        matrix[0][2], matrix[1][2], matrix[2][2], 0.0,
+        matrix[0][3], matrix[1][3], matrix[2][3], 1.0,
+    ]
+}
+
+/// Collects the binary chunk along with the buffer views and accessors pointing into it
+#[derive(Default)]
+struct GlbBuffer {
+    bin: Vec<u8>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl GlbBuffer {
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        self.bin.resize((self.bin.len() + 3) & !3, 0);
+        let mut view = format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{}", self.bin.len(), bytes.len());
+        if let Some(target) = target {
+            let _ = write!(view, ",\"target\":{}", target);
+        }
+        view.push('}');
+        self.bin.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(&mut self, bytes: &[u8], target: Option<u32>, component_type: u32, count: usize, accessor_type: &str, bounds: Option<(&[f32], &[f32])>) -> usize {
+        let view = self.view(bytes, target);
+        let mut accessor = format!("{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"{}\"", view, component_type, count, accessor_type);
+        if let Some((min, max)) = bounds {
+            let _ = write!(accessor, ",\"min\":{},\"max\":{}", json_floats(min), json_floats(max));
+        }
+        accessor.push('}');
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// Float vectors of `N` components, glTF wants bounds on positions and animation times
+    fn floats<const N: usize>(&mut self, values: &[[f32; N]], accessor_type: &str, target: Option<u32>, bounds: bool) -> usize {
+        let bytes: Vec<u8> = values.iter().flatten().flat_map(|value| value.to_le_bytes()).collect();
+        let mut min = [f32::MAX; N];
+        let mut max = [f32::MIN; N];
+        for value in values.iter() {
+            for itr in 0..N {
+                min[itr] = min[itr].min(value[itr]);
+                max[itr] = max[itr].max(value[itr]);
+            }
+        }
+        let bounds = if bounds { Some((&min[..], &max[..])) } else { None };
+        self.accessor(&bytes, target, FLOAT, values.len(), accessor_type, bounds)
+    }
+}
+
+impl MdlFile {
+    /// Geometry of one body value in the default skin, every mesh with its vertices posed by
+    /// `bones` in model space
+    fn posed_geometry(&self, body: i32, bones: &[Mat3x4]) -> Vec<MeshGeometry> {
+        let mut geometry: Vec<MeshGeometry> = self.body_models(body).iter()
+            .flat_map(|(body_part, model)| self.model_geometry(*body_part, *model, 0))
+            .filter(|mesh| !mesh.indices.is_empty())
+            .collect();
+        for vertex in geometry.iter_mut().flat_map(|mesh| mesh.vertices.iter_mut()) {
+            if let Some(bone) = bones.get(vertex.bone as usize) {
+                vertex.position = math::transform_point(bone, vertex.position);
+            }
+            if let Some(bone) = bones.get(vertex.normal_bone as usize) {
+                vertex.normal = math::normalize(math::rotate_vector(bone, vertex.normal));
+            }
+        }
+        geometry
+    }
+
+    /// Binary glTF of one body value skinned to the skeleton, with the textures as PNGs and every
+    /// sequence that can be decoded as an animation. Bone controllers and blends past the first
+    /// are left out.
+    pub fn to_glb(&self, body: i32) -> Result<Vec<u8>, MdlError> {
+        let bind_pose = self.bind_pose();
+        let bind_matrices = self.bone_matrices(&bind_pose);
+        let geometry = self.posed_geometry(body, &bind_matrices);
+        let mut buffer = GlbBuffer::default();
+
+        // nodes: the y up root, then one per bone, then the mesh
+        let root = 0;
+        let bone_node = |bone: usize| bone + 1;
+        let mesh_node = self.bones.len() + 1;
+        let mut nodes = vec![];
+        let mut root_children: Vec<String> = (0..self.bones.len())
+            .filter(|bone| self.bones[*bone].parent < 0)
+            .map(|bone| bone_node(bone).to_string())
+            .collect();
+        root_children.push(mesh_node.to_string());
+        nodes.push(format!("{{\"name\":{},\"rotation\":{},\"children\":[{}]}}", json_string(&self.file_name()), json_floats(&Z_UP_TO_Y_UP), root_children.join(",")));
+        for (itr, (bone, transform)) in self.bones.iter().zip(bind_pose.iter()).enumerate() {
+            let children: Vec<String> = (0..self.bones.len())
+                .filter(|child| self.bones[*child].parent == itr as i32)
+                .map(|child| bone_node(child).to_string())
+                .collect();
+            let mut node = format!("{{\"name\":{},\"translation\":{},\"rotation\":{}", json_string(&bone.name()), json_floats(&transform.position), json_floats(&transform.rotation));
+            if !children.is_empty() {
+                let _ = write!(node, ",\"children\":[{}]", children.join(","));
+            }
+            node.push('}');
+            nodes.push(node);
+        }
+        let skinned = !self.bones.is_empty();
+        nodes.push(format!("{{\"name\":\"mesh\",\"mesh\":0{}}}", if skinned { ",\"skin\":0" } else { "" }));
+
+        let mut primitives = vec![];
+        for mesh in geometry.iter() {
+            let positions: Vec<Vec3> = mesh.vertices.iter().map(|vertex| vertex.position).collect();
+            let normals: Vec<Vec3> = mesh.vertices.iter().map(|vertex| vertex.normal).collect();
+            let uvs: Vec<[f32; 2]> = mesh.vertices.iter().map(|vertex| vertex.uv).collect();
+            let position = buffer.floats(&positions, "VEC3", Some(ARRAY_BUFFER), true);
+            let normal = buffer.floats(&normals, "VEC3", Some(ARRAY_BUFFER), false);
+            let uv = buffer.floats(&uvs, "VEC2", Some(ARRAY_BUFFER), false);
+            let mut attributes = format!("\"POSITION\":{},\"NORMAL\":{},\"TEXCOORD_0\":{}", position, normal, uv);
+            if skinned {
+                let joints: Vec<u8> = mesh.vertices.iter().flat_map(|vertex| [vertex.bone as u16, 0, 0, 0]).flat_map(|joint| joint.to_le_bytes()).collect();
+                let weights: Vec<[f32; 4]> = mesh.vertices.iter().map(|_| [1.0, 0.0, 0.0, 0.0]).collect();
+                let joints = buffer.accessor(&joints, Some(ARRAY_BUFFER), UNSIGNED_SHORT, mesh.vertices.len(), "VEC4", None);
+                let weights = buffer.floats(&weights, "VEC4", Some(ARRAY_BUFFER), false);
+                let _ = write!(attributes, ",\"JOINTS_0\":{},\"WEIGHTS_0\":{}", joints, weights);
+            }
+            // the engine draws clockwise triangles, glTF wants them counter-clockwise
+            let indices: Vec<u8> = mesh.indices.chunks_exact(3)
+                .flat_map(|triangle| [triangle[2], triangle[1], triangle[0]])
+                .flat_map(|index| index.to_le_bytes())
+                .collect();
+            let indices = buffer.accessor(&indices, Some(ELEMENT_ARRAY_BUFFER), UNSIGNED_INT, mesh.indices.len(), "SCALAR", None);
+            let mut primitive = format!("{{\"attributes\":{{{}}},\"indices\":{}", attributes, indices);
+            let texture = self.skin_texture_index(0, mesh.skin_ref);
+            if texture < self.textures.len() {
+                let _ = write!(primitive, ",\"material\":{}", texture);
+            }
+            primitive.push('}');
+            primitives.push(primitive);
+        }
+
+        let mut materials = vec![];
+        let mut textures = vec![];
+        let mut images = vec![];
+        for (itr, texture) in self.textures.iter().enumerate() {
+            let png = buffer.view(&png_bytes(texture)?, None);
+            images.push(format!("{{\"name\":{},\"bufferView\":{},\"mimeType\":\"image/png\"}}", json_string(&png_name(texture)), png));
+            textures.push(format!("{{\"source\":{}}}", itr));
+            // glTF has no additive blending, plain blending is the closest
+            let alpha_mode = if texture.header.flags.contains(TextureFlags::MASKED) {
+                ",\"alphaMode\":\"MASK\""
+            } else if texture.header.flags.contains(TextureFlags::ADDITIVE) {
+                ",\"alphaMode\":\"BLEND\""
+            } else {
+                ""
+            };
+            materials.push(format!(
+                "{{\"name\":{},\"pbrMetallicRoughness\":{{\"baseColorTexture\":{{\"index\":{}}},\"metallicFactor\":0,\"roughnessFactor\":1}}{}}}",
+                json_string(&name_str(&texture.header.name)), itr, alpha_mode));
+        }
+
+        let mut skins = vec![];
+        if skinned {
+            let inverse_binds: Vec<[f32; 16]> = bind_matrices.iter().map(|matrix| column_major(&math::invert_transform(matrix))).collect();
+            let inverse_binds = buffer.floats(&inverse_binds, "MAT4", None, false);
+            let joints: Vec<String> = (0..self.bones.len()).map(|bone| bone_node(bone).to_string()).collect();
+            skins.push(format!("{{\"inverseBindMatrices\":{},\"skeleton\":{},\"joints\":[{}]}}", inverse_binds, root, joints.join(",")));
+        }
+
+        let mut animations = vec![];
+        for (itr, sequence) in self.sequences.iter().enumerate() {
+            // sequences in sequence group files that aren't loaded are skipped
+            let animation = match self.decode_animation(itr, 0) {
+                Ok(animation) if animation.num_frames() > 0 => animation,
+                _ => continue,
+            };
+            let fps = if sequence.fps > 0.0 { sequence.fps } else { 30.0 };
+            let times: Vec<[f32; 1]> = (0..animation.num_frames()).map(|frame| [frame as f32 / fps]).collect();
+            let input = buffer.floats(&times, "SCALAR", None, true);
+            let poses: Vec<_> = (0..animation.num_frames()).map(|frame| animation.transforms(frame)).collect();
+            let mut samplers = vec![];
+            let mut channels = vec![];
+            for bone in 0..self.bones.len() {
+                let translations: Vec<Vec3> = poses.iter().map(|pose| pose[bone].position).collect();
+                let rotations: Vec<[f32; 4]> = poses.iter().map(|pose| pose[bone].rotation).collect();
+                let translation = buffer.floats(&translations, "VEC3", None, false);
+                let rotation = buffer.floats(&rotations, "VEC4", None, false);
+                for (output, path) in [(translation, "translation"), (rotation, "rotation")] {
+                    channels.push(format!("{{\"sampler\":{},\"target\":{{\"node\":{},\"path\":\"{}\"}}}}", samplers.len(), bone_node(bone), path));
+                    samplers.push(format!("{{\"input\":{},\"output\":{},\"interpolation\":\"LINEAR\"}}", input, output));
+                }
+            }
+            animations.push(format!("{{\"name\":{},\"samplers\":[{}],\"channels\":[{}]}}", json_string(&sequence.label()), samplers.join(","), channels.join(",")));
+        }
+
+        let mut json = String::from("{\"asset\":{\"version\":\"2.0\",\"generator\":\"wadviewer\"},\"scene\":0,\"scenes\":[{\"nodes\":[0]}]");
+        let _ = write!(json, ",\"nodes\":[{}]", nodes.join(","));
+        let _ = write!(json, ",\"meshes\":[{{\"primitives\":[{}]}}]", primitives.join(","));
+        for (name, items) in [("materials", &materials), ("textures", &textures), ("images", &images), ("skins", &skins), ("animations", &animations)] {
+            if !items.is_empty() {
+                let _ = write!(json, ",\"{}\":[{}]", name, items.join(","));
+            }
+        }
+        let _ = write!(json, ",\"accessors\":[{}]", buffer.accessors.join(","));
+        let _ = write!(json, ",\"bufferViews\":[{}]", buffer.buffer_views.join(","));
+        let _ = write!(json, ",\"buffers\":[{{\"byteLength\":{}}}]}}", buffer.bin.len());
+
+        let mut json = json.into_bytes();
+        json.resize((json.len() + 3) & !3, b' ');
+        let mut bin = buffer.bin;
+        bin.resize((bin.len() + 3) & !3, 0);
+        let mut glb = vec![];
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(GLB_JSON_CHUNK.to_le_bytes());
+        glb.extend(json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(GLB_BIN_CHUNK.to_le_bytes());
+        glb.extend(bin);
+        Ok(glb)
+    }
+
+    /// Wavefront OBJ of one body value in the bind pose, along with its MTL and a PNG per
+    /// texture, as `(file name, contents)`
+    pub fn to_obj(&self, body: i32, name: &str) -> Result<Vec<(String, Vec<u8>)>, MdlError> {
+        let bones = self.bone_matrices(&self.bind_pose());
+        let geometry = self.posed_geometry(body, &bones);
+        let mtl_name = format!("{}.mtl", name);
+        let mut obj = format!("mtllib {}\n", mtl_name);
+        let mut offset = 1;
+        for (itr, mesh) in geometry.iter().enumerate() {
+            let _ = writeln!(obj, "o mesh{}", itr);
+            // y up, same as the glTF root rotation
+            for vertex in mesh.vertices.iter() {
+                let _ = writeln!(obj, "v {} {} {}", vertex.position[0], vertex.position[2], -vertex.position[1]);
+                let _ = writeln!(obj, "vt {} {}", vertex.uv[0], 1.0 - vertex.uv[1]);
+                let _ = writeln!(obj, "vn {} {} {}", vertex.normal[0], vertex.normal[2], -vertex.normal[1]);
+            }
+            if let Some(texture) = self.textures.get(self.skin_texture_index(0, mesh.skin_ref)) {
+                let _ = writeln!(obj, "usemtl {}", name_str(&texture.header.name));
+            }
+            // counter-clockwise like glTF, the engine's order is the other way around
+            for triangle in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [triangle[2] + offset, triangle[1] + offset, triangle[0] + offset];
+                let _ = writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}");
+            }
+            offset += mesh.vertices.len() as u32;
+        }
+
+        let mut mtl = String::new();
+        let mut files = vec![];
+        for texture in self.textures.iter() {
+            let _ = writeln!(mtl, "newmtl {}\nKd 1 1 1\nmap_Kd {}\n", name_str(&texture.header.name), png_name(texture));
+            files.push((png_name(texture), png_bytes(texture)?));
+        }
+        files.insert(0, (mtl_name, mtl.into_bytes()));
+        files.insert(0, (format!("{}.obj", name), obj.into_bytes()));
+        Ok(files)
+    }
+}
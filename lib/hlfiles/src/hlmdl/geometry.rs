@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fmt;
+use bytebuffer::ByteBuffer;
+
+use super::{check_bone, check_bounds, name_str, read_array, read_vec3, MdlError, MdlFile, Vec3};
+
+pub const BODY_PART_SIZE: usize = 76;
+pub const MODEL_SIZE: usize = 112;
+pub const MESH_SIZE: usize = 20;
+
+/// `mstudiobodyparts_t`, a bodygroup
+#[derive(Clone)]
+pub struct BodyPart {
+    pub name: [u8; 64],
+    pub num_models: i32,
+    /// Divides the body value to find which model of this part is shown
+    pub base: i32,
+    pub model_index: i32,
+
+    pub models: Vec<Model>,
+}
+
+impl BodyPart {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            name: reader.read_bytes(64)?.try_into().unwrap(),
+            num_models: reader.read_i32()?,
+            base: reader.read_i32()?,
+            model_index: reader.read_i32()?,
+            models: vec![],
+        })
+    }
+
+    pub fn name(&self) -> String {
+        name_str(&self.name)
+    }
+}
+
+impl fmt::Debug for BodyPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyPart")
+         .field("name", &self.name())
+         .field("num_models", &self.num_models)
+         .field("base", &self.base)
+         .field("models", &self.models)
+         .finish()
+    }
+}
+
+/// `mstudiomodel_t`, one choice of a bodygroup
+#[derive(Clone)]
+pub struct Model {
+    pub name: [u8; 64],
+    pub model_type: i32,
+    pub bounding_radius: f32,
+    pub num_mesh: i32,
+    pub mesh_index: i32,
+    pub num_verts: i32,
+    pub vert_info_index: i32,
+    pub vert_index: i32,
+    pub num_norms: i32,
+    pub norm_info_index: i32,
+    pub norm_index: i32,
+    pub num_groups: i32,
+    pub group_index: i32,
+
+    pub meshes: Vec<Mesh>,
+    /// In the space of the bone in `vertex_bones`
+    pub vertices: Vec<Vec3>,
+    pub vertex_bones: Vec<u8>,
+    pub normals: Vec<Vec3>,
+    pub normal_bones: Vec<u8>,
+}
+
+impl Model {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            name: reader.read_bytes(64)?.try_into().unwrap(),
+            model_type: reader.read_i32()?,
+            bounding_radius: reader.read_f32()?,
+            num_mesh: reader.read_i32()?,
+            mesh_index: reader.read_i32()?,
+            num_verts: reader.read_i32()?,
+            vert_info_index: reader.read_i32()?,
+            vert_index: reader.read_i32()?,
+            num_norms: reader.read_i32()?,
+            norm_info_index: reader.read_i32()?,
+            norm_index: reader.read_i32()?,
+            num_groups: reader.read_i32()?,
+            group_index: reader.read_i32()?,
+            meshes: vec![],
+            vertices: vec![],
+            vertex_bones: vec![],
+            normals: vec![],
+            normal_bones: vec![],
+        })
+    }
+
+    pub fn name(&self) -> String {
+        name_str(&self.name)
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.meshes.iter().map(|mesh| mesh.num_triangles()).sum()
+    }
+}
+
+impl fmt::Debug for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Model")
+         .field("name", &self.name())
+         .field("model_type", &self.model_type)
+         .field("bounding_radius", &self.bounding_radius)
+         .field("num_mesh", &self.num_mesh)
+         .field("num_verts", &self.num_verts)
+         .field("num_norms", &self.num_norms)
+         .finish()
+    }
+}
+
+/// One corner of a triangle command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TriangleVertex {
+    pub vertex: i16,
+    pub normal: i16,
+    /// Texel coordinates
+    pub s: i16,
+    pub t: i16,
+}
+
+/// A strip or a fan of triangles sharing one texture
+#[derive(Debug, Clone)]
+pub struct TriangleCommand {
+    pub fan: bool,
+    pub vertices: Vec<TriangleVertex>,
+}
+
+impl TriangleCommand {
+    /// Splits the strip or fan into triangles of indices into `vertices`, keeping the GL draw
+    /// order so the winding is the same the engine renders with
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        let mut triangles = vec![];
+        for itr in 2..self.vertices.len() {
+            if self.fan {
+                triangles.push([0, itr - 1, itr]);
+            } else if itr % 2 == 0 {
+                triangles.push([itr - 2, itr - 1, itr]);
+            } else {
+                triangles.push([itr - 1, itr - 2, itr]);
+            }
+        }
+        triangles
+    }
+}
+
+/// `mstudiomesh_t`
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub num_tris: i32,
+    pub tri_index: i32,
+    pub skin_ref: i32,
+    pub num_norms: i32,
+    pub norm_index: i32,
+
+    pub commands: Vec<TriangleCommand>,
+}
+
+impl Mesh {
+    pub fn from_reader(reader: &mut ByteBuffer) -> Result<Self, MdlError> {
+        Ok(Self {
+            num_tris: reader.read_i32()?,
+            tri_index: reader.read_i32()?,
+            skin_ref: reader.read_i32()?,
+            num_norms: reader.read_i32()?,
+            norm_index: reader.read_i32()?,
+            commands: vec![],
+        })
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.commands.iter().map(|command| command.vertices.len().saturating_sub(2)).sum()
+    }
+}
+
+/// Reads the zero terminated triangle command list at `offset`
+fn read_triangle_commands(reader: &mut ByteBuffer, offset: i32, num_verts: usize, num_norms: usize) -> Result<Vec<TriangleCommand>, MdlError> {
+    check_bounds("triangle commands", offset, 1, 2, reader.len())?;
+    reader.set_endian(bytebuffer::Endian::LittleEndian);
+    reader.set_rpos(offset as usize);
+    let mut commands = vec![];
+    loop {
+        let count = reader.read_i16()?;
+        if count == 0 {
+            break;
+        }
+        let mut vertices = Vec::with_capacity(count.unsigned_abs() as usize);
+        for _itr in 0..count.unsigned_abs() {
+            let vertex = TriangleVertex {
+                vertex: reader.read_i16()?,
+                normal: reader.read_i16()?,
+                s: reader.read_i16()?,
+                t: reader.read_i16()?,
+            };
+            if vertex.vertex < 0 || vertex.vertex as usize >= num_verts {
+                return Err(MdlError::BadCount { what: "triangle vertex", count: vertex.vertex as i64 })
+            }
+            if vertex.normal < 0 || vertex.normal as usize >= num_norms {
+                return Err(MdlError::BadCount { what: "triangle normal", count: vertex.normal as i64 })
+            }
+            vertices.push(vertex);
+        }
+        commands.push(TriangleCommand { fan: count < 0, vertices });
+    }
+    Ok(commands)
+}
+
+fn read_bytes_at(what: &'static str, reader: &mut ByteBuffer, offset: i32, count: i32) -> Result<Vec<u8>, MdlError> {
+    check_bounds(what, offset, count, 1, reader.len())?;
+    reader.set_rpos(offset as usize);
+    Ok(reader.read_bytes(count as usize)?)
+}
+
+/// Reads the body parts along with their models, meshes, vertices and triangle commands
+pub(super) fn read_body_parts(reader: &mut ByteBuffer, offset: i32, count: i32, num_bones: usize) -> Result<Vec<BodyPart>, MdlError> {
+    let mut body_parts = read_array("body parts", reader, offset, count, BODY_PART_SIZE, BodyPart::from_reader)?;
+    for body_part in body_parts.iter_mut() {
+        body_part.models = read_array("models", reader, body_part.model_index, body_part.num_models, MODEL_SIZE, Model::from_reader)?;
+        for model in body_part.models.iter_mut() {
+            model.vertex_bones = read_bytes_at("vertex bones", reader, model.vert_info_index, model.num_verts)?;
+            model.normal_bones = read_bytes_at("normal bones", reader, model.norm_info_index, model.num_norms)?;
+            for bone in model.vertex_bones.iter().chain(model.normal_bones.iter()) {
+                check_bone("vertex bone", *bone as i32, num_bones)?;
+            }
+            model.vertices = read_array("vertices", reader, model.vert_index, model.num_verts, 12, read_vec3)?;
+            model.normals = read_array("normals", reader, model.norm_index, model.num_norms, 12, read_vec3)?;
+            model.meshes = read_array("meshes", reader, model.mesh_index, model.num_mesh, MESH_SIZE, Mesh::from_reader)?;
+            for mesh in model.meshes.iter_mut() {
+                mesh.commands = read_triangle_commands(reader, mesh.tri_index, model.vertices.len(), model.normals.len())?;
+            }
+        }
+    }
+    Ok(body_parts)
+}
+
+/// A vertex ready to be drawn, still in the space of its bone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshVertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// Normalized against the texture size
+    pub uv: [f32; 2],
+    pub bone: u8,
+    pub normal_bone: u8,
+}
+
+/// One mesh as a plain indexed triangle list
+#[derive(Debug, Clone)]
+pub struct MeshGeometry {
+    pub skin_ref: usize,
+    pub vertices: Vec<MeshVertex>,
+    /// Three per triangle
+    pub indices: Vec<u32>,
+}
+
+impl MdlFile {
+    /// Which model of every body part a body value selects, as `(body part, model)` pairs
+    pub fn body_models(&self, body: i32) -> Vec<(usize, usize)> {
+        self.body_parts.iter()
+            .enumerate()
+            .filter(|(_, body_part)| body_part.num_models > 0)
+            .map(|(itr, body_part)| {
+                let model = (body / body_part.base.max(1)) % body_part.num_models;
+                (itr, model.max(0) as usize)
+            })
+            .collect()
+    }
+
+    /// Turns the triangle commands of every mesh in a model into indexed triangle lists, with UVs
+    /// normalized against the textures `skin_family` puts on the meshes
+    pub fn model_geometry(&self, body_part: usize, model: usize, skin_family: usize) -> Vec<MeshGeometry> {
+        let model = match self.body_parts.get(body_part).and_then(|body_part| body_part.models.get(model)) {
+            Some(model) => model,
+            None => return vec![],
+        };
+        let mut ret_vec = vec![];
+        for mesh in model.meshes.iter() {
+            let skin_ref = mesh.skin_ref.max(0) as usize;
+            // the skin table is in texels, models with external textures that aren't loaded
+            // keep texel coordinates
+            let (width, height) = match self.texture_for_skin_ref(skin_family, skin_ref) {
+                Some(texture) => (texture.header.width.max(1) as f32, texture.header.height.max(1) as f32),
+                None => (1.0, 1.0),
+            };
+            let mut vertices = vec![];
+            let mut indices = vec![];
+            let mut vertex_map: HashMap<TriangleVertex, u32> = HashMap::new();
+            for command in mesh.commands.iter() {
+                let command_indices: Vec<u32> = command.vertices.iter()
+                    .map(|triangle_vertex| {
+                        *vertex_map.entry(*triangle_vertex).or_insert_with(|| {
+                            let vertex = triangle_vertex.vertex as usize;
+                            let normal = triangle_vertex.normal as usize;
+                            vertices.push(MeshVertex {
+                                position: model.vertices[vertex],
+                                normal: model.normals[normal],
+                                uv: [triangle_vertex.s as f32 / width, triangle_vertex.t as f32 / height],
+                                bone: model.vertex_bones[vertex],
+                                normal_bone: model.normal_bones[normal],
+                            });
+                            (vertices.len() - 1) as u32
+                        })
+                    })
+                    .collect();
+                for triangle in command.triangles() {
+                    indices.extend(triangle.iter().map(|itr| command_indices[*itr]));
+                }
+            }
+            ret_vec.push(MeshGeometry {
+                skin_ref,
+                vertices,
+                indices,
+            });
+        }
+        ret_vec
+    }
+}
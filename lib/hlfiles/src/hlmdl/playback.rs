@@ -0,0 +1,262 @@
+use super::{blend_poses, Animation, BoneTransform, MdlFile};
+
+/// How many fired events the log keeps
+const EVENT_LOG_SIZE: usize = 8;
+
+/// Which sequence is playing and where, turns into a pose every frame
+pub struct Playback {
+    /// `None` shows the bind pose
+    pub sequence: Option<usize>,
+    pub frame: f32,
+    pub playing: bool,
+    pub looping: bool,
+    pub speed: f32,
+    /// Per blend axis, in the sequence's `blend_start`..`blend_end` units
+    pub blend: [f32; 2],
+    /// Per bone controller, in the controller's `start`..`end` units
+    pub controllers: Vec<f32>,
+    /// Every blend of `sequence`, or why it couldn't be decoded
+    animations: Option<(usize, Result<Vec<Animation>, String>)>,
+    last_time: Option<f64>,
+    event_log: Vec<String>,
+}
+
+impl Playback {
+    pub fn new(mdl_file: &MdlFile) -> Self {
+        let mut playback = Self {
+            sequence: None,
+            frame: 0.0,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+            blend: [0.0; 2],
+            controllers: vec![],
+            animations: None,
+            last_time: None,
+            event_log: vec![],
+        };
+        playback.reset_controllers(mdl_file);
+        playback
+    }
+
+    /// Drops the decoded animation, for when a sequence group file shows up
+    pub fn reload(&mut self) {
+        self.animations = None;
+    }
+
+    pub fn reset_controllers(&mut self, mdl_file: &MdlFile) {
+        self.controllers = mdl_file.bone_controllers.iter()
+            .map(|controller| controller.start + (controller.end - controller.start) * controller.rest.clamp(0, 255) as f32 / 255.0)
+            .collect();
+    }
+
+    pub fn set_sequence(&mut self, mdl_file: &MdlFile, sequence: Option<usize>) {
+        self.sequence = sequence;
+        self.frame = 0.0;
+        self.event_log.clear();
+        if sequence.is_none() {
+            self.playing = false;
+        }
+        if let Some(sequence) = sequence.and_then(|sequence| mdl_file.sequences.get(sequence)) {
+            self.looping = sequence.is_looping();
+            // start in the middle of the blend range, usually the neutral pose
+            for axis in 0..2 {
+                self.blend[axis] = (sequence.blend_start[axis] + sequence.blend_end[axis]) * 0.5;
+            }
+        }
+    }
+
+    fn animations(&mut self, mdl_file: &MdlFile, sequence_itr: usize) -> &Result<Vec<Animation>, String> {
+        if !matches!(&self.animations, Some((sequence, _)) if *sequence == sequence_itr) {
+            let animations = match mdl_file.sequences.get(sequence_itr) {
+                Some(sequence) => (0..sequence.num_blends.max(1) as usize)
+                    .map(|blend| mdl_file.decode_animation(sequence_itr, blend))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| err.to_string()),
+                None => Err(String::from("no sequences")),
+            };
+            self.animations = Some((sequence_itr, animations));
+        }
+        &self.animations.as_ref().unwrap().1
+    }
+
+    fn num_frames(&self, mdl_file: &MdlFile) -> usize {
+        self.sequence
+            .and_then(|sequence| mdl_file.sequences.get(sequence))
+            .map(|sequence| sequence.num_frames.max(1) as usize)
+            .unwrap_or(1)
+    }
+
+    /// Moves the frame along by however long it has been since the last call
+    fn advance(&mut self, mdl_file: &MdlFile, time: f64) {
+        let dt = self.last_time.map(|last_time| (time - last_time) as f32).unwrap_or(0.0);
+        self.last_time = Some(time);
+        let sequence = match self.sequence.and_then(|sequence| mdl_file.sequences.get(sequence)) {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        if !self.playing || dt <= 0.0 {
+            return
+        }
+        let num_frames = self.num_frames(mdl_file) as f32;
+        let last_frame = (num_frames - 1.0).max(0.0);
+        let previous = self.frame;
+        let mut frame = self.frame + dt * sequence.fps * self.speed;
+        // the last frame of a loop is the first one again, so it wraps there
+        if self.looping {
+            frame = if last_frame > 0.0 { frame.rem_euclid(last_frame) } else { 0.0 };
+        } else if frame >= last_frame || frame < 0.0 {
+            frame = frame.clamp(0.0, last_frame);
+            self.playing = false;
+        }
+        self.frame = frame;
+
+        // events fire when the frame passes them, either direction and across the loop
+        let wrapped = (self.speed >= 0.0 && frame < previous) || (self.speed < 0.0 && frame > previous);
+        for event in sequence.events.iter() {
+            let event_frame = event.frame as f32;
+            let passed = match (wrapped, self.speed >= 0.0) {
+                (false, true) => event_frame > previous && event_frame <= frame,
+                (false, false) => event_frame < previous && event_frame >= frame,
+                (true, true) => event_frame > previous || event_frame <= frame,
+                (true, false) => event_frame < previous || event_frame >= frame,
+            };
+            if passed {
+                self.event_log.push(format!("frame {}: event {} {}", event.frame, event.event, event.options()));
+            }
+        }
+        if self.event_log.len() > EVENT_LOG_SIZE {
+            self.event_log.drain(0..(self.event_log.len() - EVENT_LOG_SIZE));
+        }
+    }
+
+    /// The pose at the current frame with blends and bone controllers applied, the bind pose
+    /// when there's no sequence picked or it can't be decoded
+    pub fn pose(&mut self, mdl_file: &MdlFile) -> Vec<BoneTransform> {
+        let sequence_itr = match self.sequence {
+            Some(sequence_itr) => sequence_itr,
+            None => return mdl_file.bind_pose(),
+        };
+        let frame = self.frame;
+        let adjustments = mdl_file.controller_adjustments(&self.controllers);
+        let weights = match mdl_file.sequences.get(sequence_itr) {
+            Some(sequence) => [0, 1].map(|axis| {
+                let range = sequence.blend_end[axis] - sequence.blend_start[axis];
+                if range == 0.0 {
+                    0.0
+                } else {
+                    ((self.blend[axis] - sequence.blend_start[axis]) / range).clamp(0.0, 1.0)
+                }
+            }),
+            None => [0.0; 2],
+        };
+        let animations = match self.animations(mdl_file, sequence_itr) {
+            Ok(animations) if !animations.is_empty() => animations,
+            _ => return mdl_file.bind_pose(),
+        };
+        let poses: Vec<Vec<BoneTransform>> = animations.iter()
+            .map(|animation| animation.interpolated_adjusted(frame, &adjustments))
+            .collect();
+        // two blends mix along one axis, four make a square
+        match poses.len() {
+            1 => poses[0].clone(),
+            2 | 3 => blend_poses(&poses[0], &poses[1], weights[0]),
+            _ => blend_poses(
+                &blend_poses(&poses[0], &poses[1], weights[0]),
+                &blend_poses(&poses[2], &poses[3], weights[0]),
+                weights[1]),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, mdl_file: &MdlFile) {
+        if mdl_file.sequences.is_empty() {
+            return
+        }
+        self.advance(mdl_file, ui.input(|i| i.time));
+        if self.playing {
+            ui.ctx().request_repaint();
+        }
+
+        let mut sequence_itr = self.sequence;
+        ui.horizontal(|ui| {
+            let selected_text = match self.sequence {
+                Some(sequence) => mdl_file.sequences.get(sequence).map(|sequence| sequence.label()).unwrap_or_default(),
+                None => String::from("Bind pose"),
+            };
+            egui::ComboBox::from_id_source("playback_sequence")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut sequence_itr, None, "Bind pose");
+                    for (itr, sequence) in mdl_file.sequences.iter().enumerate() {
+                        ui.selectable_value(&mut sequence_itr, Some(itr), format!("{}: {}", itr, sequence.label()));
+                    }
+                });
+            let play_button = egui::Button::new(if self.playing { "⏸ Pause" } else { "▶ Play" });
+            if ui.add_enabled(self.sequence.is_some(), play_button).clicked() {
+                self.playing = !self.playing;
+                if self.playing && !self.looping && self.frame >= self.num_frames(mdl_file) as f32 - 1.0 {
+                    self.frame = 0.0;
+                }
+            }
+            ui.checkbox(&mut self.looping, "Loop");
+            ui.add(egui::Slider::new(&mut self.speed, -2.0..=4.0).text("speed"));
+        });
+        if sequence_itr != self.sequence {
+            self.set_sequence(mdl_file, sequence_itr);
+        }
+
+        let (sequence_itr, sequence) = match self.sequence.and_then(|itr| mdl_file.sequences.get(itr).map(|sequence| (itr, sequence))) {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        let last_frame = (self.num_frames(mdl_file) - 1) as f32;
+        let response = ui.add(egui::Slider::new(&mut self.frame, 0.0..=last_frame).text("frame").fixed_decimals(1));
+        if response.dragged() {
+            self.playing = false;
+        }
+        // event ticks under the frame slider
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(response.rect.width(), 6.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        for event in sequence.events.iter() {
+            let x = rect.left() + rect.width() * event.frame as f32 / last_frame.max(1.0);
+            painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], egui::Stroke::new(2.0, egui::Color32::YELLOW));
+        }
+
+        for axis in 0..(sequence.num_blends.clamp(0, 4) as usize / 2) {
+            let (start, end) = (sequence.blend_start[axis], sequence.blend_end[axis]);
+            ui.add(egui::Slider::new(&mut self.blend[axis], start.min(end)..=start.max(end))
+                .text(format!("blend {}", super::motion::to_string(sequence.blend_type[axis]))));
+        }
+
+        if !mdl_file.bone_controllers.is_empty() {
+            ui.horizontal(|ui| {
+                ui.label("Bone controllers");
+                if ui.small_button("Reset").clicked() {
+                    self.reset_controllers(mdl_file);
+                }
+            });
+            for (itr, (controller, value)) in mdl_file.bone_controllers.iter().zip(self.controllers.iter_mut()).enumerate() {
+                let bone_name = mdl_file.bones.get(controller.bone as usize).map(|bone| bone.name()).unwrap_or_default();
+                ui.add(egui::Slider::new(value, controller.start.min(controller.end)..=controller.start.max(controller.end))
+                    .text(format!("{} {} {}", itr, bone_name, super::motion::to_string(controller.controller_type))));
+            }
+        }
+
+        if let Err(err) = self.animations(mdl_file, sequence_itr) {
+            ui.colored_label(egui::Color32::RED, err.as_str());
+        }
+        let current_frame = self.frame.round() as i32;
+        for event in sequence.events.iter().filter(|event| event.frame == current_frame) {
+            ui.colored_label(egui::Color32::YELLOW, format!("frame {}: event {} {}", event.frame, event.event, event.options()));
+        }
+        if !self.event_log.is_empty() {
+            egui::CollapsingHeader::new(format!("Fired events ({})", self.event_log.len()))
+                .id_source("playback_event_log")
+                .show(ui, |ui| {
+                    for line in self.event_log.iter().rev() {
+                        ui.monospace(line);
+                    }
+                });
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::math::Vec3;
+
+/// A bone in an SMD's `nodes` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmdNode {
+    pub name: String,
+    pub parent: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmdVertex {
+    pub bone: usize,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub uv: [f32; 2],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmdTriangle {
+    pub texture: String,
+    pub vertices: [SmdVertex; 3],
+}
+
+/// A studiomdl source file, either a reference mesh with its skeleton or an animation
+#[derive(Debug, Clone, Default)]
+pub struct Smd {
+    pub nodes: Vec<SmdNode>,
+    /// `[frame][node]` position xyz then euler rotation xyz in radians, nodes a frame leaves
+    /// out keep the values of the frame before
+    pub frames: Vec<Vec<[f32; 6]>>,
+    pub triangles: Vec<SmdTriangle>,
+}
+
+fn parse_floats<const N: usize>(words: &[&str]) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+    for (value, word) in values.iter_mut().zip(words.iter()) {
+        *value = word.parse().ok()?;
+    }
+    if words.len() < N {
+        return None
+    }
+    Some(values)
+}
+
+/// Splits a line into words, keeping quoted bone names together
+fn words(line: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            words.push(&quoted[..end]);
+            rest = quoted.get((end + 1)..).unwrap_or_default().trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            words.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+    }
+    words
+}
+
+impl Smd {
+    /// Parses an SMD, errors say which line went wrong
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut smd = Self::default();
+        let mut lines = text.lines().enumerate().map(|(itr, line)| (itr + 1, line));
+        let bad_line = |number: usize, line: &str| format!("line {}: can't make sense of \"{}\"", number, line.trim());
+        while let Some((number, line)) = lines.next() {
+            let line_words = words(line);
+            match line_words.first().copied() {
+                None | Some("version") => continue,
+                Some(word) if word.starts_with("//") => continue,
+                Some("nodes") => {
+                    for (number, line) in lines.by_ref() {
+                        let line_words = words(line);
+                        match line_words.as_slice() {
+                            ["end", ..] => break,
+                            [_, name, parent, ..] => smd.nodes.push(SmdNode {
+                                name: name.to_string(),
+                                parent: parent.parse().map_err(|_| bad_line(number, line))?,
+                            }),
+                            [] => continue,
+                            _ => return Err(bad_line(number, line)),
+                        }
+                    }
+                },
+                Some("skeleton") => {
+                    for (number, line) in lines.by_ref() {
+                        let line_words = words(line);
+                        match line_words.as_slice() {
+                            ["end", ..] => break,
+                            ["time", ..] => {
+                                let frame = smd.frames.last().cloned().unwrap_or_else(|| vec![[0.0; 6]; smd.nodes.len()]);
+                                smd.frames.push(frame);
+                            },
+                            [node, values @ ..] => {
+                                let node: usize = node.parse().map_err(|_| bad_line(number, line))?;
+                                let values = parse_floats::<6>(values).ok_or_else(|| bad_line(number, line))?;
+                                let frame = smd.frames.last_mut().ok_or_else(|| format!("line {}: bone before the first time", number))?;
+                                match frame.get_mut(node) {
+                                    Some(node) => *node = values,
+                                    None => return Err(format!("line {}: bone {} isn't in the nodes", number, node)),
+                                }
+                            },
+                            [] => continue,
+                        }
+                    }
+                },
+                Some("triangles") => {
+                    loop {
+                        let texture = match lines.next() {
+                            Some((_, line)) if line.trim() == "end" => break,
+                            Some((_, line)) if line.trim().is_empty() => continue,
+                            Some((_, line)) => line.trim().to_string(),
+                            None => break,
+                        };
+                        let mut vertices = [SmdVertex { bone: 0, position: [0.0; 3], normal: [0.0; 3], uv: [0.0; 2] }; 3];
+                        for vertex in vertices.iter_mut() {
+                            let (number, line) = lines.next().ok_or_else(|| String::from("the file ends in the middle of a triangle"))?;
+                            let line_words = words(line);
+                            // anything after the uv is Source style weights, which GoldSrc doesn't have
+                            let values = parse_floats::<9>(&line_words).ok_or_else(|| bad_line(number, line))?;
+                            if values[0] < 0.0 || values[0] as usize >= smd.nodes.len() {
+                                return Err(format!("line {}: bone {} isn't in the nodes", number, values[0]));
+                            }
+                            *vertex = SmdVertex {
+                                bone: values[0] as usize,
+                                position: [values[1], values[2], values[3]],
+                                normal: [values[4], values[5], values[6]],
+                                uv: [values[7], values[8]],
+                            };
+                        }
+                        smd.triangles.push(SmdTriangle { texture, vertices });
+                    }
+                },
+                _ => return Err(bad_line(number, line)),
+            }
+        }
+        if smd.nodes.is_empty() {
+            return Err(String::from("no nodes"))
+        }
+        Ok(smd)
+    }
+}
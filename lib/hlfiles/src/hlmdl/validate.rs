@@ -0,0 +1,157 @@
+use std::fmt;
+
+use super::{name_str, Companion, MdlFile};
+
+/// Limits from the SDK's `studio.h`, the engine has fixed size arrays behind every one
+pub mod limits {
+    pub const MAXSTUDIOTRIANGLES: usize = 20000;
+    pub const MAXSTUDIOVERTS: usize = 2048;
+    pub const MAXSTUDIOSEQUENCES: usize = 256;
+    pub const MAXSTUDIOSKINS: usize = 100;
+    pub const MAXSTUDIOBONES: usize = 128;
+    pub const MAXSTUDIOMODELS: usize = 32;
+    pub const MAXSTUDIOBODYPARTS: usize = 32;
+    pub const MAXSTUDIOGROUPS: usize = 16;
+    pub const MAXSTUDIOMESHES: usize = 256;
+    pub const MAXSTUDIOEVENTS: usize = 1024;
+    pub const MAXSTUDIOPIVOTS: usize = 256;
+    pub const MAXSTUDIOCONTROLLERS: usize = 8;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Loads, but looks wrong or depends on something that isn't there
+    Warning,
+    /// The engine refuses the model or crashes on it
+    Error,
+}
+
+/// One thing `MdlFile::validate` found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+    /// The engine limit or behaviour behind the check
+    pub reference: &'static str,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {} ({})", severity, self.message, self.reference)
+    }
+}
+
+struct Report(Vec<Issue>);
+
+impl Report {
+    fn error(&mut self, message: String, reference: &'static str) {
+        self.0.push(Issue { severity: Severity::Error, message, reference });
+    }
+
+    fn warning(&mut self, message: String, reference: &'static str) {
+        self.0.push(Issue { severity: Severity::Warning, message, reference });
+    }
+
+    fn limit(&mut self, what: &str, count: usize, limit: usize, reference: &'static str) {
+        if count > limit {
+            self.error(format!("{} {}, the engine allows {}", count, what, limit), reference);
+        }
+    }
+}
+
+impl MdlFile {
+    /// Checks the model against what GoldSrc will put up with, errors first
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut report = Report(vec![]);
+
+        let data_length = self.header.data_length.max(0) as usize;
+        if data_length > self.data.len() {
+            report.error(format!("header says the file is {} bytes but it is {}", data_length, self.data.len()), "studiohdr_t length");
+        } else if data_length < self.data.len() {
+            report.warning(format!("{} bytes after the end the header gives", self.data.len() - data_length), "studiohdr_t length");
+        }
+
+        report.limit("bones", self.bones.len(), limits::MAXSTUDIOBONES, "MAXSTUDIOBONES");
+        report.limit("bone controllers", self.bone_controllers.len(), limits::MAXSTUDIOCONTROLLERS, "MAXSTUDIOCONTROLLERS");
+        report.limit("sequences", self.sequences.len(), limits::MAXSTUDIOSEQUENCES, "MAXSTUDIOSEQUENCES");
+        report.limit("sequence groups", self.sequence_groups.len(), limits::MAXSTUDIOGROUPS, "MAXSTUDIOGROUPS");
+        report.limit("textures", self.textures.len(), limits::MAXSTUDIOSKINS, "MAXSTUDIOSKINS");
+        report.limit("body parts", self.body_parts.len(), limits::MAXSTUDIOBODYPARTS, "MAXSTUDIOBODYPARTS");
+
+        for (itr, bone) in self.bones.iter().enumerate() {
+            // bones are set up in order, a parent after its child reads a matrix not worked out yet
+            if bone.parent >= itr as i32 {
+                report.error(format!("bone {} \"{}\" has parent {} which comes after it", itr, bone.name(), bone.parent), "R_StudioSetupBones");
+            }
+        }
+        for (itr, controller) in self.bone_controllers.iter().enumerate() {
+            if controller.index < 0 || controller.index as usize >= limits::MAXSTUDIOCONTROLLERS {
+                report.error(format!("bone controller {} has index {}", itr, controller.index), "MAXSTUDIOCONTROLLERS");
+            }
+        }
+
+        for sequence in self.sequences.iter() {
+            let label = sequence.label();
+            report.limit(&format!("events in \"{}\"", label), sequence.events.len(), limits::MAXSTUDIOEVENTS, "MAXSTUDIOEVENTS");
+            report.limit(&format!("pivots in \"{}\"", label), sequence.pivots.len(), limits::MAXSTUDIOPIVOTS, "MAXSTUDIOPIVOTS");
+            if sequence.num_frames < 1 {
+                report.error(format!("sequence \"{}\" has no frames", label), "mstudioseqdesc_t numframes");
+            }
+            if sequence.seq_group < 0 || sequence.seq_group as usize >= self.sequence_groups.len().max(1) {
+                report.error(format!("sequence \"{}\" is in group {} which doesn't exist", label, sequence.seq_group), "mstudioseqdesc_t seqgroup");
+            }
+        }
+        for companion in self.missing_companions() {
+            match companion {
+                Companion::SequenceGroup(group) => report.warning(
+                    format!("sequence group {} \"{}\" is in {} which isn't loaded", group, self.sequence_groups[group].label(), self.companion_name(companion)),
+                    "Mod_LoadStudioModel seqgroup"),
+                Companion::Textures => report.warning(
+                    format!("textures are in {} which isn't loaded", self.companion_name(companion)),
+                    "Mod_LoadStudioModel texture file"),
+            }
+        }
+
+        let num_skin_ref = self.header.num_skin_ref.max(0) as usize;
+        for (family_itr, family) in self.skin_families.iter().enumerate() {
+            for texture in family.iter() {
+                if *texture < 0 || (!self.textures.is_empty() && *texture as usize >= self.textures.len()) {
+                    report.error(format!("skin family {} points at texture {} of {}", family_itr, texture, self.textures.len()), "studiohdr_t skinindex");
+                }
+            }
+        }
+        for texture in self.textures.iter() {
+            let name = name_str(&texture.header.name);
+            let (width, height) = (texture.header.width, texture.header.height);
+            if width % 8 != 0 || height % 8 != 0 {
+                report.warning(format!("texture \"{}\" is {}x{}, not a multiple of 8", name, width, height), "studiomdl texture size");
+            }
+            if !width.is_power_of_two() || !height.is_power_of_two() {
+                report.warning(format!("texture \"{}\" is {}x{}, the renderer resamples it to a power of two", name, width, height), "GL_Upload8 resample");
+            }
+        }
+
+        for body_part in self.body_parts.iter() {
+            report.limit(&format!("models in body part \"{}\"", body_part.name()), body_part.models.len(), limits::MAXSTUDIOMODELS, "MAXSTUDIOMODELS");
+            for model in body_part.models.iter() {
+                let name = model.name();
+                report.limit(&format!("vertices in \"{}\"", name), model.vertices.len(), limits::MAXSTUDIOVERTS, "MAXSTUDIOVERTS");
+                report.limit(&format!("normals in \"{}\"", name), model.normals.len(), limits::MAXSTUDIOVERTS, "MAXSTUDIOVERTS");
+                report.limit(&format!("meshes in \"{}\"", name), model.meshes.len(), limits::MAXSTUDIOMESHES, "MAXSTUDIOMESHES");
+                report.limit(&format!("triangles in \"{}\"", name), model.num_triangles(), limits::MAXSTUDIOTRIANGLES, "MAXSTUDIOTRIANGLES");
+                for mesh in model.meshes.iter() {
+                    if mesh.skin_ref < 0 || (num_skin_ref > 0 && mesh.skin_ref as usize >= num_skin_ref) {
+                        report.error(format!("a mesh in \"{}\" uses skin ref {} of {}", name, mesh.skin_ref, num_skin_ref), "studiohdr_t numskinref");
+                    }
+                }
+            }
+        }
+
+        report.0.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+        report.0
+    }
+}
@@ -0,0 +1,338 @@
+use super::{MdlFile, MeshGeometry, TextureFlags};
+use crate::math::{self, Mat3x4, Vec3};
+
+const FOV_DEGREES: f32 = 65.0;
+/// Anything closer to the camera than this gets dropped instead of clipped
+const NEAR: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Textured,
+    Wireframe,
+    Normals,
+}
+
+/// A triangle in screen space waiting to be sorted
+struct ScreenTriangle {
+    depth: f32,
+    texture: Option<usize>,
+    positions: [egui::Pos2; 3],
+    uvs: [egui::Pos2; 3],
+    colors: [egui::Color32; 3],
+}
+
+/// Software rendered 3D view of a model, painted back to front through egui's painter
+pub struct ModelViewport {
+    pub mode: RenderMode,
+    /// Picks one model per body part, see `MdlFile::body_models`
+    pub body: i32,
+    pub skin_family: usize,
+    pub cull_back_faces: bool,
+    /// Draws every bone's axes and a line to its parent over the model
+    pub show_bones: bool,
+    pub show_attachments: bool,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    target: Vec3,
+    framed: bool,
+    /// Geometry of the models the current body value selects, for that body and skin family
+    geometry: Option<(i32, usize, Vec<MeshGeometry>)>,
+}
+
+impl Default for ModelViewport {
+    fn default() -> Self {
+        Self {
+            mode: RenderMode::Textured,
+            body: 0,
+            skin_family: 0,
+            cull_back_faces: true,
+            show_bones: false,
+            show_attachments: false,
+            yaw: 0.0,
+            pitch: 0.3,
+            distance: 100.0,
+            target: [0.0; 3],
+            framed: false,
+            geometry: None,
+        }
+    }
+}
+
+impl ModelViewport {
+    /// Drops cached geometry, for when the textures its UVs were normalized against change
+    pub fn reload(&mut self) {
+        self.geometry = None;
+    }
+
+    fn geometry(&mut self, mdl_file: &MdlFile) -> &Vec<MeshGeometry> {
+        if !matches!(&self.geometry, Some((body, skin_family, _)) if *body == self.body && *skin_family == self.skin_family) {
+            let geometry = mdl_file.body_models(self.body).iter()
+                .flat_map(|(body_part, model)| mdl_file.model_geometry(*body_part, *model, self.skin_family))
+                .collect();
+            self.geometry = Some((self.body, self.skin_family, geometry));
+        }
+        &self.geometry.as_ref().unwrap().2
+    }
+
+    /// Points the camera at the middle of the posed model and backs off far enough to see all of it
+    fn frame(&mut self, mdl_file: &MdlFile, bones: &[Mat3x4]) {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for mesh in self.geometry(mdl_file).iter() {
+            for vertex in mesh.vertices.iter() {
+                let position = match bones.get(vertex.bone as usize) {
+                    Some(bone) => math::transform_point(bone, vertex.position),
+                    None => vertex.position,
+                };
+                for itr in 0..3 {
+                    min[itr] = min[itr].min(position[itr]);
+                    max[itr] = max[itr].max(position[itr]);
+                }
+            }
+        }
+        if min[0] > max[0] {
+            return
+        }
+        self.target = math::lerp(min, max, 0.5);
+        let radius = math::length(math::sub(max, min)) * 0.5;
+        self.distance = (radius / (FOV_DEGREES.to_radians() * 0.5).tan() * 1.2).max(NEAR * 2.0);
+        self.yaw = 0.0;
+        self.pitch = 0.3;
+    }
+
+    /// Render mode, bodygroup and skin pickers
+    pub fn controls_ui(&mut self, ui: &mut egui::Ui, mdl_file: &MdlFile) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, RenderMode::Textured, "Textured");
+            ui.radio_value(&mut self.mode, RenderMode::Wireframe, "Wireframe");
+            ui.radio_value(&mut self.mode, RenderMode::Normals, "Normals");
+            ui.checkbox(&mut self.cull_back_faces, "Cull back faces");
+            ui.checkbox(&mut self.show_bones, "Bones");
+            ui.checkbox(&mut self.show_attachments, "Attachments");
+            if ui.button("Reset view").clicked() {
+                self.framed = false;
+            }
+        });
+        ui.horizontal_wrapped(|ui| {
+            for (itr, body_part) in mdl_file.body_parts.iter().enumerate() {
+                if body_part.num_models < 2 {
+                    continue;
+                }
+                let base = body_part.base.max(1);
+                let current = (self.body / base) % body_part.num_models;
+                let mut selected = current;
+                egui::ComboBox::from_id_source(("viewport_body_part", itr))
+                    .selected_text(format!("{}: {}", body_part.name(), body_part.models.get(current as usize).map(|model| model.name()).unwrap_or_default()))
+                    .show_ui(ui, |ui| {
+                        for (model_itr, model) in body_part.models.iter().enumerate() {
+                            ui.selectable_value(&mut selected, model_itr as i32, model.name());
+                        }
+                    });
+                self.body += (selected - current) * base;
+            }
+            if mdl_file.skin_families.len() > 1 {
+                egui::ComboBox::from_id_source("viewport_skin")
+                    .selected_text(format!("skin {}", self.skin_family))
+                    .show_ui(ui, |ui| {
+                        for itr in 0..mdl_file.skin_families.len() {
+                            ui.selectable_value(&mut self.skin_family, itr, format!("skin {}", itr));
+                        }
+                    });
+            }
+        });
+    }
+
+    /// Draws the model posed by `bones`, `textures` line up with `MdlFile::textures`
+    pub fn ui(&mut self, ui: &mut egui::Ui, mdl_file: &MdlFile, textures: &[egui::TextureHandle], bones: &[Mat3x4]) {
+        let size = egui::vec2(ui.available_width().max(256.0), 400.0);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::drag());
+
+        if !self.framed {
+            self.frame(mdl_file, bones);
+            self.framed = true;
+        }
+        let drag = response.drag_delta();
+        self.yaw -= drag.x * 0.01;
+        self.pitch = (self.pitch + drag.y * 0.01).clamp(-1.5, 1.5);
+        if response.hovered() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            self.distance = (self.distance * (1.0 - scroll * 0.001)).max(NEAR * 2.0);
+        }
+
+        // orbit around the target, z is up in Half-Life
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let eye = math::add(self.target, math::scale([cos_pitch * cos_yaw, cos_pitch * sin_yaw, sin_pitch], self.distance));
+        let forward = math::normalize(math::sub(self.target, eye));
+        let right = math::normalize(math::cross(forward, [0.0, 0.0, 1.0]));
+        let up = math::cross(right, forward);
+        let focal = rect.height() * 0.5 / (FOV_DEGREES.to_radians() * 0.5).tan();
+        let center = rect.center();
+        let project = |position: Vec3| -> (egui::Pos2, f32) {
+            let relative = math::sub(position, eye);
+            let depth = math::dot(relative, forward);
+            let x = math::dot(relative, right) / depth * focal;
+            let y = math::dot(relative, up) / depth * focal;
+            (egui::pos2(center.x + x, center.y - y), depth)
+        };
+
+        let mode = self.mode;
+        let cull_back_faces = self.cull_back_faces;
+        let skin_family = self.skin_family;
+        let mut triangles = vec![];
+        for mesh in self.geometry(mdl_file).iter() {
+            let texture = mdl_file.skin_texture_index(skin_family, mesh.skin_ref);
+            let flags = mdl_file.textures.get(texture).map(|texture| texture.header.flags).unwrap_or_default();
+            let texture = if texture < textures.len() { Some(texture) } else { None };
+            let projected: Vec<(egui::Pos2, f32, Vec3)> = mesh.vertices.iter()
+                .map(|vertex| {
+                    let (position, normal) = match (bones.get(vertex.bone as usize), bones.get(vertex.normal_bone as usize)) {
+                        (Some(bone), Some(normal_bone)) => (math::transform_point(bone, vertex.position), math::rotate_vector(normal_bone, vertex.normal)),
+                        _ => (vertex.position, vertex.normal),
+                    };
+                    let (screen, depth) = project(position);
+                    (screen, depth, math::normalize(normal))
+                })
+                .collect();
+            // headlight so whatever faces the camera is lit
+            let light = |normal: Vec3| 96.0 + 159.0 * (-math::dot(normal, forward)).max(0.0);
+            for triangle in mesh.indices.chunks_exact(3) {
+                let corners = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+                if corners.iter().any(|corner| projected[*corner].1 < NEAR) {
+                    continue;
+                }
+                let positions = corners.map(|corner| projected[corner].0);
+                if cull_back_faces {
+                    // the engine culls GL_FRONT, so faces it draws are clockwise on screen
+                    let ab = positions[1] - positions[0];
+                    let ac = positions[2] - positions[0];
+                    if ab.x * ac.y - ab.y * ac.x < 0.0 {
+                        continue;
+                    }
+                }
+                let normals = corners.map(|corner| projected[corner].2);
+                let colors = match mode {
+                    RenderMode::Normals => normals.map(|normal| egui::Color32::from_rgb(
+                        ((normal[0] * 0.5 + 0.5) * 255.0) as u8,
+                        ((normal[1] * 0.5 + 0.5) * 255.0) as u8,
+                        ((normal[2] * 0.5 + 0.5) * 255.0) as u8)),
+                    _ => {
+                        let shades = if flags.contains(TextureFlags::FULLBRIGHT) {
+                            [255.0; 3]
+                        } else if flags.contains(TextureFlags::FLATSHADE) {
+                            [normals.iter().map(|normal| light(*normal)).sum::<f32>() / 3.0; 3]
+                        } else {
+                            normals.map(light)
+                        };
+                        // no alpha with premultiplied blending adds onto what is behind
+                        let alpha = if flags.contains(TextureFlags::ADDITIVE) { 0 } else { 255 };
+                        shades.map(|shade| egui::Color32::from_rgba_premultiplied(shade as u8, shade as u8, shade as u8, alpha))
+                    },
+                };
+                let uvs = if flags.contains(TextureFlags::CHROME) {
+                    // chrome is a reflection, the texture follows the normal as seen from the camera
+                    normals.map(|normal| egui::pos2(math::dot(normal, right) * 0.5 + 0.5, -math::dot(normal, up) * 0.5 + 0.5))
+                } else {
+                    corners.map(|corner| egui::pos2(mesh.vertices[corner].uv[0], mesh.vertices[corner].uv[1]))
+                };
+                triangles.push(ScreenTriangle {
+                    depth: corners.iter().map(|corner| projected[*corner].1).sum::<f32>() / 3.0,
+                    texture: if mode == RenderMode::Textured { texture } else { None },
+                    positions,
+                    uvs,
+                    colors,
+                });
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(32));
+        if mode == RenderMode::Wireframe {
+            let stroke = egui::Stroke::new(1.0, egui::Color32::from_gray(200));
+            let lines = triangles.iter()
+                .flat_map(|triangle| (0..3).map(move |itr| egui::Shape::line_segment([triangle.positions[itr], triangle.positions[(itr + 1) % 3]], stroke)))
+                .collect();
+            painter.add(egui::Shape::Vec(lines));
+            self.paint_gizmos(&painter, mdl_file, bones, &project);
+            return
+        }
+
+        // painter's algorithm, far to near, one egui mesh per run of triangles sharing a texture
+        triangles.sort_by(|a, b| b.depth.total_cmp(&a.depth));
+        let mut shapes = vec![];
+        let mut current: Option<(Option<usize>, egui::Mesh)> = None;
+        for triangle in triangles.iter() {
+            if !matches!(&current, Some((texture, _)) if *texture == triangle.texture) {
+                if let Some((_, mesh)) = current.take() {
+                    shapes.push(egui::Shape::mesh(mesh));
+                }
+                let texture_id = match triangle.texture {
+                    Some(texture) => textures[texture].id(),
+                    None => egui::TextureId::default(),
+                };
+                current = Some((triangle.texture, egui::Mesh::with_texture(texture_id)));
+            }
+            let (_, mesh) = current.as_mut().unwrap();
+            for itr in 0..3 {
+                mesh.vertices.push(egui::epaint::Vertex {
+                    pos: triangle.positions[itr],
+                    uv: if triangle.texture.is_some() { triangle.uvs[itr] } else { egui::epaint::WHITE_UV },
+                    color: triangle.colors[itr],
+                });
+                mesh.indices.push((mesh.vertices.len() - 1) as u32);
+            }
+        }
+        if let Some((_, mesh)) = current {
+            shapes.push(egui::Shape::mesh(mesh));
+        }
+        painter.extend(shapes);
+        self.paint_gizmos(&painter, mdl_file, bones, &project);
+    }
+
+    /// Bone axes and attachment points, drawn on top of the model so they never hide behind it
+    fn paint_gizmos(&self, painter: &egui::Painter, mdl_file: &MdlFile, bones: &[Mat3x4], project: &dyn Fn(Vec3) -> (egui::Pos2, f32)) {
+        let mut shapes = vec![];
+        if self.show_bones {
+            // x red, y green, z blue like every other editor
+            let axis_colors = [egui::Color32::RED, egui::Color32::GREEN, egui::Color32::from_rgb(64, 128, 255)];
+            let axis_length = self.distance * 0.03;
+            for (itr, bone) in bones.iter().enumerate() {
+                let origin = [bone[0][3], bone[1][3], bone[2][3]];
+                let (screen, depth) = project(origin);
+                if depth < NEAR {
+                    continue;
+                }
+                let parent = mdl_file.bones.get(itr).map(|bone| bone.parent).unwrap_or(-1);
+                if let Some(parent) = bones.get(parent.max(0) as usize).filter(|_| parent >= 0) {
+                    let (parent_screen, parent_depth) = project([parent[0][3], parent[1][3], parent[2][3]]);
+                    if parent_depth >= NEAR {
+                        shapes.push(egui::Shape::line_segment([parent_screen, screen], egui::Stroke::new(1.0, egui::Color32::YELLOW)));
+                    }
+                }
+                for (axis, color) in axis_colors.iter().enumerate() {
+                    let mut direction = [0.0; 3];
+                    direction[axis] = axis_length;
+                    let (end, end_depth) = project(math::add(origin, math::rotate_vector(bone, direction)));
+                    if end_depth >= NEAR {
+                        shapes.push(egui::Shape::line_segment([screen, end], egui::Stroke::new(1.5, *color)));
+                    }
+                }
+            }
+        }
+        if self.show_attachments {
+            let color = egui::Color32::from_rgb(255, 0, 255);
+            for (itr, position) in mdl_file.attachment_positions(bones).into_iter().enumerate() {
+                let (screen, depth) = project(position);
+                if depth < NEAR {
+                    continue;
+                }
+                shapes.push(egui::Shape::circle_stroke(screen, 4.0, egui::Stroke::new(1.5, color)));
+                let name = mdl_file.attachments[itr].name();
+                let label = if name.is_empty() { format!("{}", itr) } else { format!("{} {}", itr, name) };
+                painter.text(screen + egui::vec2(6.0, -6.0), egui::Align2::LEFT_BOTTOM, label, egui::FontId::monospace(11.0), color);
+            }
+        }
+        painter.extend(shapes);
+    }
+}
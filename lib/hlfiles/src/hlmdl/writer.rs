@@ -0,0 +1,148 @@
+use super::{check_bounds, MdlError, MdlFile, MdlHeader, Texture, TEXTURE_HEADER_SIZE};
+
+/// Offset of `length` in `studiohdr_t`, texture files share the header
+const LENGTH_OFFSET: usize = 72;
+const PALETTE_SIZE: usize = 256 * 3;
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..(offset + 4)].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_i16(buf: &mut [u8], offset: usize, value: i16) {
+    buf[offset..(offset + 2)].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..(offset + 4)].try_into().unwrap())
+}
+
+/// Writes `textures` over the ones in a model file, in place when a texture's pixel count didn't
+/// change and appended to the end of the file otherwise
+fn write_textures(buf: &mut Vec<u8>, textures: &[Texture]) -> Result<(), MdlError> {
+    let header = MdlHeader::from_bytes(buf)?;
+    if header.num_textures as usize != textures.len() {
+        return Err(MdlError::BadCount { what: "textures", count: textures.len() as i64 })
+    }
+    check_bounds("texture headers", header.texture_index, header.num_textures, TEXTURE_HEADER_SIZE, buf.len())?;
+    for (itr, texture) in textures.iter().enumerate() {
+        let header_offset = header.texture_index as usize + itr * TEXTURE_HEADER_SIZE;
+        let old_size = read_u32(buf, header_offset + 68) as usize * read_u32(buf, header_offset + 72) as usize;
+        let old_index = read_u32(buf, header_offset + 76) as usize;
+        let size = texture.header.width as usize * texture.header.height as usize;
+        if texture.raw_data.len() != size {
+            return Err(MdlError::BadCount { what: "texture pixels", count: texture.raw_data.len() as i64 })
+        }
+        let index = if size == old_size && old_index + size + PALETTE_SIZE <= buf.len() {
+            old_index
+        } else {
+            // the old pixels are left behind as dead space
+            buf.resize((buf.len() + 3) & !3, 0);
+            let index = buf.len();
+            buf.resize(index + size + PALETTE_SIZE, 0);
+            index
+        };
+        buf[index..(index + size)].copy_from_slice(&texture.raw_data);
+        buf[(index + size)..(index + size + PALETTE_SIZE)].copy_from_slice(&texture.palette_bytes());
+
+        buf[header_offset..(header_offset + 64)].copy_from_slice(&texture.header.name);
+        write_u32(buf, header_offset + 64, texture.header.flags.bits());
+        write_u32(buf, header_offset + 68, texture.header.width);
+        write_u32(buf, header_offset + 72, texture.header.height);
+        write_u32(buf, header_offset + 76, index as u32);
+    }
+    let length = buf.len() as u32;
+    write_u32(buf, LENGTH_OFFSET, length);
+    Ok(())
+}
+
+impl MdlFile {
+    /// Writes the texture coordinates of every triangle command back, the commands keep their
+    /// layout so they always fit where they came from
+    fn write_triangle_commands(&self, buf: &mut [u8]) {
+        let meshes = self.body_parts.iter()
+            .flat_map(|body_part| body_part.models.iter())
+            .flat_map(|model| model.meshes.iter());
+        for mesh in meshes {
+            let mut offset = mesh.tri_index as usize;
+            for command in mesh.commands.iter() {
+                offset += 2;
+                for vertex in command.vertices.iter() {
+                    write_i16(buf, offset + 4, vertex.s);
+                    write_i16(buf, offset + 6, vertex.t);
+                    offset += 8;
+                }
+            }
+        }
+    }
+
+    /// Swaps a texture for an image, rescaling the texture coordinates of the meshes that use it
+    /// in any skin family when the size changes
+    pub fn replace_texture(&mut self, index: usize, image: image::RgbImage) -> Result<(), MdlError> {
+        let old_header = match self.textures.get(index) {
+            Some(texture) => texture.header.clone(),
+            None => return Err(MdlError::BadCount { what: "texture", count: index as i64 }),
+        };
+        let texture = Texture::from_image(image, &old_header);
+        let scale_s = texture.header.width as f32 / old_header.width.max(1) as f32;
+        let scale_t = texture.header.height as f32 / old_header.height.max(1) as f32;
+        if scale_s != 1.0 || scale_t != 1.0 {
+            let families = self.skin_families.len().max(1);
+            let mut skin_refs = vec![];
+            for skin_ref in 0..(self.header.num_skin_ref.max(self.textures.len() as i32).max(0) as usize) {
+                let textures: Vec<usize> = (0..families).map(|family| self.skin_texture_index(family, skin_ref)).collect();
+                if !textures.contains(&index) {
+                    continue;
+                }
+                // the other texture keeps its size, so the coordinates would need two scales
+                if textures.iter().any(|texture| *texture != index) {
+                    return Err(MdlError::SkinConflict { skin_ref, texture: index })
+                }
+                skin_refs.push(skin_ref as i32);
+            }
+            let rescale = |coord: i16, scale: f32| -> Option<i16> {
+                let coord = (coord as f32 * scale).round();
+                if coord >= i16::MIN as f32 && coord <= i16::MAX as f32 { Some(coord as i16) } else { None }
+            };
+            // every coordinate is checked before any is written, so a resize that doesn't fit
+            // leaves the model as it was
+            let vertices = self.body_parts.iter()
+                .flat_map(|body_part| body_part.models.iter())
+                .flat_map(|model| model.meshes.iter())
+                .filter(|mesh| skin_refs.contains(&mesh.skin_ref))
+                .flat_map(|mesh| mesh.commands.iter().flat_map(|command| command.vertices.iter()));
+            for vertex in vertices {
+                if rescale(vertex.s, scale_s).is_none() || rescale(vertex.t, scale_t).is_none() {
+                    return Err(MdlError::TexCoordOutOfRange { texture: index, s: vertex.s, t: vertex.t })
+                }
+            }
+            let meshes = self.body_parts.iter_mut()
+                .flat_map(|body_part| body_part.models.iter_mut())
+                .flat_map(|model| model.meshes.iter_mut())
+                .filter(|mesh| skin_refs.contains(&mesh.skin_ref));
+            for mesh in meshes {
+                for vertex in mesh.commands.iter_mut().flat_map(|command| command.vertices.iter_mut()) {
+                    vertex.s = rescale(vertex.s, scale_s).unwrap();
+                    vertex.t = rescale(vertex.t, scale_t).unwrap();
+                }
+            }
+        }
+        self.textures[index] = texture;
+        Ok(())
+    }
+
+    /// The model with its textures and texture coordinates written back
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MdlError> {
+        let mut buf = self.data.clone();
+        if self.texture_data.is_none() {
+            write_textures(&mut buf, &self.textures)?;
+        }
+        self.write_triangle_commands(&mut buf);
+        Ok(buf)
+    }
+
+    /// The `modelT.mdl` with its textures written back, when the textures came from one
+    pub fn texture_file_to_bytes(&self) -> Option<Result<Vec<u8>, MdlError>> {
+        let mut buf = self.texture_data.clone()?;
+        Some(write_textures(&mut buf, &self.textures).map(|_| buf))
+    }
+}
@@ -1,9 +1,10 @@
 use image::buffer::ConvertBuffer;
 use rgb::ComponentBytes;
-use rgb::FromSlice;
 use std::io::{BufWriter, Cursor, Read};
+use std::cell::OnceCell;
 use std::fs::File;
 use std::path::Path;
+use std::sync::Arc;
 use std::{fmt, default};
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
@@ -103,6 +104,12 @@ impl DirectoryEntry {
         ret_vec
     }
 
+    /// The name up to its null terminator, without allocating
+    pub fn name_bytes(&self) -> &[u8] {
+        let len = self.sz_name.iter().position(|byte| *byte == 0).unwrap_or(DIRECTORY_ENTRY_NAME_SIZE);
+        &self.sz_name[..len]
+    }
+
     pub fn name_str(&self) -> Option<String> {
         let mut ret_opt: Option<String> = None;
         for itr in 0..DIRECTORY_ENTRY_NAME_SIZE {
@@ -130,6 +137,152 @@ impl fmt::Debug for DirectoryEntry {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WadError {
+    TooShort { len: usize },
+    BadMagic([u8; 4]),
+    DirectoryOutOfBounds { offset: u32, count: u32 },
+    LumpOutOfBounds { index: usize, offset: u32, size: u32 },
+    /// The lump is too small for its palette, or its first mip doesn't fit in it
+    BadTexture { index: usize },
+}
+
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WadError::TooShort { len } => write!(f, "{} bytes is too short for a WAD header", len),
+            WadError::BadMagic(magic) => write!(f, "bad magic {:?}, expected WAD3", String::from_utf8_lossy(magic)),
+            WadError::DirectoryOutOfBounds { offset, count } => write!(f, "directory of {} entries at {} runs past the end of the file", count, offset),
+            WadError::LumpOutOfBounds { index, offset, size } => write!(f, "lump {} ({} bytes at {}) runs past the end of the file", index, size, offset),
+            WadError::BadTexture { index } => write!(f, "texture {} doesn't fit in its lump", index),
+        }
+    }
+}
+
+impl std::error::Error for WadError {}
+
+/// Borrows a WAD's bytes and hands out directory entries and raw lumps without copying anything
+#[derive(Clone, Copy)]
+pub struct WadReader<'a> {
+    data: &'a [u8],
+    header: WadHeader,
+}
+
+impl<'a> WadReader<'a> {
+    /// Checks the header, that the directory fits and that every lump it points at fits
+    pub fn new(data: &'a [u8]) -> Result<Self, WadError> {
+        if data.len() < WAD_HEADER_SIZE {
+            return Err(WadError::TooShort { len: data.len() })
+        }
+        let header = WadHeader::from_bytes(data[0..WAD_HEADER_SIZE].try_into().unwrap());
+        if &header.sz_magic != b"WAD3" {
+            return Err(WadError::BadMagic(header.sz_magic))
+        }
+        let dir_end = (header.n_dir as usize)
+            .checked_mul(DIRECTORY_ENTRY_SIZE)
+            .and_then(|dir_size| dir_size.checked_add(header.n_dir_offset as usize));
+        match dir_end {
+            Some(dir_end) if dir_end <= data.len() => (),
+            _ => return Err(WadError::DirectoryOutOfBounds { offset: header.n_dir_offset, count: header.n_dir }),
+        }
+        let reader = Self { data, header };
+        for (index, entry) in reader.entries().enumerate() {
+            let lump_end = (entry.n_file_pos as usize).checked_add(entry.n_disk_size as usize);
+            match lump_end {
+                Some(lump_end) if lump_end <= data.len() => (),
+                _ => return Err(WadError::LumpOutOfBounds { index, offset: entry.n_file_pos, size: entry.n_disk_size }),
+            }
+        }
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> WadHeader {
+        self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.n_dir as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.n_dir == 0
+    }
+
+    pub fn entry(&self, index: usize) -> Option<DirectoryEntry> {
+        if index >= self.len() {
+            return None
+        }
+        let entry_offset = (self.header.n_dir_offset as usize) + (index * DIRECTORY_ENTRY_SIZE);
+        Some(DirectoryEntry::from_bytes(self.data[entry_offset..(entry_offset + DIRECTORY_ENTRY_SIZE)].try_into().unwrap()))
+    }
+
+    pub fn entries(&self) -> DirectoryEntries<'a> {
+        DirectoryEntries {
+            reader: *self,
+            index: 0,
+        }
+    }
+
+    /// The raw bytes of a lump as stored on disk
+    pub fn lump(&self, entry: &DirectoryEntry) -> &'a [u8] {
+        let start = entry.n_file_pos as usize;
+        &self.data[start..(start + entry.n_disk_size as usize)]
+    }
+
+    pub fn lumps(&self) -> impl Iterator<Item = (DirectoryEntry, &'a [u8])> + 'a {
+        let reader = *self;
+        self.entries().map(move |entry| (entry, reader.lump(&entry)))
+    }
+
+    /// Looks a lump up by name, ignoring case like the engine does
+    pub fn find(&self, name: &str) -> Option<(DirectoryEntry, &'a [u8])> {
+        self.lumps().find(|(entry, _)| entry.name_bytes().eq_ignore_ascii_case(name.as_bytes()))
+    }
+}
+
+pub struct DirectoryEntries<'a> {
+    reader: WadReader<'a>,
+    index: usize,
+}
+
+impl<'a> Iterator for DirectoryEntries<'a> {
+    type Item = DirectoryEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.reader.entry(self.index)?;
+        self.index += 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.reader.len().saturating_sub(self.index);
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for DirectoryEntries<'a> {}
+
+/// A WAD mapped into memory, for reading big files without loading them
+#[cfg(not(target_arch = "wasm32"))]
+pub struct WadMmap {
+    mmap: memmap2::Mmap,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WadMmap {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        // the file is only ever read, nothing here can observe it changing underneath us other
+        // than bad data which WadReader already checks for
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self { mmap })
+    }
+
+    pub fn reader(&self) -> Result<WadReader<'_>, WadError> {
+        WadReader::new(&self.mmap)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
     pub r: u8,
@@ -176,7 +329,7 @@ impl TextureHeader {
         }
     }
 
-    fn to_vec(&self) -> Vec<u8> {
+    pub(crate) fn to_vec(&self) -> Vec<u8> {
         let mut ret_vec = Vec::<u8>::new();
         ret_vec.append(&mut self.sz_name.to_vec());
         ret_vec.append(&mut self.n_width.to_le_bytes().to_vec());
@@ -215,7 +368,6 @@ pub struct Texture {
     pub header: TextureHeader,
     pub data: Vec<u8>,
     pub palette: [Color; 256],
-    pub image: image::RgbImage,
 }
 
 impl Texture {
@@ -230,23 +382,79 @@ impl Texture {
             palette_offset_itr += 3;
         }
         let data = wad_data[((entry.n_file_pos as usize) + TEXTURE_HEADER_SIZE)..palette_offset].to_vec();
-        let mut image_vec = Vec::<u8>::new();
-        for itr in 0..((header.n_width*header.n_height) as usize) {
-            let color = palette[data[itr] as usize];
-            //image_vec.push(color.r);
-            //image_vec.push(color.g);
-            //image_vec.push(color.b);
-            image_vec.append(&mut color.to_vec());
-        }
-        let image = image::RgbImage::from_vec(header.n_width, header.n_height, image_vec).expect("Could not create image from texture");
         Self {
             header,
             data,
             palette,
-            image, 
         }
     }
 
+    /// A miptex on its own, the way BSP texture lumps keep them. `None` when it only names a
+    /// texture that lives in a WAD, or its mips and palette don't fit in `buf`
+    pub fn from_miptex(buf: &[u8]) -> Option<Self> {
+        let header = TextureHeader::from_bytes(buf.get(0..TEXTURE_HEADER_SIZE)?.try_into().ok()?);
+        if header.mip_offsets[0] == 0 {
+            return None
+        }
+        // the smallest mip is an eighth of the size each way, the palette's color count follows it
+        // usize is 32 bits on the web, so a garbage header could overflow the sums
+        let last_mip_size = ((header.n_width / 8) as usize).checked_mul((header.n_height / 8) as usize)?;
+        let palette_offset = (header.mip_offsets[3] as usize).checked_add(last_mip_size)?.checked_add(2)?;
+        let palette_bytes = buf.get(palette_offset..palette_offset.checked_add(256 * 3)?)?;
+        for (level, offset) in header.mip_offsets.iter().enumerate() {
+            let size = ((header.n_width >> level) as usize).checked_mul((header.n_height >> level) as usize)?;
+            if (*offset as usize) < TEXTURE_HEADER_SIZE || (*offset as usize).checked_add(size)? > palette_offset - 2 {
+                return None
+            }
+        }
+        let mut palette: [Color; 256] = [Color::new(0, 0, 0); 256];
+        for (color, bytes) in palette.iter_mut().zip(palette_bytes.chunks_exact(3)) {
+            *color = Color::new(bytes[0], bytes[1], bytes[2]);
+        }
+        Some(Self {
+            header,
+            data: buf[TEXTURE_HEADER_SIZE..palette_offset].to_vec(),
+            palette,
+        })
+    }
+
+    /// A 16x16 black and magenta checker named `sz_name`, stands in for a texture whose lump
+    /// couldn't be decoded
+    pub fn placeholder(sz_name: [u8; DIRECTORY_ENTRY_NAME_SIZE]) -> Self {
+        let mut data = vec![];
+        let mut mip_offsets = [0; 4];
+        for (level, offset) in mip_offsets.iter_mut().enumerate() {
+            *offset = (TEXTURE_HEADER_SIZE + data.len()) as u32;
+            let size = 16 >> level;
+            let cell = 8 >> level;
+            for y in 0..size {
+                for x in 0..size {
+                    data.push(((x / cell + y / cell) % 2) as u8);
+                }
+            }
+        }
+        // the palette's color count
+        data.extend_from_slice(&256u16.to_le_bytes());
+        let mut palette = [Color::new(0, 0, 0); 256];
+        palette[1] = Color::new(255, 0, 255);
+        Self {
+            header: TextureHeader {
+                sz_name,
+                n_width: 16,
+                n_height: 16,
+                mip_offsets,
+            },
+            data,
+            palette,
+        }
+    }
+
+    /// Full size mip level as an image, built on demand so textures don't carry a second copy
+    pub fn to_image(&self) -> image::RgbImage {
+        image::RgbImage::from_vec(self.header.n_width, self.header.n_height, self.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0))
+            .expect("Could not create image from texture")
+    }
+
     fn to_array(strings: &[&str] ) -> js_sys::Array {
         let arr = js_sys::Array::new_with_length(strings.len() as u32);
         for (i, s) in strings.iter().enumerate() {
@@ -255,37 +463,25 @@ impl Texture {
         arr
     }
 
-    fn quantize_images(images: Vec<image::RgbImage>) -> (Vec<rgb::RGBA<u8>>, Vec<u8>) {
-        let mut attributes = imagequant::new(); 
+    /// One shared palette for all the images, and each image's indices into it back to back
+    pub(crate) fn quantize_images(images: Vec<image::RgbImage>) -> (Vec<rgb::RGBA<u8>>, Vec<u8>) {
+        let attributes = imagequant::new(); 
         let mut histogram = imagequant::Histogram::new(&attributes);
         let mut quant_images = vec![];
         for image in images.iter() {
-            let quantizer = imagequant::new();
             let (width, height) = image.dimensions();
-            web_sys::console::debug_3(&"USE ME".into(), &width.into(), &height.into());
-            let tmp = image.to_vec();
-            let mut rgba_image_raw = vec![];
-            let mut itr = 0;
-            for pixel in tmp.iter() {
-                rgba_image_raw.push(*pixel);
-                if itr % 4 == 3 {
-                    rgba_image_raw.push(0);
-                    itr += 1;
-                }
-                itr += 1;
-            }
-            rgba_image_raw.push(0);
-            let rgba_image = rgba_image_raw.as_rgba();
-            let mut quant_image = quantizer.new_image(rgba_image, width as usize, height as usize, 0.0).unwrap();
-            histogram.add_image(&quantizer, &mut quant_image);
+            let rgba_image: Vec<rgb::RGBA<u8>> = image.pixels()
+                .map(|pixel| rgb::RGBA::new(pixel[0], pixel[1], pixel[2], 255))
+                .collect();
+            let mut quant_image = attributes.new_image(rgba_image, width as usize, height as usize, 0.0).unwrap();
+            histogram.add_image(&attributes, &mut quant_image).expect("failure big style");
             quant_images.push(quant_image);
         }
         let mut result = histogram.quantize(&attributes).expect("failure big style");
         let mut indices_ret_vec = vec![];
-        for (idx, image) in images.iter().enumerate() {
-            let (_, mut indices) = result.remapped(&mut quant_images[idx]).unwrap();
+        for quant_image in quant_images.iter_mut() {
+            let (_, mut indices) = result.remapped(quant_image).unwrap();
             indices_ret_vec.append(&mut indices);
-            web_sys::console::debug_3(&"USE ME".into(), &indices_ret_vec.len().into(), &idx.into());
         }
         let palette = result.palette_vec();
         (palette, indices_ret_vec)
@@ -297,7 +493,7 @@ impl Texture {
         let (palette_vec, mut indices_vec) = Texture::quantize_images(images);
         let mut palette_array: [Color; 256] = [Color::new(0, 0, 0); 256];
         for (itr, color) in palette_vec.iter().enumerate() {
-            let r = color.a;
+            let r = color.r;
             let g = color.g;
             let b = color.b;
             palette_array[itr] = Color::new(r, g, b);
@@ -310,7 +506,6 @@ impl Texture {
         let mut size = (width/4) * (height/4);
         let mip_offset_4 = mip_offset_3 + size;
         let mip_offsets = [mip_offset_1, mip_offset_2, mip_offset_3, mip_offset_4];
-        web_sys::console::debug_5(&"HELP ME".into(), &mip_offset_1.into(), &mip_offset_2.into(), &mip_offset_3.into(), &mip_offset_4.into());
         let header = TextureHeader {
             sz_name: [b'c', b'u', b'm', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
             n_width: width, 
@@ -320,18 +515,10 @@ impl Texture {
         let mut data_vec = vec![];
         data_vec.append(&mut indices_vec);
         data_vec.append(&mut vec![0, 0]);
-        let mut rgb_image_vec = vec![];
-        for idx in 0..(width*height) {
-            let color = palette_array[data_vec[idx as usize] as usize];
-            rgb_image_vec.append(&mut color.to_vec());
-        }
-        web_sys::console::debug_2(&"PISS ME".into(), &palette_array.len().into());
-        let image = image::RgbImage::from_vec(width, height, rgb_image_vec).unwrap();
         Self { 
             header,
             data: data_vec,
             palette: palette_array,
-            image, 
         }
     }
 
@@ -379,16 +566,17 @@ impl Texture {
     }
 
     fn to_vec(&mut self) -> Vec<u8> {
+        self.to_miptex()
+    }
+
+    /// Header, mips, palette and padding, the same bytes whether it goes in a WAD or a BSP
+    pub fn to_miptex(&self) -> Vec<u8> {
         let mut ret_vec = Vec::<u8>::new(); 
         ret_vec.append(&mut self.header.to_vec());
-        let mut data_clone = self.data.clone();
-        ret_vec.append(&mut data_clone);
-        let mut piss = 0;
+        ret_vec.extend(self.data.iter());
         for color in self.palette {
             ret_vec.append(&mut color.to_vec());
-            piss += 1;
         }
-        web_sys::console::debug_3(&piss.into(), &self.header.n_width.into(), &self.header.n_height.into());
         ret_vec.push(0x00);
         ret_vec.push(0x00);
         ret_vec
@@ -399,10 +587,74 @@ impl Texture {
     }
 }
 
+/// A directory entry and its texture, which is only decoded the first time it is asked for
+/// when the entry was read lazily
 #[derive(Debug, Clone)]
 pub struct EntryPair {
     pub dir_entry: DirectoryEntry,
-    pub texture: Texture,
+    texture: OnceCell<Texture>,
+    source: Option<Arc<Vec<u8>>>,
+}
+
+impl EntryPair {
+    pub fn new(dir_entry: DirectoryEntry, texture: Texture) -> Self {
+        Self {
+            dir_entry,
+            texture: OnceCell::from(texture),
+            source: None,
+        }
+    }
+
+    fn lazy(dir_entry: DirectoryEntry, source: Arc<Vec<u8>>) -> Self {
+        Self {
+            dir_entry,
+            texture: OnceCell::new(),
+            source: Some(source),
+        }
+    }
+
+    /// Lazy entries whose lump doesn't hold a whole texture come back as `Texture::placeholder`
+    pub fn texture(&self) -> &Texture {
+        self.texture.get_or_init(|| {
+            self.decode().unwrap_or_else(|| Texture::placeholder(self.dir_entry.sz_name))
+        })
+    }
+
+    /// Decodes the lump from the source with bounds checks, `None` when it isn't a texture
+    fn decode(&self) -> Option<Texture> {
+        let source = self.source.as_ref()?;
+        let start = self.dir_entry.n_file_pos as usize;
+        let lump = source.get(start..start.checked_add(self.dir_entry.n_disk_size as usize)?)?;
+        Texture::from_miptex(lump)
+    }
+
+    pub fn texture_mut(&mut self) -> &mut Texture {
+        self.texture();
+        self.texture.get_mut().unwrap()
+    }
+
+    pub fn set_texture(&mut self, texture: Texture) {
+        self.texture = OnceCell::from(texture);
+        self.source = None;
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        self.texture.get().is_some()
+    }
+
+    /// Decodes the texture if it isn't yet and lets go of the source, the whole file stays in
+    /// memory until every entry has done this
+    pub fn detach(&mut self) {
+        self.texture();
+        self.source = None;
+    }
+
+    /// Throws the decoded texture away if it can be decoded again from the source
+    pub fn unload(&mut self) {
+        if self.source.is_some() {
+            self.texture = OnceCell::new();
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -412,16 +664,9 @@ pub struct WadFile {
 }
 
 impl WadFile {
+    /// Compares the raw magic, anything can be dropped on the app and most of it isn't text
     pub fn validate_header(buf: &Vec::<u8>) -> bool {
-        if buf.len() < WAD_HEADER_SIZE {
-            return false
-        }
-        let header = WadHeader::from_bytes(buf[0..WAD_HEADER_SIZE].try_into().unwrap());
-        let sz_magic_str =  std::str::from_utf8(&header.sz_magic).unwrap();
-        if sz_magic_str != "WAD3" {
-            return false
-        }
-        true
+        buf.len() >= WAD_HEADER_SIZE && &buf[0..4] == b"WAD3"
     }
 
     pub fn from_path(path: &Path) -> Self {
@@ -436,10 +681,7 @@ impl WadFile {
         let header = WadHeader::from_bytes(&header_buf);
         let mut entries: Vec<EntryPair> = Vec::<EntryPair>::new();
         for itr in 0..(header.n_dir as usize) {
-            let entry_offset = (header.n_dir_offset as usize) + (itr * DIRECTORY_ENTRY_SIZE);
-            let dir_entry = DirectoryEntry::from_bytes(&wad_data[entry_offset..(entry_offset + DIRECTORY_ENTRY_SIZE)].try_into().expect("fucked"));
-            let texture = Texture::from_directory_entry(dir_entry, &wad_data);
-            entries.push(EntryPair { dir_entry, texture });
+            entries.push(Self::entry_from_bytes(header, itr, wad_data));
         }
         Self {
             header,
@@ -447,6 +689,75 @@ impl WadFile {
         }
     }
 
+    fn entry_from_bytes(header: WadHeader, itr: usize, wad_data: &Vec<u8>) -> EntryPair {
+        let entry_offset = (header.n_dir_offset as usize) + (itr * DIRECTORY_ENTRY_SIZE);
+        let dir_entry = DirectoryEntry::from_bytes(&wad_data[entry_offset..(entry_offset + DIRECTORY_ENTRY_SIZE)].try_into().expect("fucked"));
+        let texture = Texture::from_directory_entry(dir_entry, wad_data);
+        EntryPair::new(dir_entry, texture)
+    }
+
+    /// Only reads the directory, textures are decoded from `wad_data` the first time they are
+    /// accessed through `EntryPair::texture`
+    pub fn from_bytes_lazy(wad_data: Vec<u8>) -> Result<Self, WadError> {
+        let reader = WadReader::new(&wad_data)?;
+        let header = reader.header();
+        let dir_entries: Vec<DirectoryEntry> = reader.entries().collect();
+        let source = Arc::new(wad_data);
+        let entries = dir_entries.into_iter()
+            .map(|dir_entry| EntryPair::lazy(dir_entry, source.clone()))
+            .collect();
+        Ok(Self {
+            header,
+            entries,
+        })
+    }
+
+    /// Builds a single texture WAD out of an image, the texture is named after `name`
+    pub fn from_image(image: image::RgbImage, name: &str) -> Self {
+        let mut texture = Texture::from_image(image);
+        let mut sz_name = [0; DIRECTORY_ENTRY_NAME_SIZE];
+        for (itr, byte) in name.bytes().take(DIRECTORY_ENTRY_NAME_SIZE - 1).enumerate() {
+            sz_name[itr] = byte;
+        }
+        texture.header.sz_name = sz_name;
+        Self::from_textures(vec![texture])
+    }
+
+    /// A WAD holding `textures`, each entry is named after its texture
+    pub fn from_textures(textures: Vec<Texture>) -> Self {
+        let entries = textures.into_iter()
+            .map(|texture| {
+                let dir_entry = DirectoryEntry {
+                    n_file_pos: 0,
+                    n_disk_size: 0,
+                    n_size: 0,
+                    n_type: 0x43,
+                    b_compression: 0,
+                    padding: 0,
+                    sz_name: texture.header.sz_name,
+                };
+                EntryPair::new(dir_entry, texture)
+            })
+            .collect();
+        let mut wad_file = Self {
+            header: WadHeader {
+                sz_magic: *b"WAD3",
+                n_dir: 0,
+                n_dir_offset: 0,
+            },
+            entries,
+        };
+        wad_file.regenerate();
+        wad_file
+    }
+
+    /// Finds a texture by name the way the engine does, ignoring case
+    pub fn find_texture(&self, name: &str) -> Option<&Texture> {
+        self.entries.iter()
+            .find(|entry| entry.dir_entry.name_bytes().eq_ignore_ascii_case(name.as_bytes()))
+            .map(|entry| entry.texture())
+    }
+
     fn gen_header(&self, dir_offset: u32) -> WadHeader {
         WadHeader {
             sz_magic: "WAD3".as_bytes().try_into().unwrap(),
@@ -458,7 +769,7 @@ impl WadFile {
     pub fn regenerate(&mut self) {
         let mut offset_count = WAD_HEADER_SIZE as u32;
         for entry in self.entries.iter_mut() {
-            let size = entry.texture.calculated_size();
+            let size = entry.texture().calculated_size();
             entry.dir_entry = DirectoryEntry {
                 n_file_pos: offset_count,
                 n_disk_size: size,
@@ -477,7 +788,7 @@ impl WadFile {
         let mut ret_vec = Vec::<u8>::new();
         ret_vec.append(&mut self.header.to_vec());
         for entry in &mut self.entries {
-            ret_vec.append(&mut entry.texture.to_vec());
+            ret_vec.append(&mut entry.texture_mut().to_vec());
         }
         for entry in &mut self.entries {
             ret_vec.append(&mut entry.dir_entry.to_vec());
@@ -486,6 +797,56 @@ impl WadFile {
     }
 }
 
+/// Decodes a WAD one texture at a time so the work can be spread over frames or moved to a thread
+pub struct WadParser {
+    wad_file: WadFile,
+    next: usize,
+}
+
+impl WadParser {
+    /// Checks the directory with `WadReader` first, so a broken file is an error and not a panic
+    pub fn new(wad_data: Vec<u8>) -> Result<Self, WadError> {
+        Ok(Self {
+            wad_file: WadFile::from_bytes_lazy(wad_data)?,
+            next: 0,
+        })
+    }
+
+    /// Decodes the next texture, returns false once there is nothing left to do
+    pub fn step(&mut self) -> Result<bool, WadError> {
+        let index = self.next;
+        let entry = match self.wad_file.entries.get_mut(index) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        // decoded here and not through `texture`, a broken lump is an error rather than a placeholder
+        match entry.decode() {
+            Some(texture) => entry.texture = OnceCell::from(texture),
+            None => return Err(WadError::BadTexture { index }),
+        }
+        self.next += 1;
+        Ok(true)
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.wad_file.entries.is_empty() {
+            return 1.0
+        }
+        self.next as f32 / self.wad_file.entries.len() as f32
+    }
+
+    /// Everything is decoded by now, so the file buffer is dropped
+    pub fn finish(mut self) -> WadFile {
+        for entry in self.wad_file.entries.iter_mut() {
+            entry.detach();
+        }
+        self.wad_file
+    }
+}
+
+/// How many thumbnails get uploaded to egui per frame
+const TEXTURES_PER_FRAME: usize = 32;
+
 pub struct WadFileWidget {
     pub wad_file: WadFile,
     pub wad_image: Option<egui::TextureHandle>,
@@ -497,6 +858,8 @@ pub struct WadFileWidget {
     pub id: usize,
     pub visible: bool,
     file_dialog: FileDialog,
+    /// Made from an image, so there's no WAD on disk for maps to name
+    from_image: bool,
 }
 
 impl WadFileWidget {
@@ -504,7 +867,10 @@ impl WadFileWidget {
         Self::from_bytes_with_name(buf, id, String::from("myfile.wad"))
     }
     pub fn from_bytes_with_name(buf: &Vec<u8>, id: usize, name: String) -> Self {
-        let wad_file = WadFile::from_bytes(buf);
+        Self::from_wad_file(WadFile::from_bytes(buf), id, name)
+    }
+
+    pub fn from_wad_file(wad_file: WadFile, id: usize, name: String) -> Self {
         let wad_image = None;
         let textures = vec![];
         let texture_index = 0;
@@ -520,11 +886,25 @@ impl WadFileWidget {
             name,
             id,
             visible: true,
-            file_dialog: Default::default(),
+            file_dialog: FileDialog::default().accept(&["bmp"]),
+            from_image: false,
         }
     }
 }
 
+impl WadFileWidget {
+    /// Opens any image format the `image` crate understands as a new single texture WAD
+    pub fn from_image_bytes_with_name(buf: &Vec<u8>, id: usize, name: String) -> Option<Self> {
+        let image = image::load_from_memory(&buf[..]).ok()?.to_rgb8();
+        let texture_name = Path::new(&name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| name.clone());
+        let wad_file = WadFile::from_image(image, &texture_name);
+        Some(Self { from_image: true, ..Self::from_wad_file(wad_file, id, name) })
+    }
+}
+
 impl super::HlFileWidget for WadFileWidget {
     fn show(&mut self, ctx: &egui::Context) {
         let mut vis = self.visible;
@@ -547,6 +927,13 @@ impl super::HlFileWidget for WadFileWidget {
     fn get_visibility(&mut self) -> bool {
         self.visible 
     }
+
+    fn wad_file(&self) -> Option<&WadFile> {
+        if self.from_image {
+            return None
+        }
+        Some(&self.wad_file)
+    }
 }
 
 impl super::View for WadFileWidget {
@@ -559,13 +946,13 @@ impl super::View for WadFileWidget {
                         //ui.set_width(512.);
                         ui.menu_image_button(image.into(), image.size_vec2(), |ui| {
                             if ui.button("Download").clicked() {
-                                let texture = &self.wad_file.entries[self.texture_index].texture;
+                                let texture = self.wad_file.entries[self.texture_index].texture();
                                 let texture_header = texture.header;
                                 let width = texture_header.n_width;
                                 let height = texture_header.n_height;
                                 let mut raw_image = Cursor::new(Vec::new());
                                 let mut image_writer = BufWriter::new(raw_image);
-                                texture.image.write_to(&mut image_writer, image::ImageFormat::Bmp);
+                                texture.to_image().write_to(&mut image_writer, image::ImageFormat::Bmp);
                                 self.file_dialog.save("cummy.bmp", image_writer.into_inner().unwrap().into_inner());
                                 ui.close_menu();
                             } 
@@ -577,17 +964,18 @@ impl super::View for WadFileWidget {
                                 ui.close_menu();
                             } 
                         });
-                        if let Some((name, file)) = self.file_dialog.get() {
-                            let file_name = self.wad_file.entries[self.texture_index].texture.header.sz_name;
+                        if let Some((name, file)) = self.file_dialog.get().and_then(|files| files.into_iter().next()) {
+                            let file_name = self.wad_file.entries[self.texture_index].texture().header.sz_name;
                             let image: image::RgbImage = image::load_from_memory_with_format(&file[..], image::ImageFormat::Bmp).unwrap().to_rgb8();
-                            self.wad_file.entries[self.texture_index].texture = Texture::from_image(image);
-                            self.wad_file.entries[self.texture_index].texture.header.sz_name = file_name;
+                            let mut texture = Texture::from_image(image);
+                            texture.header.sz_name = file_name;
+                            self.wad_file.entries[self.texture_index].set_texture(texture);
                             self.wad_file.regenerate();
                             self.update_texture = true;
                             self.init_textures = true;
                         }
                         ui.vertical(|ui| {
-                            let texture = &mut self.wad_file.entries[self.texture_index].texture;
+                            let texture = self.wad_file.entries[self.texture_index].texture_mut();
                             let name_bytes = texture.header.sz_name;
                             let mut file_name = std::str::from_utf8(&name_bytes).unwrap().to_string();
                             let response =  ui.text_edit_singleline(&mut file_name);
@@ -632,7 +1020,7 @@ impl super::View for WadFileWidget {
                     ui.horizontal(|ui| {
                         while cur_width < max_width {
                             let texture = &self.textures[texture_itr];
-                            let width = self.wad_file.entries[texture_itr].texture.header.n_width;
+                            let width = texture.size()[0] as u32;
                             let response = ui.add(egui::ImageButton::new(texture, texture.size_vec2()));
                             if response.clicked() {
                                 self.texture_index = texture_itr;
@@ -651,8 +1039,17 @@ impl super::View for WadFileWidget {
 
         if self.init_textures {
             self.textures.clear();
-            for entry in self.wad_file.entries.iter() {
-                let texture = &entry.texture;
+            self.init_textures = false;
+        }
+
+        // decoding is lazy, so only a handful of thumbnails are made each frame to keep huge
+        // WADs responsive, and lazy entries drop the texture again once egui has the thumbnail
+        if self.textures.len() < self.wad_file.entries.len() {
+            let start = self.textures.len();
+            let end = (start + TEXTURES_PER_FRAME).min(self.wad_file.entries.len());
+            for entry in self.wad_file.entries[start..end].iter_mut() {
+                let was_decoded = entry.is_decoded();
+                let texture = entry.texture();
                 let egui_image = ui.ctx().load_texture(
                     "my-image", 
                     egui::ColorImage::from_rgb(
@@ -660,11 +1057,14 @@ impl super::View for WadFileWidget {
                         &texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0)),
                     Default::default());
                 self.textures.push(egui_image);
+                if !was_decoded {
+                    entry.unload();
+                }
             }
-            self.init_textures = false;
+            ui.ctx().request_repaint();
         }
 
-        if self.update_texture {
+        if self.update_texture && self.texture_index < self.textures.len() {
             self.wad_image = Some(self.textures[self.texture_index].to_owned());
             self.update_texture = false;
         }
@@ -673,6 +1073,35 @@ impl super::View for WadFileWidget {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    /// One 16x16 texture called TEST
+    fn test_wad() -> Vec<u8> {
+        let mut name = [0u8; 16];
+        name[..4].copy_from_slice(b"TEST");
+        let mut lump = name.to_vec();
+        lump.extend(16u32.to_le_bytes());
+        lump.extend(16u32.to_le_bytes());
+        for mip_offset in [40u32, 296, 360, 376] {
+            lump.extend(mip_offset.to_le_bytes());
+        }
+        lump.extend((0..340).map(|itr| (itr % 256) as u8));
+        lump.extend(256u16.to_le_bytes());
+        lump.extend((0..768).map(|itr| (itr / 3) as u8));
+        lump.extend([0, 0]);
+
+        let mut wad = b"WAD3".to_vec();
+        wad.extend(1u32.to_le_bytes());
+        wad.extend((WAD_HEADER_SIZE as u32 + lump.len() as u32).to_le_bytes());
+        wad.extend(&lump);
+        wad.extend((WAD_HEADER_SIZE as u32).to_le_bytes());
+        wad.extend((lump.len() as u32).to_le_bytes());
+        wad.extend((lump.len() as u32).to_le_bytes());
+        wad.extend([0x43, 0, 0, 0]);
+        wad.extend(name);
+        wad
+    }
+
     #[test]
     fn it_works() {
         println!("ass");
@@ -680,4 +1109,92 @@ mod tests {
     #[test]
     fn read_header() {
     }
+
+    #[test]
+    fn header_check_takes_any_bytes() {
+        assert!(WadFile::validate_header(&test_wad()));
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        assert!(!WadFile::validate_header(&png));
+        assert!(!WadFile::validate_header(&vec![0xFF, 0xD8, 0xFF, 0xE0]));
+    }
+
+    #[test]
+    fn reader_borrows_lumps() {
+        let wad_data = test_wad();
+        let reader = WadReader::new(&wad_data).unwrap();
+        assert_eq!(reader.entries().len(), 1);
+        let (entry, lump) = reader.find("test").unwrap();
+        assert_eq!(entry.name_bytes(), b"TEST");
+        assert_eq!(lump.len(), 1152);
+        assert_eq!(lump.as_ptr(), wad_data[WAD_HEADER_SIZE..].as_ptr());
+        assert!(reader.find("missing").is_none());
+    }
+
+    #[test]
+    fn reader_rejects_truncated() {
+        let wad_data = test_wad();
+        assert_eq!(WadReader::new(&wad_data[..8]).err(), Some(WadError::TooShort { len: 8 }));
+        let truncated = &wad_data[..wad_data.len() - 1];
+        assert!(matches!(WadReader::new(truncated), Err(WadError::DirectoryOutOfBounds { .. })));
+        let mut bad_lump = wad_data.clone();
+        let size_offset = wad_data.len() - DIRECTORY_ENTRY_SIZE + 4;
+        bad_lump[size_offset..(size_offset + 4)].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(WadReader::new(&bad_lump), Err(WadError::LumpOutOfBounds { index: 0, .. })));
+
+        let mut too_wide = wad_data.clone();
+        too_wide[(WAD_HEADER_SIZE + 16)..(WAD_HEADER_SIZE + 20)].copy_from_slice(&64u32.to_le_bytes());
+        let mut parser = WadParser::new(too_wide).unwrap();
+        assert_eq!(parser.step().err(), Some(WadError::BadTexture { index: 0 }));
+        assert!(WadParser::new(wad_data[..8].to_vec()).is_err());
+    }
+
+    #[test]
+    fn lazy_matches_eager() {
+        let wad_data = test_wad();
+        let eager = WadFile::from_bytes(&wad_data);
+        let lazy = WadFile::from_bytes_lazy(wad_data).unwrap();
+        assert_eq!(lazy.entries.len(), 1);
+        assert!(!lazy.entries[0].is_decoded());
+        assert_eq!(lazy.entries[0].dir_entry.name_str(), Some(String::from("TEST")));
+        assert_eq!(
+            lazy.entries[0].texture().to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0),
+            eager.entries[0].texture().to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0));
+        assert!(lazy.entries[0].is_decoded());
+    }
+
+    #[test]
+    fn lazy_wads_check_the_directory_and_stand_in_for_broken_lumps() {
+        let wad_data = test_wad();
+        assert!(matches!(WadFile::from_bytes_lazy(wad_data[..8].to_vec()), Err(WadError::TooShort { len: 8 })));
+
+        let mut truncated = wad_data.clone();
+        let pos_offset = wad_data.len() - DIRECTORY_ENTRY_SIZE;
+        truncated[pos_offset..(pos_offset + 4)].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(WadFile::from_bytes_lazy(truncated), Err(WadError::LumpOutOfBounds { index: 0, .. })));
+
+        // a height that runs the mips past the palette
+        let mut broken = wad_data;
+        broken[(WAD_HEADER_SIZE + 20)..(WAD_HEADER_SIZE + 24)].copy_from_slice(&u32::MAX.to_le_bytes());
+        let lazy = WadFile::from_bytes_lazy(broken).unwrap();
+        let texture = lazy.entries[0].texture();
+        assert_eq!((texture.header.n_width, texture.header.n_height), (16, 16));
+        assert_eq!(texture.header.sz_name, lazy.entries[0].dir_entry.sz_name);
+        assert_eq!(&texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0)[..3], &[0, 0, 0]);
+        assert_eq!(&texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0)[24..27], &[255, 0, 255]);
+        assert_eq!(texture.to_rgb_image_vec(MIPMAP_LEVEL::LEVEL3).len(), 2 * 2 * 3);
+    }
+
+    #[test]
+    fn decoded_entries_let_go_of_the_file() {
+        let mut parser = WadParser::new(test_wad()).unwrap();
+        while parser.step().unwrap() {}
+        let wad_file = parser.finish();
+        assert!(wad_file.entries.iter().all(|entry| entry.is_decoded() && entry.source.is_none()));
+
+        let mut lazy = WadFile::from_bytes_lazy(test_wad()).unwrap();
+        let before = lazy.entries[0].texture().to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0);
+        lazy.entries[0].unload();
+        assert!(!lazy.entries[0].is_decoded());
+        assert_eq!(lazy.entries[0].texture().to_rgb_image_vec(MIPMAP_LEVEL::LEVEL0), before);
+    }
 }
@@ -58,3 +58,59 @@ impl super::View for DevInfoWindow {
         });
     }
 }
+
+/// Tells the user why a file could not be opened
+pub struct ErrorWindow {
+    id: usize,
+    visible: bool,
+    title: String,
+    message: String,
+}
+
+impl ErrorWindow {
+    pub fn new(id: usize, title: String, message: String) -> Self {
+        Self {
+            id,
+            visible: true,
+            title,
+            message,
+        }
+    }
+}
+
+impl super::HlFileWidget for ErrorWindow {
+    fn show(&mut self, ctx: &egui::Context) {
+        let mut vis = self.visible;
+        use super::View as _;
+        egui::Window::new(self.title.as_str())
+            .open(&mut vis)
+            .collapsible(false)
+            .id(egui::Id::new(self.id))
+            .show(ctx, |ui| self.ui(ui));
+        // the Close button clears `visible` inside `ui`, the title bar's X clears `vis`
+        self.visible &= vis;
+    }
+
+    fn set_visibility(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    fn get_visibility(&mut self) -> bool {
+        self.visible 
+    }
+
+    fn get_name(&self) -> String {
+        format!("⚠ {}", self.title)
+    }
+}
+
+impl super::View for ErrorWindow {
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            ui.colored_label(ui.visuals().error_fg_color, self.message.as_str());
+            if ui.button("Close").clicked() {
+                self.visible = false;
+            }
+        });
+    }
+}
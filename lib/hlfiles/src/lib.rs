@@ -1,7 +1,11 @@
+pub mod archive;
 pub mod file_dialog;
+pub mod hlbsp;
 pub mod hlwad;
 pub mod hlmdl;
 pub mod info;
+pub mod loader;
+pub mod math;
 
 #[macro_use]
 extern crate bmp;
@@ -18,6 +22,20 @@ pub trait HlFileWidget {
     fn get_name(&self) -> String;
     fn set_visibility(&mut self, visible: bool);
     fn get_visibility(&mut self) -> bool;
+    /// Offers a file that may belong to this one, like a model's texture file, returns true if
+    /// it was taken
+    fn add_companion_file(&mut self, _name: &str, _file: &[u8]) -> bool {
+        false
+    }
+    /// The WAD this widget shows, so others can look textures up in it
+    fn wad_file(&self) -> Option<&hlwad::WadFile> {
+        None
+    }
+    /// True when the widget wants `receive_wads` called with every open WAD
+    fn wants_wads(&self) -> bool {
+        false
+    }
+    fn receive_wads(&mut self, _wads: &[(String, &hlwad::WadFile)]) {}
 }
 
 pub trait GuiImage {
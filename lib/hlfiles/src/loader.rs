@@ -0,0 +1,252 @@
+use std::fmt;
+use crate::hlbsp::{BspError, BspFile, BspParser};
+use crate::hlwad::{WadError, WadFile, WadParser};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc};
+
+/// How long a frame may spend parsing on the web before handing control back to the browser
+#[cfg(target_arch = "wasm32")]
+const FRAME_BUDGET_MS: f64 = 8.0;
+
+/// Whatever a finished `LoadTask` produced
+pub enum Loaded {
+    Wad(WadFile),
+    Bsp(Box<BspFile>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    Wad(WadError),
+    Bsp(BspError),
+    /// The parser thread went away without saying why, it panicked
+    Crashed,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Wad(err) => write!(f, "{}", err),
+            LoadError::Bsp(err) => write!(f, "{}", err),
+            LoadError::Crashed => write!(f, "the parser crashed"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<WadError> for LoadError {
+    fn from(err: WadError) -> Self {
+        LoadError::Wad(err)
+    }
+}
+
+impl From<BspError> for LoadError {
+    fn from(err: BspError) -> Self {
+        LoadError::Bsp(err)
+    }
+}
+
+/// Something that can be parsed a little bit at a time
+trait Parse: Send {
+    /// Does one unit of work, returns false when done
+    fn step(&mut self) -> Result<bool, LoadError>;
+    fn progress(&self) -> f32;
+    fn finish(self: Box<Self>) -> Result<Loaded, LoadError>;
+}
+
+impl Parse for WadParser {
+    fn step(&mut self) -> Result<bool, LoadError> {
+        Ok(WadParser::step(self)?)
+    }
+
+    fn progress(&self) -> f32 {
+        WadParser::progress(self)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Loaded, LoadError> {
+        Ok(Loaded::Wad(WadParser::finish(*self)))
+    }
+}
+
+impl Parse for BspParser {
+    fn step(&mut self) -> Result<bool, LoadError> {
+        Ok(BspParser::step(self)?)
+    }
+
+    fn progress(&self) -> f32 {
+        BspParser::progress(self)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Loaded, LoadError> {
+        Ok(Loaded::Bsp(Box::new(BspParser::finish(*self)?)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+enum LoadMessage {
+    Progress(f32),
+    Done(Result<Loaded, LoadError>),
+}
+
+enum LoadState {
+    /// Native: the parser runs on its own thread and reports back
+    #[cfg(not(target_arch = "wasm32"))]
+    Thread {
+        rx: mpsc::Receiver<LoadMessage>,
+        cancel: Arc<AtomicBool>,
+    },
+    /// Web: the parser gets a slice of every frame. A web worker doesn't share memory with the
+    /// page, so the parsed file would have to be serialized to come back, costing about as much
+    /// as parsing it. Sharing memory takes wasm threads, which need a nightly `build-std` build
+    /// and COOP/COEP headers from whatever serves the page. A single step can't be cut short,
+    /// so parsers keep them to about one texture or one lump
+    #[cfg(target_arch = "wasm32")]
+    Sliced(Box<dyn Parse>),
+    /// Failed before there was anything to parse
+    Failed(LoadError),
+    Finished,
+}
+
+/// A file being parsed in the background
+pub struct LoadTask {
+    pub name: String,
+    progress: f32,
+    state: LoadState,
+}
+
+impl LoadTask {
+    pub fn wad(name: String, wad_data: Vec<u8>) -> Self {
+        match WadParser::new(wad_data) {
+            Ok(parser) => Self::new(name, Box::new(parser)),
+            Err(err) => Self::failed(name, err.into()),
+        }
+    }
+
+    pub fn bsp(name: String, bsp_data: Vec<u8>) -> Self {
+        Self::new(name, Box::new(BspParser::new(bsp_data)))
+    }
+
+    fn failed(name: String, err: LoadError) -> Self {
+        Self {
+            name,
+            progress: 0.0,
+            state: LoadState::Failed(err),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new(name: String, mut parser: Box<dyn Parse>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_clone = cancel.clone();
+        std::thread::spawn(move || {
+            loop {
+                match parser.step() {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(err) => {
+                        tx.send(LoadMessage::Done(Err(err))).ok();
+                        return;
+                    },
+                }
+                if cancel_clone.load(Ordering::Relaxed) {
+                    return;
+                }
+                if tx.send(LoadMessage::Progress(parser.progress())).is_err() {
+                    return;
+                }
+            }
+            tx.send(LoadMessage::Done(parser.finish())).ok();
+        });
+        Self {
+            name,
+            progress: 0.0,
+            state: LoadState::Thread { rx, cancel },
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new(name: String, parser: Box<dyn Parse>) -> Self {
+        Self {
+            name,
+            progress: 0.0,
+            state: LoadState::Sliced(parser),
+        }
+    }
+
+    /// Between 0 and 1
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, LoadState::Finished)
+    }
+
+    /// Stops parsing, the task will never produce anything
+    pub fn cancel(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let LoadState::Thread { cancel, .. } = &self.state {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        self.state = LoadState::Finished;
+    }
+
+    /// Call every frame, returns the parsed file or why it couldn't be parsed once it is done
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn poll(&mut self) -> Option<Result<Loaded, LoadError>> {
+        let loaded = match &self.state {
+            LoadState::Thread { rx, .. } => loop {
+                match rx.try_recv() {
+                    Ok(LoadMessage::Progress(progress)) => self.progress = progress,
+                    Ok(LoadMessage::Done(done)) => break done,
+                    Err(mpsc::TryRecvError::Empty) => return None,
+                    // the thread died without finishing
+                    Err(mpsc::TryRecvError::Disconnected) => break Err(LoadError::Crashed),
+                }
+            },
+            LoadState::Failed(err) => Err(err.clone()),
+            LoadState::Finished => return None,
+        };
+        self.progress = 1.0;
+        self.state = LoadState::Finished;
+        Some(loaded)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn poll(&mut self) -> Option<Result<Loaded, LoadError>> {
+        let parser = match &mut self.state {
+            LoadState::Sliced(parser) => parser,
+            LoadState::Failed(err) => {
+                let err = err.clone();
+                self.state = LoadState::Finished;
+                return Some(Err(err))
+            },
+            LoadState::Finished => return None,
+        };
+        let start = js_sys::Date::now();
+        let mut done = false;
+        while js_sys::Date::now() - start < FRAME_BUDGET_MS {
+            match parser.step() {
+                Ok(true) => (),
+                Ok(false) => {
+                    done = true;
+                    break;
+                },
+                Err(err) => {
+                    self.state = LoadState::Finished;
+                    return Some(Err(err))
+                },
+            }
+        }
+        self.progress = parser.progress();
+        if !done {
+            return None
+        }
+        match std::mem::replace(&mut self.state, LoadState::Finished) {
+            LoadState::Sliced(parser) => Some(parser.finish()),
+            _ => None,
+        }
+    }
+}
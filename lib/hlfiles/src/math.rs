@@ -0,0 +1,198 @@
+//! The little bit of mathlib.c the model and map code needs
+
+pub type Vec3 = [f32; 3];
+/// x, y, z, w
+pub type Quat = [f32; 4];
+/// Rotation in the first three columns, translation in the last
+pub type Mat3x4 = [[f32; 4]; 3];
+
+pub const IDENTITY: Mat3x4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+];
+
+pub fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: Vec3, scale: f32) -> Vec3 {
+    [a[0] * scale, a[1] * scale, a[2] * scale]
+}
+
+pub fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: Vec3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+pub fn normalize(a: Vec3) -> Vec3 {
+    let len = length(a);
+    if len == 0.0 {
+        return a
+    }
+    scale(a, 1.0 / len)
+}
+
+pub fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+/// Euler angles in radians (roll around x, pitch around y, yaw around z) to a quaternion
+pub fn angle_quaternion(angles: Vec3) -> Quat {
+    let (sr, cr) = (angles[0] * 0.5).sin_cos();
+    let (sp, cp) = (angles[1] * 0.5).sin_cos();
+    let (sy, cy) = (angles[2] * 0.5).sin_cos();
+    [
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+        cr * cp * cy + sr * sp * sy,
+    ]
+}
+
+/// Back to euler angles in radians, the inverse of `angle_quaternion`
+pub fn quaternion_angles(q: Quat) -> Vec3 {
+    let matrix = quaternion_matrix(q);
+    let sp = -matrix[2][0];
+    if sp.abs() < 0.99999 {
+        [
+            matrix[2][1].atan2(matrix[2][2]),
+            sp.asin(),
+            matrix[1][0].atan2(matrix[0][0]),
+        ]
+    } else {
+        // gimbal lock, fold roll into yaw
+        [
+            0.0,
+            sp.signum() * std::f32::consts::FRAC_PI_2,
+            (-matrix[0][1]).atan2(matrix[1][1]),
+        ]
+    }
+}
+
+pub fn quaternion_slerp(p: Quat, q: Quat, t: f32) -> Quat {
+    // take the short way around
+    let mut q = q;
+    let a: f32 = (0..4).map(|itr| (p[itr] - q[itr]) * (p[itr] - q[itr])).sum();
+    let b: f32 = (0..4).map(|itr| (p[itr] + q[itr]) * (p[itr] + q[itr])).sum();
+    if a > b {
+        q = [-q[0], -q[1], -q[2], -q[3]];
+    }
+
+    let cosom = p[0] * q[0] + p[1] * q[1] + p[2] * q[2] + p[3] * q[3];
+    let (sclp, sclq) = if 1.0 + cosom > 0.000001 {
+        if 1.0 - cosom > 0.000001 {
+            let omega = cosom.acos();
+            let sinom = omega.sin();
+            (((1.0 - t) * omega).sin() / sinom, (t * omega).sin() / sinom)
+        } else {
+            (1.0 - t, t)
+        }
+    } else {
+        let perpendicular = [-q[1], q[0], -q[3], q[2]];
+        let sclp = ((1.0 - t) * std::f32::consts::FRAC_PI_2).sin();
+        let sclq = (t * std::f32::consts::FRAC_PI_2).sin();
+        return [
+            sclp * p[0] + sclq * perpendicular[0],
+            sclp * p[1] + sclq * perpendicular[1],
+            sclp * p[2] + sclq * perpendicular[2],
+            perpendicular[3],
+        ]
+    };
+    [
+        sclp * p[0] + sclq * q[0],
+        sclp * p[1] + sclq * q[1],
+        sclp * p[2] + sclq * q[2],
+        sclp * p[3] + sclq * q[3],
+    ]
+}
+
+pub fn quaternion_matrix(q: Quat) -> Mat3x4 {
+    [
+        [
+            1.0 - 2.0 * q[1] * q[1] - 2.0 * q[2] * q[2],
+            2.0 * q[0] * q[1] - 2.0 * q[3] * q[2],
+            2.0 * q[0] * q[2] + 2.0 * q[3] * q[1],
+            0.0,
+        ],
+        [
+            2.0 * q[0] * q[1] + 2.0 * q[3] * q[2],
+            1.0 - 2.0 * q[0] * q[0] - 2.0 * q[2] * q[2],
+            2.0 * q[1] * q[2] - 2.0 * q[3] * q[0],
+            0.0,
+        ],
+        [
+            2.0 * q[0] * q[2] - 2.0 * q[3] * q[1],
+            2.0 * q[1] * q[2] + 2.0 * q[3] * q[0],
+            1.0 - 2.0 * q[0] * q[0] - 2.0 * q[1] * q[1],
+            0.0,
+        ],
+    ]
+}
+
+/// Rotation from `q` with `position` as the translation
+pub fn transform_matrix(q: Quat, position: Vec3) -> Mat3x4 {
+    let mut matrix = quaternion_matrix(q);
+    for itr in 0..3 {
+        matrix[itr][3] = position[itr];
+    }
+    matrix
+}
+
+/// `a * b`, applying `b` first
+pub fn concat_transforms(a: &Mat3x4, b: &Mat3x4) -> Mat3x4 {
+    let mut out = [[0.0; 4]; 3];
+    for row in 0..3 {
+        for column in 0..4 {
+            out[row][column] = a[row][0] * b[0][column] + a[row][1] * b[1][column] + a[row][2] * b[2][column];
+        }
+        out[row][3] += a[row][3];
+    }
+    out
+}
+
+pub fn transform_point(matrix: &Mat3x4, point: Vec3) -> Vec3 {
+    [
+        dot(point, [matrix[0][0], matrix[0][1], matrix[0][2]]) + matrix[0][3],
+        dot(point, [matrix[1][0], matrix[1][1], matrix[1][2]]) + matrix[1][3],
+        dot(point, [matrix[2][0], matrix[2][1], matrix[2][2]]) + matrix[2][3],
+    ]
+}
+
+/// Only the rotation part, for normals and directions
+pub fn rotate_vector(matrix: &Mat3x4, vector: Vec3) -> Vec3 {
+    [
+        dot(vector, [matrix[0][0], matrix[0][1], matrix[0][2]]),
+        dot(vector, [matrix[1][0], matrix[1][1], matrix[1][2]]),
+        dot(vector, [matrix[2][0], matrix[2][1], matrix[2][2]]),
+    ]
+}
+
+/// Inverse of a rotation + translation matrix
+pub fn invert_transform(matrix: &Mat3x4) -> Mat3x4 {
+    let mut out = [[0.0; 4]; 3];
+    for row in 0..3 {
+        for column in 0..3 {
+            out[row][column] = matrix[column][row];
+        }
+    }
+    for row in out.iter_mut() {
+        row[3] = -(row[0] * matrix[0][3] + row[1] * matrix[1][3] + row[2] * matrix[2][3]);
+    }
+    out
+}
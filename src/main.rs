@@ -4,16 +4,19 @@ use eframe::egui;
 
 extern crate hlfiles;
 
+use hlfiles::hlbsp;
 use hlfiles::hlmdl;
 use hlfiles::hlwad;
 use hlfiles::info;
 use hlfiles::file_dialog::FileDialog;
+use hlfiles::loader::{LoadTask, Loaded};
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(320.0, 240.0)),
+        drag_and_drop_support: true,
         ..Default::default()
     };
     eframe::run_native(
@@ -46,17 +49,24 @@ fn main() {
     })
 }
 
+/// WADs bigger than this are opened lazily instead of decoding every texture up front
+const LAZY_WAD_SIZE: usize = 64 * 1024 * 1024;
+
 struct MyApp {
     file_dialog: FileDialog,
     hl_file_widgets: Vec<Box<dyn hlfiles::HlFileWidget>>,
+    load_tasks: Vec<LoadTask>,
     id_incrementor: usize,
 }
 
 impl Default for MyApp {
     fn default() -> Self {
         Self {
-            file_dialog: Default::default(),
+            file_dialog: FileDialog::default()
+                .multiple(true)
+                .accept(&["wad", "bsp", "map", "mdl", "qc", "smd", "bmp", "png", "jpg", "jpeg", "tga"]),
             hl_file_widgets: vec![],
+            load_tasks: vec![],
             id_incrementor: 0,
         }
     }
@@ -67,6 +77,212 @@ impl MyApp {
         self.id_incrementor = self.id_incrementor.checked_add(1).expect("Wow, that's a fuck ton of windows");
         self.id_incrementor
     }
+
+    /// Opens a batch of files, companion files (a model's textures or sequence groups) go last
+    /// so the model they belong to is already open
+    fn open_files(&mut self, files: Vec<(String, Vec<u8>)>) {
+        // a qc comes with its smds and bitmaps, those turn into the model and anything the qcs
+        // don't use opens on its own
+        let is_qc = |name: &str| name.to_lowercase().ends_with(".qc");
+        let files = if files.iter().any(|(name, _)| is_qc(name)) {
+            let mut used: Vec<bool> = files.iter().map(|(name, _)| is_qc(name)).collect();
+            for (qc_name, qc) in files.iter().filter(|(name, _)| is_qc(name)) {
+                let id = self.id_incrementor();
+                let qc = String::from_utf8_lossy(qc);
+                let (mdl_file, qc_used) = hlmdl::MdlFile::compile_tracked(&qc, &files);
+                for (used, qc_used) in used.iter_mut().zip(qc_used) {
+                    *used |= qc_used;
+                }
+                let widget = mdl_file.and_then(|mdl_file| hlmdl::MdlFileWidget::from_bytes(&mdl_file.data, id));
+                match widget {
+                    Ok(widget) => self.hl_file_widgets.push(Box::new(widget)),
+                    Err(error) => self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                        id,
+                        format!("Could not compile {}", qc_name),
+                        error.to_string()))),
+                }
+            }
+            files.into_iter()
+                .zip(used)
+                .filter(|(_, used)| !used)
+                .map(|(file, _)| file)
+                .collect()
+        } else {
+            files
+        };
+        let (companions, files): (Vec<_>, Vec<_>) = files.into_iter()
+            .partition(|(_, file)| hlmdl::MdlFile::is_companion_file(file));
+        for (name, file) in files.into_iter().chain(companions) {
+            self.open_file(name, file);
+        }
+    }
+
+    /// Sniffs the file header and opens the file in the matching widget
+    fn open_file(&mut self, name: String, file: Vec<u8>) {
+        if hlmdl::MdlFile::is_companion_file(&file) {
+            if self.hl_file_widgets.iter_mut().any(|hl_file_widget| hl_file_widget.add_companion_file(&name, &file)) {
+                return;
+            }
+            if &file[0..4] == b"IDSQ" {
+                let id = self.id_incrementor();
+                self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                    id,
+                    format!("Could not open {}", name),
+                    String::from("it is a sequence group, open the model it belongs to first"))));
+                return;
+            }
+            // a texture file on its own still shows its textures
+        }
+        if hlwad::WadFile::validate_header(&file) {
+            if file.len() > LAZY_WAD_SIZE {
+                // only the directory is read, the widget decodes thumbnails as it goes
+                let id = self.id_incrementor();
+                match hlwad::WadFile::from_bytes_lazy(file) {
+                    Ok(wad_file) => self.hl_file_widgets.push(Box::new(hlwad::WadFileWidget::from_wad_file(wad_file, id, name))),
+                    Err(error) => self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                        id,
+                        format!("Could not open {}", name),
+                        error.to_string()))),
+                }
+            } else {
+                self.load_tasks.push(LoadTask::wad(name, file));
+            }
+            return;
+        }
+        if hlbsp::BspFile::validate_header(&file) {
+            self.load_tasks.push(LoadTask::bsp(name, file));
+            return;
+        }
+        if hlmdl::MdlFile::validate_header(&file) {
+            let id = self.id_incrementor();
+            match hlmdl::MdlFileWidget::from_bytes(&file, id) {
+                Ok(widget) => self.hl_file_widgets.push(Box::new(widget)),
+                Err(error) => self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                    id,
+                    format!("Could not open {}", name),
+                    error.to_string()))),
+            }
+            return;
+        }
+        if name.to_lowercase().ends_with(".map") {
+            let id = self.id_incrementor();
+            self.hl_file_widgets.push(Box::new(hlbsp::MapFileWidget::from_bytes(&file, id, name)));
+            return;
+        }
+        let id = self.id_incrementor();
+        match hlwad::WadFileWidget::from_image_bytes_with_name(&file, id, name.clone()) {
+            Some(widget) => self.hl_file_widgets.push(Box::new(widget)),
+            None => self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                id,
+                format!("Could not open {}", name),
+                String::from("it isn't a WAD, map, model or image this can read")))),
+        }
+    }
+
+    /// Gives every widget that asked for them the WADs open in the other widgets
+    fn hand_out_wads(&mut self) {
+        for itr in 0..self.hl_file_widgets.len() {
+            if !self.hl_file_widgets[itr].wants_wads() {
+                continue;
+            }
+            let (before, rest) = self.hl_file_widgets.split_at_mut(itr);
+            let (widget, after) = rest.split_first_mut().unwrap();
+            let wads: Vec<(String, &hlwad::WadFile)> = before.iter().chain(after.iter())
+                .filter_map(|other| other.wad_file().map(|wad_file| (other.get_name(), wad_file)))
+                .collect();
+            widget.receive_wads(&wads);
+        }
+    }
+
+    fn poll_load_tasks(&mut self, ctx: &egui::Context) {
+        let mut load_tasks = std::mem::take(&mut self.load_tasks);
+        for load_task in load_tasks.iter_mut() {
+            let loaded = match load_task.poll() {
+                Some(loaded) => loaded,
+                None => continue,
+            };
+            let id = self.id_incrementor();
+            let name = load_task.name.clone();
+            match loaded {
+                Ok(Loaded::Wad(wad_file)) => self.hl_file_widgets.push(Box::new(hlwad::WadFileWidget::from_wad_file(wad_file, id, name))),
+                Ok(Loaded::Bsp(bsp_file)) => self.hl_file_widgets.push(Box::new(hlbsp::BspFileWidget::from_bsp_file(*bsp_file, id, name))),
+                Err(error) => self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                    id,
+                    format!("Could not open {}", name),
+                    error.to_string()))),
+            }
+        }
+        load_tasks.retain(|load_task| !load_task.is_finished());
+        self.load_tasks = load_tasks;
+        if !self.load_tasks.is_empty() || self.file_dialog.progress().is_some() {
+            ctx.request_repaint();
+        }
+    }
+
+    fn show_load_tasks(&mut self, ui: &mut egui::Ui) {
+        if let Some(progress) = self.file_dialog.progress() {
+            ui.horizontal(|ui| {
+                ui.add(egui::ProgressBar::new(progress).text("Reading").desired_width(120.0));
+                if ui.small_button("Cancel").clicked() {
+                    self.file_dialog.cancel();
+                }
+            });
+        }
+        for load_task in self.load_tasks.iter_mut() {
+            ui.label(load_task.name.as_str());
+            ui.horizontal(|ui| {
+                ui.add(egui::ProgressBar::new(load_task.progress()).show_percentage().desired_width(120.0));
+                if ui.small_button("Cancel").clicked() {
+                    load_task.cancel();
+                }
+            });
+        }
+    }
+
+    fn open_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped_files = ctx.input(|i| i.raw.dropped_files.clone());
+        let mut files = vec![];
+        for dropped_file in dropped_files {
+            let name = match &dropped_file.path {
+                Some(path) if dropped_file.name.is_empty() => path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                _ => dropped_file.name.clone(),
+            };
+            // web gives us the bytes, native gives us a path
+            let file = match (&dropped_file.bytes, &dropped_file.path) {
+                (Some(bytes), _) => Ok(bytes.to_vec()),
+                (None, Some(path)) => std::fs::read(path).map_err(|err| err.to_string()),
+                (None, None) => Err(String::from("the drop didn't come with the file's contents")),
+            };
+            match file {
+                Ok(file) => files.push((name, file)),
+                Err(error) => {
+                    let id = self.id_incrementor();
+                    self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(id, format!("Could not read {}", name), error)));
+                },
+            }
+        }
+        self.open_files(files);
+    }
+
+    fn show_hovered_files(&self, ctx: &egui::Context) {
+        let hovered_files = ctx.input(|i| i.raw.hovered_files.len());
+        if hovered_files == 0 {
+            return;
+        }
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("file_drop_target")));
+        let screen_rect = ctx.screen_rect();
+        painter.rect_filled(screen_rect, 0.0, egui::Color32::from_black_alpha(192));
+        painter.text(
+            screen_rect.center(),
+            egui::Align2::CENTER_CENTER,
+            format!("Drop {} file(s) to open", hovered_files),
+            egui::TextStyle::Heading.resolve(&ctx.style()),
+            egui::Color32::WHITE,
+        );
+    }
 }
 
 impl eframe::App for MyApp {
@@ -74,6 +290,7 @@ impl eframe::App for MyApp {
         for hl_file_widget in self.hl_file_widgets.iter_mut() {
             hl_file_widget.show(ctx);
         }
+        self.hand_out_wads();
         egui::TopBottomPanel::top("top").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
@@ -88,19 +305,27 @@ impl eframe::App for MyApp {
                     }
                 });
             });
-            if let Some((name, file)) = self.file_dialog.get() {
-                if hlwad::WadFile::validate_header(&file) {
-                    let id = self.id_incrementor();
-                    self.hl_file_widgets.push(Box::new(hlwad::WadFileWidget::from_bytes_with_name(&file, id, name)));
-                }
-                //if hlmdl::MdlFile::validate_header(&file) {
-                    //let id = self.id_incrementor();
-                    //self.hl_file_widgets.push(Box::new(hlmdl::MdlFileWidget::from_bytes(&file, id)));
-                //}
+            if let Some(files) = self.file_dialog.get() {
+                self.open_files(files);
+            }
+            for (name, reason) in self.file_dialog.errors() {
+                let id = self.id_incrementor();
+                self.hl_file_widgets.push(Box::new(info::ErrorWindow::new(
+                    id,
+                    format!("Could not read {}", name),
+                    reason)));
             }
         });
+        self.open_dropped_files(ctx);
+        self.show_hovered_files(ctx);
+        self.poll_load_tasks(ctx);
         egui::SidePanel::left("file-list").show(ctx, |ui| {
             ui.vertical(|ui| {
+                if self.file_dialog.progress().is_some() || !self.load_tasks.is_empty() {
+                    ui.label("Loading");
+                    self.show_load_tasks(ui);
+                    ui.separator();
+                }
                 ui.label("Open Files");
                 for file_widget in self.hl_file_widgets.iter_mut() {
                     let mut fill = egui::Color32::LIGHT_GRAY;